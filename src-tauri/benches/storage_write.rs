@@ -0,0 +1,105 @@
+use app_lib::db::storage::Storage;
+use app_lib::db::types::{AppUsageRecord, UsageState};
+use chrono::{Duration, TimeZone, Utc};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use rusqlite::Connection;
+use std::path::PathBuf;
+
+const BATCH_SIZE: usize = 64;
+
+// 交替切换应用，每条记录都会开启新会话
+fn records(count: usize) -> Vec<AppUsageRecord> {
+    let start = Utc.with_ymd_and_hms(2026, 1, 1, 0, 0, 0).unwrap();
+    (0..count)
+        .map(|i| AppUsageRecord {
+            timestamp: start + Duration::seconds(i as i64),
+            app_name: if i % 2 == 0 { "editor" } else { "browser" }.to_string(),
+            duration: 1,
+            state: UsageState::Active,
+            window_title: Some(format!("file-{}.rs", i % 7)),
+            window_class: None,
+            exe_path: None,
+            pid: None,
+            utc_offset: Some(0),
+        })
+        .collect()
+}
+
+fn database_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("time-whisper-bench-{}-{}.db", name, std::process::id()));
+    for suffix in ["", "-wal", "-shm", "-journal"] {
+        let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+    }
+    path
+}
+
+fn open_storage(name: &str) -> Storage {
+    Storage::open(&database_path(name)).unwrap()
+}
+
+// 对照组：回滚日志模式，每条记录一条自动提交的 INSERT
+fn open_baseline(name: &str) -> Connection {
+    let path = database_path(name);
+    drop(Storage::open(&path).unwrap());
+    let conn = Connection::open(&path).unwrap();
+    let journal_mode: String = conn.pragma_update_and_check(None, "journal_mode", "DELETE", |row| row.get(0)).unwrap();
+    assert_eq!(journal_mode, "delete");
+    conn
+}
+
+// 之前每秒单独提交一条记录，现在由写入任务攒成一批在一个事务中提交
+fn storage_write(c: &mut Criterion) {
+    let mut group = c.benchmark_group("storage_write");
+    group.throughput(Throughput::Elements(BATCH_SIZE as u64));
+
+    let conn = open_baseline("baseline");
+    group.bench_function("autocommit_insert_delete_journal", |b| {
+        b.iter_batched(
+            || records(BATCH_SIZE),
+            |records| {
+                for record in records {
+                    conn.execute(
+                        "INSERT INTO app_sessions (app_name, state, start_time, end_time, window_title, utc_offset)
+                         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                        (
+                            &record.app_name,
+                            record.state.as_str(),
+                            record.timestamp.to_rfc3339(),
+                            (record.timestamp + Duration::seconds(record.duration as i64)).to_rfc3339(),
+                            &record.window_title,
+                            0,
+                        ),
+                    ).unwrap();
+                }
+            },
+            BatchSize::SmallInput,
+        )
+    });
+
+    let storage = open_storage("per-record");
+    group.bench_function("record_usage_per_record", |b| {
+        b.iter_batched(
+            || records(BATCH_SIZE),
+            |records| {
+                for record in records {
+                    storage.record_usage(record).unwrap();
+                }
+            },
+            BatchSize::SmallInput,
+        )
+    });
+
+    let storage = open_storage("batched");
+    group.bench_function("record_batch", |b| {
+        b.iter_batched(
+            || records(BATCH_SIZE),
+            |records| storage.record_batch(&records).unwrap(),
+            BatchSize::SmallInput,
+        )
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, storage_write);
+criterion_main!(benches);
@@ -0,0 +1,33 @@
+import Gio from 'gi://Gio';
+import {Extension} from 'resource:///org/gnome/shell/extensions/extension.js';
+
+const OBJECT_PATH = '/com/timewhisper/ActiveWindow';
+const INTERFACE = `
+<node>
+  <interface name="com.timewhisper.ActiveWindow">
+    <method name="Get">
+      <arg type="u" direction="out" name="pid"/>
+      <arg type="s" direction="out" name="window_class"/>
+      <arg type="s" direction="out" name="title"/>
+    </method>
+  </interface>
+</node>`;
+
+export default class TimeWhisperExtension extends Extension {
+    enable() {
+        this._dbus = Gio.DBusExportedObject.wrapJSObject(INTERFACE, this);
+        this._dbus.export(Gio.DBus.session, OBJECT_PATH);
+    }
+
+    disable() {
+        this._dbus.unexport();
+        this._dbus = null;
+    }
+
+    Get() {
+        const window = global.display.focus_window;
+        if (!window)
+            return [0, '', ''];
+        return [Math.max(window.get_pid(), 0), window.get_wm_class() ?? '', window.get_title() ?? ''];
+    }
+}
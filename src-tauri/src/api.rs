@@ -0,0 +1,393 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use axum::extract::{Query, Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio_stream::wrappers::WatchStream;
+use tokio_stream::{Stream, StreamExt};
+use crate::db::storage::{Storage, StorageError};
+use crate::db::types::{AppUsage, AppUsageStats, Granularity, SessionRecord, UsageQuery};
+
+const API_ENABLED_KEY: &str = "api_enabled";
+const API_PORT_KEY: &str = "api_port";
+const API_TOKEN_KEY: &str = "api_token";
+pub const DEFAULT_API_PORT: u16 = 7531;
+// 单次请求最多返回的原始会话数
+const MAX_EVENTS: usize = 10_000;
+
+// 与 usage_updated 事件相同的实时数据
+pub type LiveUsage = HashMap<String, AppUsage>;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ApiSettings {
+    pub enabled: bool,
+    pub port: u16,
+    pub token: String,
+}
+
+impl ApiSettings {
+    // 默认关闭，第一次读取时生成令牌并保存
+    pub fn load(storage: &Storage) -> Result<Self, StorageError> {
+        let enabled = storage.get_setting(API_ENABLED_KEY)?.as_deref() == Some("true");
+        let port = storage.get_setting(API_PORT_KEY)?
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(DEFAULT_API_PORT);
+        let token = match storage.get_setting(API_TOKEN_KEY)? {
+            Some(token) if !token.is_empty() => token,
+            _ => {
+                let token = generate_token()?;
+                storage.set_setting(API_TOKEN_KEY, &token)?;
+                token
+            }
+        };
+        Ok(Self { enabled, port, token })
+    }
+
+    pub fn save(&self, storage: &Storage) -> Result<(), StorageError> {
+        if self.port == 0 {
+            return Err(StorageError::InvalidQuery("API port must not be 0".to_string()));
+        }
+        if self.token.len() < 16 {
+            return Err(StorageError::InvalidQuery("API token must be at least 16 characters".to_string()));
+        }
+        storage.set_setting(API_ENABLED_KEY, if self.enabled { "true" } else { "false" })?;
+        storage.set_setting(API_PORT_KEY, &self.port.to_string())?;
+        storage.set_setting(API_TOKEN_KEY, &self.token)
+    }
+}
+
+pub fn generate_token() -> io::Result<String> {
+    let mut bytes = [0u8; 32];
+    getrandom::getrandom(&mut bytes).map_err(io::Error::from)?;
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+// 比较耗时与第一个不同字符的位置无关
+fn token_matches(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given.bytes().zip(expected.bytes()).fold(0u8, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+#[derive(Clone)]
+struct ApiState {
+    storage: Arc<Mutex<Storage>>,
+    token: Arc<str>,
+    live: watch::Receiver<LiveUsage>,
+}
+
+struct ApiError(StatusCode, String);
+
+impl From<StorageError> for ApiError {
+    fn from(error: StorageError) -> Self {
+        let status = match error {
+            StorageError::InvalidQuery(_) | StorageError::InvalidTimeZone(_) => StatusCode::BAD_REQUEST,
+            StorageError::NotFound(_) => StatusCode::NOT_FOUND,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        ApiError(status, error.to_string())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(serde_json::json!({ "error": self.1 }))).into_response()
+    }
+}
+
+// 和应用共用一个数据库连接，SQLite 调用放到阻塞线程池中
+async fn with_storage<T, F>(state: &ApiState, f: F) -> Result<T, ApiError>
+where
+    T: Send + 'static,
+    F: FnOnce(&Storage) -> Result<T, StorageError> + Send + 'static,
+{
+    let storage = state.storage.clone();
+    tokio::task::spawn_blocking(move || {
+        let storage = storage.lock().map_err(|e| ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        f(&storage).map_err(ApiError::from)
+    })
+        .await
+        .map_err(|e| ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+}
+
+#[derive(Deserialize)]
+struct RangeParams {
+    range: Option<String>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    granularity: Option<Granularity>,
+    // 逗号分隔的应用名
+    apps: Option<String>,
+    limit: Option<usize>,
+    time_zone: Option<String>,
+}
+
+impl RangeParams {
+    fn to_query(&self, storage: &Storage) -> Result<UsageQuery, StorageError> {
+        let mut query = match (self.from, self.to) {
+            (Some(from), Some(to)) => UsageQuery {
+                from,
+                to,
+                granularity: Granularity::Day,
+                apps: Vec::new(),
+                limit: None,
+                time_zone: None,
+            },
+            (None, None) => storage.range_query(self.range.as_deref().unwrap_or("daily"))?,
+            _ => return Err(StorageError::InvalidQuery("from and to must be given together".to_string())),
+        };
+        query.granularity = self.granularity.unwrap_or(query.granularity);
+        query.apps = self.apps.as_deref()
+            .map(|apps| apps.split(',').map(|app| app.trim().to_string()).collect())
+            .unwrap_or_default();
+        query.limit = self.limit;
+        query.time_zone = self.time_zone.clone();
+        Ok(query)
+    }
+}
+
+async fn require_token(State(state): State<ApiState>, request: Request, next: Next) -> Response {
+    let header_token = request.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    // EventSource 无法设置请求头，允许通过查询参数传递令牌
+    let query_token = request.uri()
+        .query()
+        .and_then(|query| query.split('&').find_map(|pair| pair.strip_prefix("token=")));
+
+    match header_token.or(query_token) {
+        Some(token) if token_matches(token, &state.token) => next.run(request).await,
+        _ => ApiError(StatusCode::UNAUTHORIZED, "Missing or invalid token".to_string()).into_response(),
+    }
+}
+
+// 最近一次出现在前台的应用，启动时从数据库载入的应用还没有出现过（last_active 为 0）
+async fn current(State(state): State<ApiState>) -> Json<Option<AppUsage>> {
+    let live = state.live.borrow();
+    Json(live.values().filter(|usage| usage.last_active > 0).max_by_key(|usage| usage.last_active).cloned())
+}
+
+async fn today(State(state): State<ApiState>) -> Result<Json<Vec<AppUsageStats>>, ApiError> {
+    with_storage(&state, |storage| storage.get_usage_stats("daily")).await.map(Json)
+}
+
+async fn stats(State(state): State<ApiState>, Query(params): Query<RangeParams>) -> Result<Json<Vec<AppUsageStats>>, ApiError> {
+    with_storage(&state, move |storage| storage.get_usage_stats_range(&params.to_query(storage)?))
+        .await
+        .map(Json)
+}
+
+async fn events(State(state): State<ApiState>, Query(params): Query<RangeParams>) -> Result<Json<Vec<SessionRecord>>, ApiError> {
+    with_storage(&state, move |storage| {
+        let mut query = params.to_query(storage)?;
+        let limit = query.limit.take().unwrap_or(MAX_EVENTS).min(MAX_EVENTS);
+        let mut sessions = Vec::new();
+        storage.visit_sessions(&query, |session| {
+            if sessions.len() < limit {
+                sessions.push(session);
+            }
+            Ok::<(), StorageError>(())
+        })?;
+        Ok(sessions)
+    })
+    .await
+    .map(Json)
+}
+
+async fn stream(State(state): State<ApiState>) -> Sse<impl Stream<Item = Result<Event, axum::Error>>> {
+    let updates = WatchStream::new(state.live.clone())
+        .map(|live| Event::default().event("usage_updated").json_data(live));
+    Sse::new(updates).keep_alive(KeepAlive::default())
+}
+
+pub fn router(storage: Arc<Mutex<Storage>>, token: &str, live: watch::Receiver<LiveUsage>) -> Router {
+    let state = ApiState {
+        storage,
+        token: Arc::from(token),
+        live,
+    };
+    Router::new()
+        .route("/api/current", get(current))
+        .route("/api/today", get(today))
+        .route("/api/stats", get(stats))
+        .route("/api/events", get(events))
+        .route("/api/stream", get(stream))
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_token))
+        .with_state(state)
+}
+
+// 丢弃时停止服务
+pub struct ApiServer {
+    addr: SocketAddr,
+    task: JoinHandle<()>,
+}
+
+impl ApiServer {
+    // 只监听 127.0.0.1，port 为 0 时由系统分配
+    pub async fn start(port: u16, storage: Arc<Mutex<Storage>>, token: &str, live: watch::Receiver<LiveUsage>) -> io::Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port)).await?;
+        let addr = listener.local_addr()?;
+        let app = router(storage, token, live);
+        let task = tokio::spawn(async move {
+            if let Err(e) = axum::serve(listener, app).await {
+                tracing::error!("HTTP API stopped: {}", e);
+            }
+        });
+        tracing::info!("HTTP API listening on http://{}", addr);
+        Ok(Self { addr, task })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for ApiServer {
+    fn drop(&mut self) {
+        self.task.abort();
+        tracing::info!("HTTP API on {} stopped", self.addr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::types::{AppUsageRecord, UsageState};
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, Lines};
+    use tokio::net::TcpStream;
+
+    const TOKEN: &str = "0123456789abcdef0123456789abcdef";
+
+    fn shared_storage() -> Arc<Mutex<Storage>> {
+        Arc::new(Mutex::new(Storage::open_in_memory().unwrap()))
+    }
+
+    fn usage(name: &str, total_time: u64, last_active: u64) -> (String, AppUsage) {
+        (name.to_string(), AppUsage { name: name.to_string(), total_time, last_active })
+    }
+
+    async fn send(addr: SocketAddr, path: &str, token: Option<&str>) -> TcpStream {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let auth = token.map(|t| format!("Authorization: Bearer {}\r\n", t)).unwrap_or_default();
+        let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n{}Connection: close\r\n\r\n", path, auth);
+        stream.write_all(request.as_bytes()).await.unwrap();
+        stream
+    }
+
+    async fn get(addr: SocketAddr, path: &str, token: Option<&str>) -> (u16, serde_json::Value) {
+        let mut response = String::new();
+        send(addr, path, token).await.read_to_string(&mut response).await.unwrap();
+        let status = response[9..12].parse().unwrap();
+        let body = response.split_once("\r\n\r\n").unwrap().1;
+        (status, serde_json::from_str(body).unwrap())
+    }
+
+    // 1. 没有令牌或令牌错误时拒绝访问
+    #[tokio::test]
+    async fn test_requires_token() {
+        let (_, live) = watch::channel(LiveUsage::new());
+        let server = ApiServer::start(0, shared_storage(), TOKEN, live).await.unwrap();
+        assert!(server.addr().ip().is_loopback());
+
+        assert_eq!(get(server.addr(), "/api/current", None).await.0, 401);
+        assert_eq!(get(server.addr(), "/api/current", Some("wrong")).await.0, 401);
+        assert_eq!(get(server.addr(), &format!("/api/current?token={}", TOKEN), None).await.0, 200);
+    }
+
+    // 2. 实时数据和数据库查询
+    #[tokio::test]
+    async fn test_endpoints() {
+        let storage = shared_storage();
+        let start = Utc::now() - chrono::Duration::seconds(90);
+        for (offset, app_name) in [(0, "code"), (40, "firefox")] {
+            storage.lock().unwrap().record_usage(AppUsageRecord {
+                timestamp: start + chrono::Duration::seconds(offset),
+                app_name: app_name.to_string(),
+                duration: 30,
+                state: UsageState::Active,
+                window_title: None,
+                window_class: None,
+                exe_path: None,
+                pid: None,
+                utc_offset: None,
+            }).unwrap();
+        }
+
+        let (sender, live) = watch::channel(LiveUsage::new());
+        let server = ApiServer::start(0, storage.clone(), TOKEN, live).await.unwrap();
+        let addr = server.addr();
+
+        assert_eq!(get(addr, "/api/current", Some(TOKEN)).await.1, serde_json::Value::Null);
+        sender.send_replace(LiveUsage::from([usage("code", 30, 100), usage("firefox", 30, 160)]));
+        assert_eq!(get(addr, "/api/current", Some(TOKEN)).await.1["name"], "firefox");
+
+        let (status, today) = get(addr, "/api/today", Some(TOKEN)).await;
+        assert_eq!(status, 200);
+        assert_eq!(today.as_array().unwrap().len(), 2);
+
+        let (_, stats) = get(addr, "/api/stats?range=weekly&apps=code", Some(TOKEN)).await;
+        assert_eq!(stats[0]["name"], "code");
+        assert_eq!(stats[0]["total_time"], 30);
+
+        let (_, events) = get(addr, "/api/events?range=daily&limit=1", Some(TOKEN)).await;
+        assert_eq!(events.as_array().unwrap().len(), 1);
+        assert_eq!(events[0]["app_name"], "code");
+
+        assert_eq!(get(addr, "/api/stats?range=yearly", Some(TOKEN)).await.0, 400);
+        assert_eq!(get(addr, "/api/stats?from=2026-03-01T00:00:00Z", Some(TOKEN)).await.0, 400);
+
+        drop(server);
+    }
+
+    async fn next_data(lines: &mut Lines<BufReader<TcpStream>>) -> serde_json::Value {
+        loop {
+            let line = lines.next_line().await.unwrap().unwrap();
+            if let Some(data) = line.strip_prefix("data: ") {
+                return serde_json::from_str(data).unwrap();
+            }
+        }
+    }
+
+    // 3. SSE 推送实时数据的每次更新
+    #[tokio::test]
+    async fn test_stream() {
+        let (sender, live) = watch::channel(LiveUsage::new());
+        let server = ApiServer::start(0, shared_storage(), TOKEN, live).await.unwrap();
+        let mut lines = BufReader::new(send(server.addr(), "/api/stream", Some(TOKEN)).await).lines();
+
+        assert_eq!(next_data(&mut lines).await, serde_json::json!({}));
+        sender.send_replace(LiveUsage::from([usage("code", 1, 100)]));
+        assert_eq!(next_data(&mut lines).await["code"]["total_time"], 1);
+    }
+
+    // 4. 设置默认关闭，令牌只生成一次
+    #[test]
+    fn test_api_settings() {
+        let storage = Storage::open_in_memory().unwrap();
+        let settings = ApiSettings::load(&storage).unwrap();
+        assert!(!settings.enabled);
+        assert_eq!(settings.port, DEFAULT_API_PORT);
+        assert_eq!(settings.token.len(), 64);
+        assert_eq!(ApiSettings::load(&storage).unwrap(), settings);
+
+        let updated = ApiSettings { enabled: true, port: 9000, ..settings.clone() };
+        updated.save(&storage).unwrap();
+        assert_eq!(ApiSettings::load(&storage).unwrap(), updated);
+        assert!(ApiSettings { port: 0, ..settings.clone() }.save(&storage).is_err());
+        assert!(ApiSettings { token: "short".to_string(), ..settings }.save(&storage).is_err());
+
+        assert!(token_matches(TOKEN, TOKEN));
+        assert!(!token_matches(TOKEN, &TOKEN[1..]));
+    }
+}
@@ -0,0 +1,280 @@
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use app_lib::db::export::{self, ExportFormat, ExportKind};
+use app_lib::db::storage::{self, Storage};
+use app_lib::db::types::{AppUsageStats, Granularity, UsageQuery};
+use chrono::{DateTime, Utc};
+use clap::{Parser, Subcommand, ValueEnum};
+
+#[derive(Parser)]
+#[command(name = "time-whisper-cli", version, about = "Query Time Whisper usage statistics from the command line")]
+struct Cli {
+    #[arg(long, global = true, help = "Path to usage_stats.db (defaults to the desktop app's database)")]
+    db: Option<PathBuf>,
+    #[arg(long, global = true, help = "Print JSON instead of a table")]
+    json: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    #[command(about = "Usage since local midnight")]
+    Today,
+    #[command(about = "Most used applications in a range")]
+    Top {
+        #[arg(long, default_value = "daily", help = "daily, 3days, weekly or monthly")]
+        range: String,
+        #[arg(long, short = 'n', default_value_t = 10)]
+        limit: usize,
+    },
+    #[command(about = "Usage of one application per day, week or month")]
+    App {
+        name: String,
+        #[arg(long, value_enum, default_value_t = By::Day)]
+        by: By,
+        #[arg(long, default_value = "weekly", help = "daily, 3days, weekly or monthly")]
+        range: String,
+    },
+    #[command(about = "Export sessions or aggregated stats")]
+    Export {
+        #[arg(long, value_enum, default_value_t = Kind::Records)]
+        kind: Kind,
+        #[arg(long, value_enum, help = "Defaults to the output file extension")]
+        format: Option<Format>,
+        #[arg(long, default_value = "monthly", help = "daily, 3days, weekly or monthly")]
+        range: String,
+        #[arg(long, requires = "to", help = "RFC 3339 start time, overrides --range")]
+        from: Option<DateTime<Utc>>,
+        #[arg(long, requires = "from", help = "RFC 3339 end time")]
+        to: Option<DateTime<Utc>>,
+        #[arg(help = "Output file, or - for stdout")]
+        output: PathBuf,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum By {
+    Day,
+    Week,
+    Month,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Kind {
+    Records,
+    Stats,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    Csv,
+    Jsonl,
+    Parquet,
+}
+
+impl From<By> for Granularity {
+    fn from(by: By) -> Self {
+        match by {
+            By::Day => Granularity::Day,
+            By::Week => Granularity::Week,
+            By::Month => Granularity::Month,
+        }
+    }
+}
+
+impl From<Kind> for ExportKind {
+    fn from(kind: Kind) -> Self {
+        match kind {
+            Kind::Records => ExportKind::Records,
+            Kind::Stats => ExportKind::Stats,
+        }
+    }
+}
+
+impl From<Format> for ExportFormat {
+    fn from(format: Format) -> Self {
+        match format {
+            Format::Csv => ExportFormat::Csv,
+            Format::Jsonl => ExportFormat::Jsonl,
+            Format::Parquet => ExportFormat::Parquet,
+        }
+    }
+}
+
+fn format_from_extension(path: &Path) -> Option<ExportFormat> {
+    match path.extension()?.to_str()?.to_lowercase().as_str() {
+        "csv" => Some(ExportFormat::Csv),
+        "jsonl" | "ndjson" => Some(ExportFormat::Jsonl),
+        "parquet" => Some(ExportFormat::Parquet),
+        _ => None,
+    }
+}
+
+fn format_duration(seconds: u64) -> String {
+    let (hours, minutes, seconds) = (seconds / 3600, seconds % 3600 / 60, seconds % 60);
+    if hours > 0 {
+        format!("{}h {:02}m", hours, minutes)
+    } else if minutes > 0 {
+        format!("{}m {:02}s", minutes, seconds)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+fn print_json<T: serde::Serialize>(value: &T) -> Result<(), String> {
+    let text = serde_json::to_string_pretty(value).map_err(|e| e.to_string())?;
+    println!("{}", text);
+    Ok(())
+}
+
+// 两列表格，第一列左对齐、第二列右对齐
+fn print_table(header: (&str, &str), rows: &[(String, String)]) {
+    let left = rows.iter().map(|(l, _)| l.chars().count()).chain([header.0.len()]).max().unwrap_or(0);
+    let right = rows.iter().map(|(_, r)| r.len()).chain([header.1.len()]).max().unwrap_or(0);
+    println!("{:<left$}  {:>right$}", header.0, header.1, left = left, right = right);
+    for (l, r) in rows {
+        println!("{:<left$}  {:>right$}", l, r, left = left, right = right);
+    }
+}
+
+fn print_stats(stats: &[AppUsageStats], json: bool) -> Result<(), String> {
+    if json {
+        return print_json(&stats);
+    }
+    if stats.is_empty() {
+        println!("No usage recorded");
+        return Ok(());
+    }
+    let rows: Vec<(String, String)> = stats.iter()
+        .map(|stat| (stat.name.clone(), format_duration(stat.total_time)))
+        .collect();
+    print_table(("APP", "TIME"), &rows);
+    Ok(())
+}
+
+fn run(cli: Cli) -> Result<(), String> {
+    let db_path = match cli.db {
+        Some(path) => path,
+        None => storage::default_database_path().ok_or("Cannot determine the data directory, pass --db")?,
+    };
+    let storage = Storage::open(&db_path).map_err(|e| e.to_string())?;
+
+    match cli.command {
+        Command::Today => {
+            let stats = storage.get_usage_stats("daily").map_err(|e| e.to_string())?;
+            print_stats(&stats, cli.json)
+        }
+        Command::Top { range, limit } => {
+            let mut query = storage.range_query(&range).map_err(|e| e.to_string())?;
+            query.limit = Some(limit);
+            let stats = storage.get_usage_stats_range(&query).map_err(|e| e.to_string())?;
+            print_stats(&stats, cli.json)
+        }
+        Command::App { name, by, range } => {
+            let mut query = storage.range_query(&range).map_err(|e| e.to_string())?;
+            query.granularity = by.into();
+            query.apps = vec![name.clone()];
+            let stat = storage.get_usage_stats_range(&query)
+                .map_err(|e| e.to_string())?
+                .pop()
+                .unwrap_or(AppUsageStats {
+                    name,
+                    total_time: 0,
+                    daily_usage: Vec::new(),
+                    titles: Vec::new(),
+                });
+            if cli.json {
+                return print_json(&stat);
+            }
+            let mut rows: Vec<(String, String)> = stat.daily_usage.iter()
+                .map(|daily| (daily.date.to_string(), format_duration(daily.duration)))
+                .collect();
+            rows.push(("total".to_string(), format_duration(stat.total_time)));
+            print_table(("DATE", "TIME"), &rows);
+            Ok(())
+        }
+        Command::Export { kind, format, range, from, to, output } => {
+            let query = match (from, to) {
+                (Some(from), Some(to)) => UsageQuery {
+                    from,
+                    to,
+                    granularity: Granularity::Day,
+                    apps: Vec::new(),
+                    limit: None,
+                    time_zone: None,
+                },
+                _ => storage.range_query(&range).map_err(|e| e.to_string())?,
+            };
+            let to_stdout = output.as_os_str() == "-";
+            let format = format.map(ExportFormat::from)
+                .or_else(|| format_from_extension(&output))
+                .or(if to_stdout { Some(ExportFormat::Jsonl) } else { None })
+                .ok_or("Cannot infer the export format from the file name, pass --format")?;
+
+            let count = if to_stdout {
+                let mut stdout = io::stdout();
+                let count = match kind {
+                    Kind::Records => export::export_records(&storage, &query, format, &mut stdout),
+                    Kind::Stats => export::export_stats(&storage, &query, format, &mut stdout),
+                }
+                .map_err(|e| e.to_string())?;
+                stdout.flush().map_err(|e| e.to_string())?;
+                count
+            } else {
+                export::export_to_path(&storage, &query, kind.into(), format, &output)
+                    .map_err(|e| e.to_string())?
+            };
+            eprintln!("Exported {} rows", count);
+            Ok(())
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    // 日志写到 stderr，避免混入管道中的输出
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::WARN)
+        .with_writer(io::stderr)
+        .init();
+
+    match run(Cli::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(0), "0s");
+        assert_eq!(format_duration(59), "59s");
+        assert_eq!(format_duration(61), "1m 01s");
+        assert_eq!(format_duration(3600 * 26 + 120), "26h 02m");
+    }
+
+    #[test]
+    fn test_format_from_extension() {
+        assert_eq!(format_from_extension(Path::new("out.CSV")), Some(ExportFormat::Csv));
+        assert_eq!(format_from_extension(Path::new("out.ndjson")), Some(ExportFormat::Jsonl));
+        assert_eq!(format_from_extension(Path::new("dir/out.parquet")), Some(ExportFormat::Parquet));
+        assert_eq!(format_from_extension(Path::new("out")), None);
+    }
+
+    #[test]
+    fn test_cli_arguments() {
+        let cli = Cli::try_parse_from(["time-whisper-cli", "app", "firefox", "--by", "week", "--json"]).unwrap();
+        assert!(cli.json);
+        assert!(matches!(cli.command, Command::App { ref name, by: By::Week, .. } if name == "firefox"));
+
+        // --from 必须和 --to 一起使用
+        assert!(Cli::try_parse_from(["time-whisper-cli", "export", "--from", "2026-03-01T00:00:00Z", "out.csv"]).is_err());
+    }
+}
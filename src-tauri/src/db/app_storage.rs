@@ -0,0 +1,17 @@
+use std::io;
+use std::path::PathBuf;
+use tauri::{AppHandle, Manager};
+use super::storage::{Storage, StorageError, DATABASE_FILE};
+
+// 桌面应用的数据库位于 Tauri 的 app_data_dir 下
+pub fn database_path(app_handle: &AppHandle) -> Result<PathBuf, StorageError> {
+    let data_dir = app_handle
+        .path()
+        .app_data_dir()
+        .map_err(|e| StorageError::Io(io::Error::new(io::ErrorKind::NotFound, e.to_string())))?;
+    Ok(data_dir.join(DATABASE_FILE))
+}
+
+pub fn open_storage(app_handle: &AppHandle) -> Result<Storage, StorageError> {
+    Storage::open(&database_path(app_handle)?)
+}
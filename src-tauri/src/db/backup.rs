@@ -0,0 +1,353 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use rusqlite::backup::{Backup, StepResult};
+use rusqlite::{Connection, ErrorCode};
+use super::encryption::{self, DatabaseKey};
+use super::storage::{remove_if_exists, replace_database, with_suffix, Storage, StorageError};
+use super::types::BackupInfo;
+
+// 保留最近的几个备份，更早的在创建新备份后删除
+pub const MAX_BACKUPS: usize = 7;
+const BACKUP_DIR: &str = "backups";
+const BACKUP_PREFIX: &str = "usage_stats-";
+const BACKUP_EXTENSION: &str = ".db";
+const NAME_TIME_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+// 被恢复替换掉的数据库保留为 usage_stats.db.pre-restore，只保留最近一个
+const PRE_RESTORE_SUFFIX: &str = ".pre-restore";
+// 替换数据库期间保留的原文件，替换后的数据库打不开时换回
+const ROLLBACK_SUFFIX: &str = ".rollback";
+const BACKUP_RETRY_DELAY: std::time::Duration = std::time::Duration::from_millis(100);
+
+// 备份保存在数据库所在目录的 backups 下
+pub fn backup_dir(db_path: &Path) -> PathBuf {
+    db_path.parent().unwrap_or(Path::new(".")).join(BACKUP_DIR)
+}
+
+// 按创建时间从新到旧排列
+pub fn list_backups(db_path: &Path) -> Result<Vec<BackupInfo>, StorageError> {
+    let entries = match fs::read_dir(backup_dir(db_path)) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut backups = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let Some(created_at) = parse_backup_name(&name) else {
+            continue;
+        };
+        backups.push(BackupInfo { name, created_at, size: entry.metadata()?.len() });
+    }
+    backups.sort_by_key(|backup| std::cmp::Reverse(backup.created_at));
+    Ok(backups)
+}
+
+fn parse_backup_name(name: &str) -> Option<DateTime<Utc>> {
+    let time = name.strip_prefix(BACKUP_PREFIX)?.strip_suffix(BACKUP_EXTENSION)?;
+    NaiveDateTime::parse_from_str(time, NAME_TIME_FORMAT).ok().map(|time| time.and_utc())
+}
+
+// 通过 SQLite 的在线备份接口复制，不需要停止写入。加密的数据库用同一个密钥加密备份
+pub fn create_backup(db_path: &Path, now: DateTime<Utc>) -> Result<BackupInfo, StorageError> {
+    let dir = backup_dir(db_path);
+    fs::create_dir_all(&dir)?;
+    let key = encryption::database_key(db_path)?;
+    let source = Storage::open_with_key(db_path, key.as_ref())?;
+
+    let name = format!("{}{}{}", BACKUP_PREFIX, now.format(NAME_TIME_FORMAT), BACKUP_EXTENSION);
+    let path = dir.join(&name);
+    // 复制完成前使用临时文件名，中途失败不会留下不完整的备份
+    let partial = with_suffix(&path, ".partial");
+    let result = (|| -> Result<(), StorageError> {
+        let mut dest = Connection::open(&partial)?;
+        if let Some(key) = &key {
+            encryption::apply_key(&dest, key)?;
+        }
+        // 一次复制所有页，其他连接的写入不会让备份从头开始；数据库被锁住时稍后重试
+        let backup = Backup::new(&source.conn, &mut dest)?;
+        while backup.step(-1)? != StepResult::Done {
+            std::thread::sleep(BACKUP_RETRY_DELAY);
+        }
+        Ok(())
+    })();
+    if let Err(e) = result {
+        let _ = fs::remove_file(&partial);
+        return Err(e);
+    }
+    fs::rename(&partial, &path)?;
+    let info = BackupInfo { name, created_at: now, size: fs::metadata(&path)?.len() };
+    tracing::info!("Created database backup {} ({} bytes)", info.name, info.size);
+
+    for old in list_backups(db_path)?.into_iter().skip(MAX_BACKUPS) {
+        fs::remove_file(dir.join(&old.name))?;
+        tracing::info!("Removed old database backup {}", old.name);
+    }
+    Ok(info)
+}
+
+// 返回 integrity_check 报告的问题，完好的数据库返回空列表
+pub fn check_integrity(path: &Path, key: Option<&DatabaseKey>) -> Result<Vec<String>, StorageError> {
+    let result = (|| -> Result<Vec<String>, StorageError> {
+        let conn = Connection::open(path)?;
+        if let Some(key) = key {
+            encryption::apply_key(&conn, key)?;
+        }
+        let mut stmt = conn.prepare("PRAGMA integrity_check")?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        let problems = rows.collect::<Result<Vec<_>, _>>()?;
+        Ok(problems.into_iter().filter(|problem| problem != "ok").collect())
+    })();
+    // 损坏严重时连文件头或 schema 都无法读取
+    match result {
+        Err(StorageError::Sqlite(e)) if matches!(e.sqlite_error_code(), Some(ErrorCode::DatabaseCorrupt | ErrorCode::NotADatabase)) => {
+            Ok(vec![e.to_string()])
+        }
+        result => result,
+    }
+}
+
+// 用指定的备份替换数据库。调用前需要关闭这个数据库的其他连接
+pub fn restore_backup(db_path: &Path, name: &str) -> Result<(), StorageError> {
+    // 只接受备份目录中列出的文件名
+    if !list_backups(db_path)?.iter().any(|backup| backup.name == name) {
+        return Err(StorageError::NotFound(format!("backup {}", name)));
+    }
+    let backup = backup_dir(db_path).join(name);
+    let key = encryption::database_key(db_path)?;
+    let problems = check_integrity(&backup, key.as_ref())?;
+    if !problems.is_empty() {
+        return Err(StorageError::InvalidQuery(format!("backup {} is corrupted: {}", name, problems.join("; "))));
+    }
+    restore_from(db_path, &backup)?;
+    tracing::info!("Restored database from backup {}", name);
+    Ok(())
+}
+
+// 启动时检查数据库，损坏时从最近一个完好的备份恢复，返回使用的备份
+pub fn restore_if_corrupted(db_path: &Path) -> Result<Option<BackupInfo>, StorageError> {
+    if !db_path.exists() {
+        return Ok(None);
+    }
+    let key = encryption::database_key(db_path)?;
+    let problems = check_integrity(db_path, key.as_ref())?;
+    if problems.is_empty() {
+        return Ok(None);
+    }
+    tracing::error!("Database integrity check failed: {}", problems.join("; "));
+
+    for backup in list_backups(db_path)? {
+        let path = backup_dir(db_path).join(&backup.name);
+        match check_integrity(&path, key.as_ref()) {
+            Ok(problems) if problems.is_empty() => {
+                restore_from(db_path, &path)?;
+                tracing::warn!("Restored database from backup {}", backup.name);
+                return Ok(Some(backup));
+            }
+            Ok(problems) => tracing::warn!("Skipping corrupted backup {}: {}", backup.name, problems.join("; ")),
+            Err(e) => tracing::warn!("Skipping unreadable backup {}: {}", backup.name, e),
+        }
+    }
+    tracing::error!("No usable backup found, keeping the damaged database");
+    Ok(None)
+}
+
+fn restore_from(db_path: &Path, backup: &Path) -> Result<(), StorageError> {
+    let staged = with_suffix(db_path, ".restore");
+    fs::copy(backup, &staged)?;
+    // 被替换的数据库连同 WAL 中的内容一起保留，以便手动找回
+    if db_path.exists() {
+        let key = encryption::database_key(db_path)?;
+        if let Err(e) = checkpoint(db_path, key.as_ref()) {
+            tracing::warn!("Failed to checkpoint the database before restoring: {}", e);
+        }
+        fs::copy(db_path, with_suffix(db_path, PRE_RESTORE_SUFFIX))?;
+    }
+    replace_database(&staged, db_path)
+}
+
+// 把 WAL 中的内容写回数据库文件，之后可以单独复制这个文件
+fn checkpoint(path: &Path, key: Option<&DatabaseKey>) -> Result<(), StorageError> {
+    let conn = Connection::open(path)?;
+    if let Some(key) = key {
+        encryption::apply_key(&conn, key)?;
+    }
+    conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+    Ok(())
+}
+
+// 替换数据库文件并确认新文件能够打开，失败时换回原来的文件和密钥并返回错误。
+// 调用前需要关闭这个数据库的其他连接
+pub fn replace_with_rollback(
+    db_path: &Path,
+    replace: impl FnOnce(&Path) -> Result<(), StorageError>,
+) -> Result<(), StorageError> {
+    let rollback = with_suffix(db_path, ROLLBACK_SUFFIX);
+    let original_key = encryption::database_key(db_path)?;
+    let has_original = db_path.exists();
+    if has_original {
+        checkpoint(db_path, original_key.as_ref())?;
+        fs::copy(db_path, &rollback)?;
+    }
+
+    let Err(e) = replace(db_path).and_then(|()| Storage::open(db_path).map(drop)) else {
+        return remove_if_exists(&rollback);
+    };
+    tracing::error!("Replacing the database failed, rolling back: {}", e);
+    if has_original {
+        replace_database(&rollback, db_path)?;
+    } else {
+        remove_if_exists(db_path)?;
+    }
+    encryption::restore_key(original_key)?;
+    Err(e)
+}
+
+// 用新密钥把已有的备份重写到临时文件，new_key 为空时解密，返回 (临时文件, 备份) 列表。
+// 任何一个备份无法重写时删除已经生成的临时文件并返回错误，这时不能更换密钥
+pub(super) fn rekey_backups(
+    db_path: &Path,
+    old_key: Option<&DatabaseKey>,
+    new_key: Option<&DatabaseKey>,
+) -> Result<Vec<(PathBuf, PathBuf)>, StorageError> {
+    let mut staged = Vec::new();
+    for backup in list_backups(db_path)? {
+        let path = backup_dir(db_path).join(&backup.name);
+        match encryption::rewrite_database(&path, old_key, new_key) {
+            Ok(rekeyed) => staged.push((rekeyed, path)),
+            Err(e) => {
+                discard_rekeyed(&staged);
+                return Err(StorageError::Encryption(format!("failed to re-key backup {}: {}", backup.name, e)));
+            }
+        }
+    }
+    Ok(staged)
+}
+
+pub(super) fn discard_rekeyed(staged: &[(PathBuf, PathBuf)]) {
+    for (rekeyed, _) in staged {
+        let _ = fs::remove_file(rekeyed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Seek, SeekFrom, Write};
+    use chrono::Duration;
+
+    fn utc(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(value).unwrap().with_timezone(&Utc)
+    }
+
+    fn temp_db(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("time-whisper-backup-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("usage_stats.db")
+    }
+
+    fn marker(path: &Path) -> Option<String> {
+        Storage::open_with_key(path, None).unwrap().get_setting("marker").unwrap()
+    }
+
+    // 覆盖第 2 页，表的 B 树损坏
+    fn corrupt(path: &Path) {
+        let mut file = fs::OpenOptions::new().write(true).open(path).unwrap();
+        file.seek(SeekFrom::Start(4096)).unwrap();
+        file.write_all(&[0xff; 4096]).unwrap();
+    }
+
+    // 1. 备份按时间命名，超过 MAX_BACKUPS 个时删除最早的
+    #[test]
+    fn test_create_and_rotate() {
+        let db_path = temp_db("rotate");
+        let storage = Storage::open(&db_path).unwrap();
+        storage.set_setting("marker", "first").unwrap();
+
+        let start = utc("2026-05-01T09:00:00Z");
+        let first = create_backup(&db_path, start).unwrap();
+        assert_eq!(first.name, "usage_stats-20260501T090000Z.db");
+        storage.set_setting("marker", "second").unwrap();
+        for day in 1..=MAX_BACKUPS as i64 {
+            create_backup(&db_path, start + Duration::days(day)).unwrap();
+        }
+
+        let backups = list_backups(&db_path).unwrap();
+        assert_eq!(backups.len(), MAX_BACKUPS);
+        assert_eq!(backups[0].created_at, start + Duration::days(MAX_BACKUPS as i64));
+        assert!(!backups.iter().any(|backup| backup.name == first.name));
+        // 备份包含创建时连接中尚未检查点的写入
+        assert_eq!(marker(&backup_dir(&db_path).join(&backups[0].name)).as_deref(), Some("second"));
+        let _ = fs::remove_dir_all(db_path.parent().unwrap());
+    }
+
+    // 2. 数据库损坏时从最近一个完好的备份恢复，跳过损坏的备份
+    #[test]
+    fn test_restore_if_corrupted() {
+        let db_path = temp_db("corrupted");
+        let storage = Storage::open(&db_path).unwrap();
+        storage.set_setting("marker", "good").unwrap();
+        create_backup(&db_path, utc("2026-05-01T09:00:00Z")).unwrap();
+        let newest = create_backup(&db_path, utc("2026-05-02T09:00:00Z")).unwrap();
+        drop(storage);
+        assert_eq!(restore_if_corrupted(&db_path).unwrap(), None);
+
+        corrupt(&backup_dir(&db_path).join(&newest.name));
+        corrupt(&db_path);
+        assert!(!check_integrity(&db_path, None).unwrap().is_empty());
+
+        let restored = restore_if_corrupted(&db_path).unwrap().unwrap();
+        assert_eq!(restored.created_at, utc("2026-05-01T09:00:00Z"));
+        assert!(check_integrity(&db_path, None).unwrap().is_empty());
+        assert_eq!(marker(&db_path).as_deref(), Some("good"));
+        assert!(with_suffix(&db_path, PRE_RESTORE_SUFFIX).exists());
+        let _ = fs::remove_dir_all(db_path.parent().unwrap());
+    }
+
+    // 3. 手动恢复只接受备份目录中的文件
+    #[test]
+    fn test_restore_backup() {
+        let db_path = temp_db("manual");
+        let storage = Storage::open(&db_path).unwrap();
+        storage.set_setting("marker", "old").unwrap();
+        let backup = create_backup(&db_path, utc("2026-05-01T09:00:00Z")).unwrap();
+        storage.set_setting("marker", "new").unwrap();
+        drop(storage);
+
+        assert!(matches!(restore_backup(&db_path, "../usage_stats.db"), Err(StorageError::NotFound(_))));
+        restore_backup(&db_path, &backup.name).unwrap();
+        assert_eq!(marker(&db_path).as_deref(), Some("old"));
+        assert_eq!(marker(&with_suffix(&db_path, PRE_RESTORE_SUFFIX)).as_deref(), Some("new"));
+        let _ = fs::remove_dir_all(db_path.parent().unwrap());
+    }
+
+    // 4. 替换后的数据库打不开时换回原来的文件
+    #[test]
+    fn test_replace_with_rollback() {
+        // 回滚时会写密钥链，不能和修改密钥的测试同时进行
+        #[cfg(feature = "encryption")]
+        let _guard = encryption::KEY_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let db_path = temp_db("rollback");
+        let storage = Storage::open(&db_path).unwrap();
+        storage.set_setting("marker", "kept").unwrap();
+        drop(storage);
+
+        let result = replace_with_rollback(&db_path, |path| {
+            fs::write(path, [0x5a; 4096])?;
+            Ok(())
+        });
+        assert!(result.is_err());
+        assert_eq!(marker(&db_path).as_deref(), Some("kept"));
+        assert!(!with_suffix(&db_path, ROLLBACK_SUFFIX).exists());
+
+        replace_with_rollback(&db_path, |path| {
+            Storage::open(path)?.set_setting("marker", "replaced")
+        }).unwrap();
+        assert_eq!(marker(&db_path).as_deref(), Some("replaced"));
+        assert!(!with_suffix(&db_path, ROLLBACK_SUFFIX).exists());
+        let _ = fs::remove_dir_all(db_path.parent().unwrap());
+    }
+}
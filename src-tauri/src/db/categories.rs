@@ -0,0 +1,441 @@
+use std::collections::HashMap;
+use chrono::NaiveDate;
+use regex::{Regex, RegexBuilder};
+use rusqlite::OptionalExtension;
+use super::storage::{Storage, StorageError};
+use super::types::{
+    Category, CategoryRule, CategoryStats, DailyUsage, MatchField, MatchKind, Productivity, ProductivityScore,
+    UsageQuery,
+};
+
+const UNCATEGORIZED: &str = "Uncategorized";
+
+// glob 转为整体匹配的正则，除 * 和 ? 外的字符都按字面匹配
+fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::from("^");
+    for c in glob.chars() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    regex
+}
+
+pub fn compile_pattern(kind: MatchKind, pattern: &str) -> Result<Regex, StorageError> {
+    if pattern.is_empty() {
+        return Err(StorageError::InvalidCategory("pattern must not be empty".to_string()));
+    }
+    let builder = match kind {
+        MatchKind::Glob => RegexBuilder::new(&glob_to_regex(pattern)).case_insensitive(true).build(),
+        MatchKind::Regex => RegexBuilder::new(pattern).build(),
+    };
+    builder.map_err(|e| StorageError::InvalidCategory(format!("{}: {}", pattern, e)))
+}
+
+struct CompiledRule {
+    category_id: i64,
+    field: MatchField,
+    regex: Regex,
+}
+
+// 按优先级依次尝试规则，第一个匹配的规则决定分类
+pub struct Categorizer {
+    rules: Vec<CompiledRule>,
+}
+
+impl Categorizer {
+    pub fn new(rules: &[CategoryRule]) -> Result<Self, StorageError> {
+        let mut sorted: Vec<&CategoryRule> = rules.iter().collect();
+        sorted.sort_by(|a, b| b.priority.cmp(&a.priority).then_with(|| a.id.cmp(&b.id)));
+
+        let rules = sorted.into_iter()
+            .map(|rule| Ok(CompiledRule {
+                category_id: rule.category_id,
+                field: rule.field,
+                regex: compile_pattern(rule.kind, &rule.pattern)?,
+            }))
+            .collect::<Result<Vec<_>, StorageError>>()?;
+        Ok(Self { rules })
+    }
+
+    pub fn categorize(&self, process_name: &str, window_class: Option<&str>, window_title: Option<&str>) -> Option<i64> {
+        self.rules.iter()
+            .find(|rule| {
+                let value = match rule.field {
+                    MatchField::ProcessName => Some(process_name),
+                    MatchField::WindowClass => window_class,
+                    MatchField::WindowTitle => window_title,
+                };
+                value.is_some_and(|value| rule.regex.is_match(value))
+            })
+            .map(|rule| rule.category_id)
+    }
+}
+
+fn productivity_score(productive: u64, neutral: u64, distracting: u64) -> u8 {
+    let total = productive + neutral + distracting;
+    if total == 0 {
+        return 0;
+    }
+    ((productive as f64 + neutral as f64 * 0.5) / total as f64 * 100.0).round() as u8
+}
+
+impl Storage {
+    pub fn list_categories(&self) -> Result<Vec<Category>, StorageError> {
+        let mut stmt = self.conn.prepare("SELECT id, name, productivity FROM categories ORDER BY name")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?))
+        })?;
+
+        let mut categories = Vec::new();
+        for row in rows {
+            let (id, name, productivity) = row?;
+            categories.push(Category {
+                id,
+                name,
+                productivity: Productivity::parse(&productivity).unwrap_or_default(),
+            });
+        }
+        Ok(categories)
+    }
+
+    fn check_category_name(&self, name: &str, exclude_id: Option<i64>) -> Result<(), StorageError> {
+        if name.trim().is_empty() {
+            return Err(StorageError::InvalidCategory("name must not be empty".to_string()));
+        }
+        let existing: Option<i64> = self.conn.query_row(
+            "SELECT id FROM categories WHERE name = ?1",
+            [name.trim()],
+            |row| row.get(0),
+        ).optional()?;
+        match existing {
+            Some(id) if Some(id) != exclude_id => {
+                Err(StorageError::InvalidCategory(format!("{} already exists", name.trim())))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn create_category(&self, name: &str, productivity: Productivity) -> Result<Category, StorageError> {
+        self.check_category_name(name, None)?;
+        self.conn.execute(
+            "INSERT INTO categories (name, productivity) VALUES (?1, ?2)",
+            (name.trim(), productivity.as_str()),
+        )?;
+        Ok(Category {
+            id: self.conn.last_insert_rowid(),
+            name: name.trim().to_string(),
+            productivity,
+        })
+    }
+
+    pub fn update_category(&self, category: &Category) -> Result<(), StorageError> {
+        self.check_category_name(&category.name, Some(category.id))?;
+        let updated = self.conn.execute(
+            "UPDATE categories SET name = ?1, productivity = ?2 WHERE id = ?3",
+            (category.name.trim(), category.productivity.as_str(), category.id),
+        )?;
+        if updated == 0 {
+            return Err(StorageError::NotFound(format!("category {}", category.id)));
+        }
+        Ok(())
+    }
+
+    // 同时删除该分类下的规则和限额
+    pub fn delete_category(&self, id: i64) -> Result<(), StorageError> {
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute("DELETE FROM category_rules WHERE category_id = ?1", [id])?;
+        tx.execute(
+            "DELETE FROM limit_alerts WHERE limit_id IN (SELECT id FROM usage_limits WHERE category_id = ?1)",
+            [id],
+        )?;
+        tx.execute("DELETE FROM usage_limits WHERE category_id = ?1", [id])?;
+        if tx.execute("DELETE FROM categories WHERE id = ?1", [id])? == 0 {
+            return Err(StorageError::NotFound(format!("category {}", id)));
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn list_category_rules(&self) -> Result<Vec<CategoryRule>, StorageError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, category_id, field, kind, pattern, priority FROM category_rules ORDER BY priority DESC, id"
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, i64>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, String>(4)?,
+                row.get::<_, i32>(5)?,
+            ))
+        })?;
+
+        let mut rules = Vec::new();
+        for row in rows {
+            let (id, category_id, field, kind, pattern, priority) = row?;
+            match (MatchField::parse(&field), MatchKind::parse(&kind)) {
+                (Some(field), Some(kind)) => rules.push(CategoryRule { id, category_id, field, kind, pattern, priority }),
+                _ => tracing::warn!("Skipping category rule {} with unknown field {} or kind {}", id, field, kind),
+            }
+        }
+        Ok(rules)
+    }
+
+    pub fn add_category_rule(
+        &self,
+        category_id: i64,
+        field: MatchField,
+        kind: MatchKind,
+        pattern: &str,
+        priority: i32,
+    ) -> Result<CategoryRule, StorageError> {
+        compile_pattern(kind, pattern)?;
+        let exists: bool = self.conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM categories WHERE id = ?1)",
+            [category_id],
+            |row| row.get(0),
+        )?;
+        if !exists {
+            return Err(StorageError::NotFound(format!("category {}", category_id)));
+        }
+
+        self.conn.execute(
+            "INSERT INTO category_rules (category_id, field, kind, pattern, priority) VALUES (?1, ?2, ?3, ?4, ?5)",
+            (category_id, field.as_str(), kind.as_str(), pattern, priority),
+        )?;
+        Ok(CategoryRule {
+            id: self.conn.last_insert_rowid(),
+            category_id,
+            field,
+            kind,
+            pattern: pattern.to_string(),
+            priority,
+        })
+    }
+
+    pub fn delete_category_rule(&self, id: i64) -> Result<(), StorageError> {
+        if self.conn.execute("DELETE FROM category_rules WHERE id = ?1", [id])? == 0 {
+            return Err(StorageError::NotFound(format!("category rule {}", id)));
+        }
+        Ok(())
+    }
+
+    pub fn categorizer(&self) -> Result<Categorizer, StorageError> {
+        Categorizer::new(&self.list_category_rules()?)
+    }
+
+    // 按分类汇总活跃时间，未匹配任何规则的时间归入 Uncategorized
+    pub fn get_category_stats(&self, query: &UsageQuery) -> Result<Vec<CategoryStats>, StorageError> {
+        let categories: HashMap<i64, Category> = self.list_categories()?
+            .into_iter()
+            .map(|category| (category.id, category))
+            .collect();
+        let categorizer = self.categorizer()?;
+
+        let mut bucket_totals: HashMap<(Option<i64>, NaiveDate), u64> = HashMap::new();
+        for (span, parts) in self.bucketed_spans(query)? {
+            let category_id = categorizer.categorize(
+                &span.app_name,
+                span.window_class.as_deref(),
+                span.window_title.as_deref(),
+            );
+            for (bucket, duration) in parts {
+                *bucket_totals.entry((category_id, bucket)).or_insert(0) += duration;
+            }
+        }
+
+        let mut stats: HashMap<Option<i64>, CategoryStats> = HashMap::new();
+        for ((category_id, date), duration) in bucket_totals {
+            let category = category_id.and_then(|id| categories.get(&id));
+            let stat = stats.entry(category_id).or_insert_with(|| CategoryStats {
+                category_id,
+                name: category.map_or(UNCATEGORIZED.to_string(), |c| c.name.clone()),
+                productivity: category.map_or(Productivity::Neutral, |c| c.productivity),
+                total_time: 0,
+                daily_usage: Vec::new(),
+            });
+            stat.total_time += duration;
+            stat.daily_usage.push(DailyUsage { date, duration });
+        }
+
+        let mut stats: Vec<CategoryStats> = stats.into_values()
+            .map(|mut stat| {
+                stat.daily_usage.sort_by_key(|d| d.date);
+                stat
+            })
+            .collect();
+        stats.sort_by(|a, b| b.total_time.cmp(&a.total_time).then_with(|| a.name.cmp(&b.name)));
+        if let Some(limit) = query.limit {
+            stats.truncate(limit);
+        }
+        Ok(stats)
+    }
+
+    // 每个时间桶的高效/中性/分心时长及得分，未分类的时间按中性计算
+    pub fn get_productivity_scores(&self, query: &UsageQuery) -> Result<Vec<ProductivityScore>, StorageError> {
+        let mut query = query.clone();
+        query.limit = None;
+
+        let mut scores: HashMap<NaiveDate, ProductivityScore> = HashMap::new();
+        for stat in self.get_category_stats(&query)? {
+            for daily in stat.daily_usage {
+                let score = scores.entry(daily.date).or_insert_with(|| ProductivityScore {
+                    date: daily.date,
+                    productive: 0,
+                    neutral: 0,
+                    distracting: 0,
+                    score: 0,
+                });
+                match stat.productivity {
+                    Productivity::Productive => score.productive += daily.duration,
+                    Productivity::Neutral => score.neutral += daily.duration,
+                    Productivity::Distracting => score.distracting += daily.duration,
+                }
+            }
+        }
+
+        let mut scores: Vec<ProductivityScore> = scores.into_values()
+            .map(|mut score| {
+                score.score = productivity_score(score.productive, score.neutral, score.distracting);
+                score
+            })
+            .collect();
+        scores.sort_by_key(|score| score.date);
+        Ok(scores)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::types::{AppUsageRecord, Granularity, UsageState};
+    use chrono::{DateTime, Utc};
+
+    fn utc(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(value).unwrap().with_timezone(&Utc)
+    }
+
+    fn rule(id: i64, category_id: i64, field: MatchField, kind: MatchKind, pattern: &str, priority: i32) -> CategoryRule {
+        CategoryRule { id, category_id, field, kind, pattern: pattern.to_string(), priority }
+    }
+
+    fn record(storage: &Storage, start: &str, app_name: &str, duration: u64, title: Option<&str>) {
+        storage.record_usage(AppUsageRecord {
+            timestamp: utc(start),
+            app_name: app_name.to_string(),
+            duration,
+            state: UsageState::Active,
+            window_title: title.map(String::from),
+            window_class: None,
+            exe_path: None,
+            pid: None,
+            utc_offset: None,
+        }).unwrap();
+    }
+
+    fn day_query() -> UsageQuery {
+        UsageQuery {
+            from: utc("2026-03-01T00:00:00Z"),
+            to: utc("2026-03-03T00:00:00Z"),
+            granularity: Granularity::Day,
+            apps: Vec::new(),
+            limit: None,
+            time_zone: Some("UTC".to_string()),
+        }
+    }
+
+    // 1. glob 忽略大小写并整体匹配，正则按原样匹配
+    #[test]
+    fn test_pattern_matching() {
+        let glob = compile_pattern(MatchKind::Glob, "code*.exe").unwrap();
+        assert!(glob.is_match("Code.exe"));
+        assert!(glob.is_match("code-insiders.exe"));
+        assert!(!glob.is_match("vscode.exe"));
+        assert!(compile_pattern(MatchKind::Glob, "a+b?").unwrap().is_match("a+bc"));
+
+        let regex = compile_pattern(MatchKind::Regex, "^n?vim$").unwrap();
+        assert!(regex.is_match("nvim"));
+        assert!(!regex.is_match("NVIM"));
+
+        assert!(matches!(compile_pattern(MatchKind::Regex, "("), Err(StorageError::InvalidCategory(_))));
+        assert!(matches!(compile_pattern(MatchKind::Glob, ""), Err(StorageError::InvalidCategory(_))));
+    }
+
+    // 2. 规则按优先级匹配，可以匹配类名和标题
+    #[test]
+    fn test_categorizer_priority() {
+        let categorizer = Categorizer::new(&[
+            rule(1, 10, MatchField::ProcessName, MatchKind::Glob, "firefox", 0),
+            rule(2, 20, MatchField::WindowTitle, MatchKind::Glob, "*YouTube*", 5),
+            rule(3, 30, MatchField::WindowClass, MatchKind::Glob, "jetbrains-*", 0),
+        ]).unwrap();
+
+        assert_eq!(categorizer.categorize("firefox", None, Some("Rust docs")), Some(10));
+        assert_eq!(categorizer.categorize("firefox", None, Some("Music - YouTube")), Some(20));
+        assert_eq!(categorizer.categorize("java", Some("jetbrains-idea"), None), Some(30));
+        assert_eq!(categorizer.categorize("java", None, None), None);
+    }
+
+    // 3. 分类和规则的增删改
+    #[test]
+    fn test_category_crud() {
+        let storage = Storage::open_in_memory().unwrap();
+        let defaults = storage.list_categories().unwrap();
+        assert!(defaults.iter().any(|c| c.name == "Development" && c.productivity == Productivity::Productive));
+
+        let mut reading = storage.create_category("Reading", Productivity::Productive).unwrap();
+        assert!(matches!(storage.create_category("Reading", Productivity::Neutral), Err(StorageError::InvalidCategory(_))));
+        assert!(matches!(storage.create_category("  ", Productivity::Neutral), Err(StorageError::InvalidCategory(_))));
+
+        reading.productivity = Productivity::Neutral;
+        storage.update_category(&reading).unwrap();
+        assert!(storage.list_categories().unwrap().contains(&reading));
+
+        let rule = storage.add_category_rule(reading.id, MatchField::ProcessName, MatchKind::Glob, "calibre*", 1).unwrap();
+        assert_eq!(storage.list_category_rules().unwrap()[0], rule);
+        assert!(matches!(
+            storage.add_category_rule(reading.id, MatchField::ProcessName, MatchKind::Regex, "[", 0),
+            Err(StorageError::InvalidCategory(_))
+        ));
+        assert!(matches!(
+            storage.add_category_rule(9999, MatchField::ProcessName, MatchKind::Glob, "x", 0),
+            Err(StorageError::NotFound(_))
+        ));
+
+        storage.delete_category(reading.id).unwrap();
+        assert!(!storage.list_category_rules().unwrap().iter().any(|r| r.id == rule.id));
+        assert!(matches!(storage.delete_category(reading.id), Err(StorageError::NotFound(_))));
+    }
+
+    // 4. 按分类汇总并计算每日得分
+    #[test]
+    fn test_category_stats_and_scores() {
+        let storage = Storage::open_in_memory().unwrap();
+        record(&storage, "2026-03-01T09:00:00Z", "Code.exe", 3600, None);            // Development
+        record(&storage, "2026-03-01T10:00:00Z", "firefox", 1800, Some("YouTube"));  // Entertainment
+        record(&storage, "2026-03-01T10:30:00Z", "slack", 1200, None);               // Communication
+        record(&storage, "2026-03-02T09:00:00Z", "calculator", 600, None);           // 未分类
+
+        let stats = storage.get_category_stats(&day_query()).unwrap();
+        let totals: Vec<(&str, u64)> = stats.iter().map(|s| (s.name.as_str(), s.total_time)).collect();
+        assert_eq!(totals, vec![
+            ("Development", 3600),
+            ("Entertainment", 1800),
+            ("Communication", 1200),
+            (UNCATEGORIZED, 600),
+        ]);
+        assert_eq!(stats[3].category_id, None);
+
+        let scores = storage.get_productivity_scores(&day_query()).unwrap();
+        assert_eq!(scores.len(), 2);
+        assert_eq!((scores[0].productive, scores[0].neutral, scores[0].distracting), (3600, 1200, 1800));
+        // (3600 + 1200 / 2) / 6600
+        assert_eq!(scores[0].score, 64);
+        assert_eq!(scores[1].score, 50);
+    }
+}
@@ -0,0 +1,503 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use rusqlite::{Connection, DatabaseName, ErrorCode};
+use super::backup;
+use super::storage::{remove_if_exists, replace_database, with_suffix, StorageError};
+use super::types::{EncryptionStatus, KeySource};
+
+// 未加密的 SQLite 文件以这 16 字节开头，SQLCipher 加密后整个文件都是密文
+const PLAINTEXT_HEADER: &[u8] = b"SQLite format 3\0";
+// 用口令加密的数据库从这个环境变量读取口令
+pub const PASSPHRASE_ENV: &str = "TIME_WHISPER_DB_PASSPHRASE";
+#[cfg(all(feature = "encryption", not(test)))]
+const KEYRING_SERVICE: &str = "com.time-whisper.dev";
+#[cfg(all(feature = "encryption", not(test)))]
+const KEYRING_USER: &str = "database-key";
+
+// 本进程打开加密数据库时使用的密钥，只保存确认可以打开数据库的密钥，修改密钥后随之更新
+static CURRENT_KEY: Mutex<Option<DatabaseKey>> = Mutex::new(None);
+
+#[derive(Clone, PartialEq, Eq)]
+pub enum DatabaseKey {
+    // 64 位十六进制的随机密钥，直接作为 SQLCipher 的原始密钥，保存在系统密钥链中
+    Raw(String),
+    // SQLCipher 用 PBKDF2 从口令派生密钥
+    Passphrase(String),
+}
+
+impl DatabaseKey {
+    pub fn generate() -> Result<Self, StorageError> {
+        let mut bytes = [0u8; 32];
+        getrandom::getrandom(&mut bytes).map_err(io::Error::from)?;
+        Ok(DatabaseKey::Raw(bytes.iter().map(|b| format!("{:02x}", b)).collect()))
+    }
+
+    pub fn passphrase(value: &str) -> Result<Self, StorageError> {
+        if value.is_empty() {
+            return Err(StorageError::Encryption("passphrase must not be empty".to_string()));
+        }
+        Ok(DatabaseKey::Passphrase(value.to_string()))
+    }
+
+    pub fn source(&self) -> KeySource {
+        match self {
+            DatabaseKey::Raw(_) => KeySource::Keyring,
+            DatabaseKey::Passphrase(_) => KeySource::Passphrase,
+        }
+    }
+
+    // PRAGMA key 和 ATTACH ... KEY 接受的写法
+    fn sql_value(&self) -> String {
+        match self {
+            DatabaseKey::Raw(hex) => format!("x'{}'", hex),
+            DatabaseKey::Passphrase(passphrase) => passphrase.clone(),
+        }
+    }
+}
+
+// 密钥不出现在日志中
+impl fmt::Debug for DatabaseKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "DatabaseKey({:?})", self.source())
+    }
+}
+
+pub fn encryption_supported() -> bool {
+    cfg!(feature = "encryption")
+}
+
+fn ensure_supported() -> Result<(), StorageError> {
+    if encryption_supported() {
+        Ok(())
+    } else {
+        Err(StorageError::Encryption("this build does not include database encryption".to_string()))
+    }
+}
+
+pub fn is_encrypted(path: &Path) -> Result<bool, StorageError> {
+    let mut header = Vec::with_capacity(PLAINTEXT_HEADER.len());
+    match File::open(path) {
+        Ok(file) => file.take(PLAINTEXT_HEADER.len() as u64).read_to_end(&mut header)?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e.into()),
+    };
+    // 新建的空文件还没有写入文件头
+    Ok(!header.is_empty() && header != PLAINTEXT_HEADER)
+}
+
+pub fn encryption_status(path: &Path) -> Result<EncryptionStatus, StorageError> {
+    let encrypted = is_encrypted(path)?;
+    let key_source = if encrypted {
+        CURRENT_KEY.lock().unwrap().as_ref().map(DatabaseKey::source)
+    } else {
+        None
+    };
+    Ok(EncryptionStatus { supported: encryption_supported(), encrypted, key_source, locked: encrypted && key_source.is_none() })
+}
+
+// 未加密的数据库返回 None，否则依次使用本进程的密钥、环境变量中的口令和密钥链中的密钥
+pub(super) fn database_key(path: &Path) -> Result<Option<DatabaseKey>, StorageError> {
+    if !is_encrypted(path)? {
+        return Ok(None);
+    }
+    ensure_supported()?;
+    let mut current = CURRENT_KEY.lock().unwrap();
+    if let Some(key) = current.as_ref() {
+        return Ok(Some(key.clone()));
+    }
+    let key = match std::env::var(PASSPHRASE_ENV) {
+        Ok(passphrase) if !passphrase.is_empty() => DatabaseKey::Passphrase(passphrase),
+        _ => load_keyring_key()?.ok_or_else(|| StorageError::Encryption(format!(
+            "the database is locked, unlock it with its passphrase or set {}",
+            PASSPHRASE_ENV
+        )))?,
+    };
+    // 口令错误时保持锁定，之后仍可以用 unlock_database 解锁
+    apply_key(&Connection::open(path)?, &key)?;
+    *current = Some(key.clone());
+    Ok(Some(key))
+}
+
+// 用口令解锁加密的数据库，之后本进程打开这个数据库时使用这个口令
+pub fn unlock_database(path: &Path, passphrase: &str) -> Result<(), StorageError> {
+    ensure_supported()?;
+    if !is_encrypted(path)? {
+        return Err(StorageError::InvalidQuery("the database is not encrypted".to_string()));
+    }
+    let key = DatabaseKey::passphrase(passphrase)?;
+    apply_key(&Connection::open(path)?, &key)?;
+    *CURRENT_KEY.lock().unwrap() = Some(key);
+    tracing::info!("Database unlocked");
+    Ok(())
+}
+
+pub(super) fn apply_key(conn: &Connection, key: &DatabaseKey) -> Result<(), StorageError> {
+    ensure_supported()?;
+    conn.pragma_update(None, "key", key.sql_value())?;
+    // 设置密钥时不会校验，读取一次 schema 才知道密钥是否正确
+    conn.query_row("SELECT count(*) FROM sqlite_master", [], |_| Ok(()))
+        .map_err(|e| match e.sqlite_error_code() {
+            Some(ErrorCode::NotADatabase) => StorageError::Encryption("wrong database key".to_string()),
+            _ => e.into(),
+        })
+}
+
+// 用新密钥重写整个数据库，new_key 为空时解密。调用前需要关闭这个数据库的其他连接
+pub fn change_database_key(path: &Path, new_key: Option<DatabaseKey>) -> Result<(), StorageError> {
+    ensure_supported()?;
+    let old_key = database_key(path)?;
+    if old_key.is_none() && new_key.is_none() {
+        return Err(StorageError::InvalidQuery("the database is not encrypted".to_string()));
+    }
+    let staged = rewrite_database(path, old_key.as_ref(), new_key.as_ref())?;
+    // 已有的备份也先用新密钥重写好，旧密钥丢弃后没有重写的备份就无法再打开
+    let staged_backups = match backup::rekey_backups(path, old_key.as_ref(), new_key.as_ref()) {
+        Ok(staged_backups) => staged_backups,
+        Err(e) => {
+            let _ = fs::remove_file(&staged);
+            return Err(e);
+        }
+    };
+
+    // 先保存新密钥再替换文件。任何一步失败时原来的数据库不变，密钥链换回原来的密钥
+    if let Err(e) = store_key(new_key.as_ref()).and_then(|()| replace_database(&staged, path)) {
+        let _ = fs::remove_file(&staged);
+        backup::discard_rekeyed(&staged_backups);
+        if let Err(restore_error) = store_key(old_key.as_ref()) {
+            tracing::error!("Failed to restore the old database key in the keyring: {}", restore_error);
+        }
+        return Err(e);
+    }
+    for (rekeyed, backup) in &staged_backups {
+        if let Err(e) = replace_database(rekeyed, backup) {
+            tracing::error!("Failed to replace backup {} with its re-keyed copy: {}", backup.display(), e);
+            let _ = fs::remove_file(rekeyed);
+        }
+    }
+
+    match &new_key {
+        Some(key) => tracing::info!("Database encrypted with a {:?} key", key.source()),
+        None => tracing::info!("Database decrypted"),
+    }
+    *CURRENT_KEY.lock().unwrap() = new_key;
+    Ok(())
+}
+
+// 换回更换密钥前的数据库文件时一并换回密钥和密钥链中的密钥。未加密的数据库不使用密钥
+pub(super) fn restore_key(key: Option<DatabaseKey>) -> Result<(), StorageError> {
+    store_key(key.as_ref())?;
+    if key.is_some() {
+        *CURRENT_KEY.lock().unwrap() = key;
+    }
+    Ok(())
+}
+
+// 密钥链中只保存随机密钥，使用口令或未加密时删除
+fn store_key(key: Option<&DatabaseKey>) -> Result<(), StorageError> {
+    if !encryption_supported() {
+        return Ok(());
+    }
+    match key {
+        Some(DatabaseKey::Raw(hex)) => update_keyring(Some(hex)),
+        _ => update_keyring(None),
+    }
+}
+
+// 把数据库导出为用 to 加密的副本，返回副本的路径
+pub(super) fn rewrite_database(path: &Path, from: Option<&DatabaseKey>, to: Option<&DatabaseKey>) -> Result<PathBuf, StorageError> {
+    let staged = with_suffix(path, ".rekey");
+    remove_if_exists(&staged)?;
+    let result = (|| -> Result<(), StorageError> {
+        let conn = Connection::open(path)?;
+        if let Some(key) = from {
+            apply_key(&conn, key)?;
+        }
+        // WAL 中尚未写回的内容也要导出
+        conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+        let version: i32 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        conn.execute(
+            "ATTACH DATABASE ?1 AS staged KEY ?2",
+            (staged.to_string_lossy().into_owned(), to.map(DatabaseKey::sql_value).unwrap_or_default()),
+        )?;
+        conn.query_row("SELECT sqlcipher_export('staged')", [], |_| Ok(()))?;
+        // sqlcipher_export 不复制 user_version
+        conn.pragma_update(Some(DatabaseName::Attached("staged")), "user_version", version)?;
+        conn.execute("DETACH DATABASE staged", [])?;
+        Ok(())
+    })();
+    if let Err(e) = result {
+        let _ = fs::remove_file(&staged);
+        return Err(e);
+    }
+    Ok(staged)
+}
+
+#[cfg(all(feature = "encryption", not(test)))]
+fn keyring_entry() -> Result<keyring::Entry, StorageError> {
+    keyring::Entry::new(KEYRING_SERVICE, KEYRING_USER)
+        .map_err(|e| StorageError::Encryption(format!("keyring unavailable: {}", e)))
+}
+
+#[cfg(all(feature = "encryption", not(test)))]
+fn load_keyring_key() -> Result<Option<DatabaseKey>, StorageError> {
+    match keyring_entry()?.get_password() {
+        Ok(hex) if hex.len() == 64 && hex.bytes().all(|b| b.is_ascii_hexdigit()) => Ok(Some(DatabaseKey::Raw(hex))),
+        Ok(_) => Err(StorageError::Encryption("the database key in the keyring is malformed".to_string())),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => Err(StorageError::Encryption(format!("failed to read the keyring: {}", e))),
+    }
+}
+
+// hex 为空时删除密钥链中的密钥
+#[cfg(all(feature = "encryption", not(test)))]
+fn update_keyring(hex: Option<&str>) -> Result<(), StorageError> {
+    let entry = keyring_entry()?;
+    let result = match hex {
+        Some(hex) => entry.set_password(hex),
+        None => match entry.delete_password() {
+            Err(keyring::Error::NoEntry) => Ok(()),
+            result => result,
+        },
+    };
+    result.map_err(|e| StorageError::Encryption(format!("failed to update the keyring: {}", e)))
+}
+
+// 测试不读写系统密钥链，用内存中的一项代替
+#[cfg(all(feature = "encryption", test))]
+static TEST_KEYRING: Mutex<Option<String>> = Mutex::new(None);
+// 修改 CURRENT_KEY 或密钥链的测试依次执行
+#[cfg(all(feature = "encryption", test))]
+pub(super) static KEY_TEST_LOCK: Mutex<()> = Mutex::new(());
+
+#[cfg(all(feature = "encryption", test))]
+fn load_keyring_key() -> Result<Option<DatabaseKey>, StorageError> {
+    Ok(TEST_KEYRING.lock().unwrap().clone().map(DatabaseKey::Raw))
+}
+
+#[cfg(all(feature = "encryption", test))]
+fn update_keyring(hex: Option<&str>) -> Result<(), StorageError> {
+    *TEST_KEYRING.lock().unwrap() = hex.map(str::to_string);
+    Ok(())
+}
+
+#[cfg(not(feature = "encryption"))]
+fn load_keyring_key() -> Result<Option<DatabaseKey>, StorageError> {
+    Ok(None)
+}
+
+#[cfg(not(feature = "encryption"))]
+fn update_keyring(_hex: Option<&str>) -> Result<(), StorageError> {
+    ensure_supported()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::storage::Storage;
+
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("time-whisper-encryption-{}-{}.db", name, std::process::id()))
+    }
+
+    fn cleanup(path: &Path) {
+        for suffix in ["", "-wal", "-shm", ".rekey"] {
+            let _ = fs::remove_file(with_suffix(path, suffix));
+        }
+    }
+
+    // 1. 按文件头区分加密和未加密的数据库
+    #[test]
+    fn test_is_encrypted() {
+        let path = temp_path("header");
+        assert!(!is_encrypted(&path).unwrap());
+        File::create(&path).unwrap();
+        assert!(!is_encrypted(&path).unwrap());
+
+        drop(Storage::open(&path).unwrap());
+        assert!(!is_encrypted(&path).unwrap());
+        assert_eq!(
+            encryption_status(&path).unwrap(),
+            EncryptionStatus { supported: encryption_supported(), encrypted: false, key_source: None, locked: false }
+        );
+
+        fs::write(&path, [0x5a; 64]).unwrap();
+        assert!(is_encrypted(&path).unwrap());
+        cleanup(&path);
+    }
+
+    // 2. 没有启用 encryption 功能时加密的数据库无法打开
+    #[cfg(not(feature = "encryption"))]
+    #[test]
+    fn test_encryption_unsupported() {
+        let path = temp_path("unsupported");
+        drop(Storage::open(&path).unwrap());
+        assert!(matches!(
+            change_database_key(&path, Some(DatabaseKey::generate().unwrap())),
+            Err(StorageError::Encryption(_))
+        ));
+
+        fs::write(&path, [0x5a; 64]).unwrap();
+        assert!(matches!(Storage::open(&path), Err(StorageError::Encryption(_))));
+        cleanup(&path);
+    }
+
+    // 2. 加密、更换密钥和解密后数据和结构版本不变
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn test_rewrite_database() {
+        use crate::db::storage::SCHEMA_VERSION;
+
+        let path = temp_path("rewrite");
+        let storage = Storage::open(&path).unwrap();
+        storage.set_setting("marker", "kept").unwrap();
+        drop(storage);
+
+        let passphrase = DatabaseKey::passphrase("correct horse").unwrap();
+        let staged = rewrite_database(&path, None, Some(&passphrase)).unwrap();
+        replace_database(&staged, &path).unwrap();
+        assert!(is_encrypted(&path).unwrap());
+        assert!(Storage::open_with_key(&path, None).is_err());
+        assert!(matches!(
+            Storage::open_with_key(&path, Some(&DatabaseKey::passphrase("wrong").unwrap())),
+            Err(StorageError::Encryption(_))
+        ));
+
+        let raw = DatabaseKey::generate().unwrap();
+        let staged = rewrite_database(&path, Some(&passphrase), Some(&raw)).unwrap();
+        replace_database(&staged, &path).unwrap();
+        let storage = Storage::open_with_key(&path, Some(&raw)).unwrap();
+        assert_eq!(storage.get_setting("marker").unwrap().as_deref(), Some("kept"));
+        let version: i32 = storage.conn.pragma_query_value(None, "user_version", |row| row.get(0)).unwrap();
+        assert_eq!(version, SCHEMA_VERSION);
+        drop(storage);
+
+        let staged = rewrite_database(&path, Some(&raw), None).unwrap();
+        replace_database(&staged, &path).unwrap();
+        assert!(!is_encrypted(&path).unwrap());
+        let storage = Storage::open_with_key(&path, None).unwrap();
+        assert_eq!(storage.get_setting("marker").unwrap().as_deref(), Some("kept"));
+        drop(storage);
+        cleanup(&path);
+    }
+
+    // 3. 更换密钥时已有的备份一起更换，加密和解密后都能恢复
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn test_change_key_rekeys_backups() {
+        let _guard = KEY_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let dir = std::env::temp_dir().join(format!("time-whisper-encryption-backups-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("usage_stats.db");
+        Storage::open(&path).unwrap().set_setting("marker", "backed up").unwrap();
+        let backup = backup::create_backup(&path, chrono::Utc::now()).unwrap();
+        let backup_path = backup::backup_dir(&path).join(&backup.name);
+        Storage::open(&path).unwrap().set_setting("marker", "current").unwrap();
+
+        change_database_key(&path, Some(DatabaseKey::passphrase("correct horse").unwrap())).unwrap();
+        assert!(is_encrypted(&backup_path).unwrap());
+        backup::restore_backup(&path, &backup.name).unwrap();
+        assert_eq!(Storage::open(&path).unwrap().get_setting("marker").unwrap().as_deref(), Some("backed up"));
+
+        change_database_key(&path, None).unwrap();
+        assert!(!is_encrypted(&backup_path).unwrap());
+        backup::restore_backup(&path, &backup.name).unwrap();
+        assert_eq!(Storage::open(&path).unwrap().get_setting("marker").unwrap().as_deref(), Some("backed up"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    // 4. 没有可用的密钥时数据库保持锁定，用口令解锁后可以打开
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn test_unlock_database() {
+        let _guard = KEY_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let path = temp_path("unlock");
+        cleanup(&path);
+        Storage::open(&path).unwrap().set_setting("marker", "kept").unwrap();
+        let staged = rewrite_database(&path, None, Some(&DatabaseKey::passphrase("correct horse").unwrap())).unwrap();
+        replace_database(&staged, &path).unwrap();
+        *CURRENT_KEY.lock().unwrap() = None;
+        *TEST_KEYRING.lock().unwrap() = None;
+
+        assert!(encryption_status(&path).unwrap().locked);
+        assert!(matches!(Storage::open(&path), Err(StorageError::Encryption(_))));
+        assert!(matches!(unlock_database(&path, "wrong"), Err(StorageError::Encryption(_))));
+        assert!(encryption_status(&path).unwrap().locked);
+
+        unlock_database(&path, "correct horse").unwrap();
+        let status = encryption_status(&path).unwrap();
+        assert!(!status.locked);
+        assert_eq!(status.key_source, Some(KeySource::Passphrase));
+        assert_eq!(Storage::open(&path).unwrap().get_setting("marker").unwrap().as_deref(), Some("kept"));
+        *CURRENT_KEY.lock().unwrap() = None;
+        cleanup(&path);
+    }
+
+    // 5. 替换文件失败时密钥链中仍是能打开数据库的密钥，并且不留下副本
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn test_change_key_replace_failure() {
+        let _guard = KEY_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let path = temp_path("replace-failure");
+        cleanup(&path);
+        Storage::open(&path).unwrap().set_setting("marker", "kept").unwrap();
+        let old = DatabaseKey::generate().unwrap();
+        let staged = rewrite_database(&path, None, Some(&old)).unwrap();
+        replace_database(&staged, &path).unwrap();
+        store_key(Some(&old)).unwrap();
+        *CURRENT_KEY.lock().unwrap() = Some(old.clone());
+
+        // -shm 是目录时替换前无法删除它
+        let shm = with_suffix(&path, "-shm");
+        fs::create_dir(&shm).unwrap();
+        let result = change_database_key(&path, Some(DatabaseKey::generate().unwrap()));
+        fs::remove_dir(&shm).unwrap();
+        assert!(matches!(result, Err(StorageError::Io(_))));
+        assert!(!with_suffix(&path, ".rekey").exists());
+        let stored = load_keyring_key().unwrap().unwrap();
+        assert_eq!(stored, old);
+        let storage = Storage::open_with_key(&path, Some(&stored)).unwrap();
+        assert_eq!(storage.get_setting("marker").unwrap().as_deref(), Some("kept"));
+        drop(storage);
+
+        // 换回原来的文件时即使 CURRENT_KEY 没有变化也写回密钥链
+        store_key(Some(&DatabaseKey::generate().unwrap())).unwrap();
+        restore_key(Some(old.clone())).unwrap();
+        assert_eq!(load_keyring_key().unwrap(), Some(old));
+
+        *CURRENT_KEY.lock().unwrap() = None;
+        store_key(None).unwrap();
+        cleanup(&path);
+    }
+
+    // 6. 有备份无法用新密钥重写时不更换密钥，数据库和其他备份保持原样
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn test_change_key_fails_on_unreadable_backup() {
+        let _guard = KEY_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let dir = std::env::temp_dir().join(format!("time-whisper-encryption-bad-backup-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("usage_stats.db");
+        drop(Storage::open(&path).unwrap());
+        let good = backup::create_backup(&path, "2026-05-01T09:00:00Z".parse().unwrap()).unwrap();
+        let bad = backup::create_backup(&path, "2026-05-02T09:00:00Z".parse().unwrap()).unwrap();
+        fs::write(backup::backup_dir(&path).join(&bad.name), [0x5a; 4096]).unwrap();
+
+        let result = change_database_key(&path, Some(DatabaseKey::passphrase("correct horse").unwrap()));
+        assert!(matches!(result, Err(StorageError::Encryption(message)) if message.contains(&bad.name)));
+        assert!(!is_encrypted(&path).unwrap());
+        assert!(!is_encrypted(&backup::backup_dir(&path).join(&good.name)).unwrap());
+        let leftovers: Vec<_> = fs::read_dir(backup::backup_dir(&path)).unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name.ends_with(".rekey"))
+            .collect();
+        assert!(leftovers.is_empty());
+        assert!(!with_suffix(&path, ".rekey").exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,398 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+use arrow_array::{
+    ArrayRef, Date32Array, Int32Array, RecordBatch, StringArray, TimestampSecondArray, UInt32Array, UInt64Array,
+};
+use arrow_schema::{ArrowError, DataType, Field, Schema, SchemaRef, TimeUnit};
+use chrono::NaiveDate;
+use parquet::arrow::ArrowWriter;
+use parquet::errors::ParquetError;
+use serde::{Deserialize, Serialize};
+use super::storage::{Storage, StorageError};
+use super::types::{AppUsageStats, SessionRecord, UsageQuery};
+
+// Parquet 每攒够这么多行写一个 row group
+const PARQUET_BATCH_ROWS: usize = 8192;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Csv,
+    Jsonl,
+    Parquet,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportKind {
+    // 原始会话记录
+    Records,
+    // 按查询的 granularity 聚合后的统计
+    Stats,
+}
+
+#[derive(Debug)]
+pub enum ExportError {
+    Storage(StorageError),
+    Io(io::Error),
+    Csv(csv::Error),
+    Json(serde_json::Error),
+    Arrow(ArrowError),
+    Parquet(ParquetError),
+}
+
+impl From<StorageError> for ExportError {
+    fn from(err: StorageError) -> Self {
+        ExportError::Storage(err)
+    }
+}
+
+impl From<io::Error> for ExportError {
+    fn from(err: io::Error) -> Self {
+        ExportError::Io(err)
+    }
+}
+
+impl From<csv::Error> for ExportError {
+    fn from(err: csv::Error) -> Self {
+        ExportError::Csv(err)
+    }
+}
+
+impl From<serde_json::Error> for ExportError {
+    fn from(err: serde_json::Error) -> Self {
+        ExportError::Json(err)
+    }
+}
+
+impl From<ArrowError> for ExportError {
+    fn from(err: ArrowError) -> Self {
+        ExportError::Arrow(err)
+    }
+}
+
+impl From<ParquetError> for ExportError {
+    fn from(err: ParquetError) -> Self {
+        ExportError::Parquet(err)
+    }
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExportError::Storage(err) => write!(f, "{}", err),
+            ExportError::Io(err) => write!(f, "IO Error: {}", err),
+            ExportError::Csv(err) => write!(f, "CSV Error: {}", err),
+            ExportError::Json(err) => write!(f, "JSON Error: {}", err),
+            ExportError::Arrow(err) => write!(f, "Arrow Error: {}", err),
+            ExportError::Parquet(err) => write!(f, "Parquet Error: {}", err),
+        }
+    }
+}
+
+// 统计数据展开后的一行：某个应用在某个时间段（日/周/月的第一天）的使用时长
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct StatsRow {
+    pub app_name: String,
+    pub date: NaiveDate,
+    pub duration: u64,
+}
+
+pub fn stats_rows(stats: &[AppUsageStats]) -> Vec<StatsRow> {
+    stats.iter()
+        .flat_map(|stat| stat.daily_usage.iter().map(move |daily| StatsRow {
+            app_name: stat.name.clone(),
+            date: daily.date,
+            duration: daily.duration,
+        }))
+        .collect()
+}
+
+// 能写入 Parquet 的行类型
+trait ExportRow: Serialize + Sized {
+    fn schema() -> SchemaRef;
+    fn to_batch(rows: &[Self]) -> Result<RecordBatch, ArrowError>;
+}
+
+impl ExportRow for SessionRecord {
+    fn schema() -> SchemaRef {
+        let timestamp = DataType::Timestamp(TimeUnit::Second, Some("UTC".into()));
+        Arc::new(Schema::new(vec![
+            Field::new("app_name", DataType::Utf8, false),
+            Field::new("state", DataType::Utf8, false),
+            Field::new("start_time", timestamp.clone(), false),
+            Field::new("end_time", timestamp, false),
+            Field::new("duration", DataType::UInt64, false),
+            Field::new("window_title", DataType::Utf8, true),
+            Field::new("window_class", DataType::Utf8, true),
+            Field::new("exe_path", DataType::Utf8, true),
+            Field::new("pid", DataType::UInt32, true),
+            Field::new("utc_offset", DataType::Int32, true),
+        ]))
+    }
+
+    fn to_batch(rows: &[Self]) -> Result<RecordBatch, ArrowError> {
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(StringArray::from_iter_values(rows.iter().map(|r| r.app_name.as_str()))),
+            Arc::new(StringArray::from_iter_values(rows.iter().map(|r| r.state.as_str()))),
+            Arc::new(TimestampSecondArray::from_iter_values(rows.iter().map(|r| r.start_time.timestamp())).with_timezone("UTC")),
+            Arc::new(TimestampSecondArray::from_iter_values(rows.iter().map(|r| r.end_time.timestamp())).with_timezone("UTC")),
+            Arc::new(UInt64Array::from_iter_values(rows.iter().map(|r| r.duration))),
+            Arc::new(StringArray::from_iter(rows.iter().map(|r| r.window_title.as_deref()))),
+            Arc::new(StringArray::from_iter(rows.iter().map(|r| r.window_class.as_deref()))),
+            Arc::new(StringArray::from_iter(rows.iter().map(|r| r.exe_path.as_deref()))),
+            Arc::new(UInt32Array::from_iter(rows.iter().map(|r| r.pid))),
+            Arc::new(Int32Array::from_iter(rows.iter().map(|r| r.utc_offset))),
+        ];
+        RecordBatch::try_new(Self::schema(), columns)
+    }
+}
+
+impl ExportRow for StatsRow {
+    fn schema() -> SchemaRef {
+        Arc::new(Schema::new(vec![
+            Field::new("app_name", DataType::Utf8, false),
+            Field::new("date", DataType::Date32, false),
+            Field::new("duration", DataType::UInt64, false),
+        ]))
+    }
+
+    fn to_batch(rows: &[Self]) -> Result<RecordBatch, ArrowError> {
+        let epoch = NaiveDate::from_ymd_opt(1970, 1, 1).unwrap();
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(StringArray::from_iter_values(rows.iter().map(|r| r.app_name.as_str()))),
+            Arc::new(Date32Array::from_iter_values(rows.iter().map(|r| (r.date - epoch).num_days() as i32))),
+            Arc::new(UInt64Array::from_iter_values(rows.iter().map(|r| r.duration))),
+        ];
+        RecordBatch::try_new(Self::schema(), columns)
+    }
+}
+
+// 按格式逐行写出，Parquet 需要先攒成批
+enum RowSink<W: Write + Send, T: ExportRow> {
+    Csv(csv::Writer<W>),
+    Jsonl(W),
+    Parquet { writer: ArrowWriter<W>, buffer: Vec<T> },
+}
+
+impl<W: Write + Send, T: ExportRow> RowSink<W, T> {
+    fn new(format: ExportFormat, writer: W) -> Result<Self, ExportError> {
+        Ok(match format {
+            ExportFormat::Csv => RowSink::Csv(csv::Writer::from_writer(writer)),
+            ExportFormat::Jsonl => RowSink::Jsonl(writer),
+            ExportFormat::Parquet => RowSink::Parquet {
+                writer: ArrowWriter::try_new(writer, T::schema(), None)?,
+                buffer: Vec::with_capacity(PARQUET_BATCH_ROWS),
+            },
+        })
+    }
+
+    fn push(&mut self, row: T) -> Result<(), ExportError> {
+        match self {
+            RowSink::Csv(writer) => writer.serialize(&row)?,
+            RowSink::Jsonl(writer) => {
+                serde_json::to_writer(&mut *writer, &row)?;
+                writer.write_all(b"\n")?;
+            }
+            RowSink::Parquet { writer, buffer } => {
+                buffer.push(row);
+                if buffer.len() >= PARQUET_BATCH_ROWS {
+                    writer.write(&T::to_batch(buffer)?)?;
+                    buffer.clear();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<(), ExportError> {
+        match self {
+            RowSink::Csv(mut writer) => writer.flush()?,
+            RowSink::Jsonl(mut writer) => writer.flush()?,
+            RowSink::Parquet { mut writer, buffer } => {
+                if !buffer.is_empty() {
+                    writer.write(&T::to_batch(&buffer)?)?;
+                }
+                writer.close()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// 导出与查询区间有重叠的原始会话（不裁剪到区间边界），返回写出的行数
+pub fn export_records<W: Write + Send>(
+    storage: &Storage,
+    query: &UsageQuery,
+    format: ExportFormat,
+    writer: W,
+) -> Result<usize, ExportError> {
+    let mut sink = RowSink::<W, SessionRecord>::new(format, writer)?;
+    let mut count = 0;
+    storage.visit_sessions(query, |record| {
+        count += 1;
+        sink.push(record)
+    })?;
+    sink.finish()?;
+    Ok(count)
+}
+
+// 导出聚合后的统计，每个应用每个时间段一行，返回写出的行数
+pub fn export_stats<W: Write + Send>(
+    storage: &Storage,
+    query: &UsageQuery,
+    format: ExportFormat,
+    writer: W,
+) -> Result<usize, ExportError> {
+    let rows = stats_rows(&storage.get_usage_stats_range(query)?);
+    let count = rows.len();
+    let mut sink = RowSink::<W, StatsRow>::new(format, writer)?;
+    for row in rows {
+        sink.push(row)?;
+    }
+    sink.finish()?;
+    Ok(count)
+}
+
+pub fn export_to_path(
+    storage: &Storage,
+    query: &UsageQuery,
+    kind: ExportKind,
+    format: ExportFormat,
+    path: &Path,
+) -> Result<usize, ExportError> {
+    let mut file = BufWriter::new(File::create(path)?);
+    let result = match kind {
+        ExportKind::Records => export_records(storage, query, format, &mut file),
+        ExportKind::Stats => export_stats(storage, query, format, &mut file),
+    }
+    .and_then(|count| {
+        file.flush()?;
+        Ok(count)
+    });
+
+    // 失败时不留下写了一半的文件
+    if result.is_err() {
+        drop(file);
+        if let Err(e) = fs::remove_file(path) {
+            tracing::warn!("Failed to remove incomplete export {}: {}", path.display(), e);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::types::{AppUsageRecord, Granularity, UsageState};
+    use chrono::{DateTime, Utc};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    fn utc(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(value).unwrap().with_timezone(&Utc)
+    }
+
+    fn seeded_storage() -> Storage {
+        let storage = Storage::open_in_memory().unwrap();
+        let sessions = [
+            ("2026-03-01T09:00:00Z", "editor", 3600, UsageState::Active, Some("main.rs")),
+            ("2026-03-01T10:00:00Z", "browser", 600, UsageState::Idle, None),
+            ("2026-03-02T09:00:00Z", "editor", 1200, UsageState::Active, Some("lib.rs")),
+        ];
+        for (start, app_name, duration, state, title) in sessions {
+            storage.record_usage(AppUsageRecord {
+                timestamp: utc(start),
+                app_name: app_name.to_string(),
+                duration,
+                state,
+                window_title: title.map(String::from),
+                window_class: None,
+                exe_path: None,
+                pid: Some(42),
+                utc_offset: Some(0),
+            }).unwrap();
+        }
+        storage
+    }
+
+    fn query() -> UsageQuery {
+        UsageQuery {
+            from: utc("2026-03-01T00:00:00Z"),
+            to: utc("2026-03-03T00:00:00Z"),
+            granularity: Granularity::Day,
+            apps: Vec::new(),
+            limit: None,
+            time_zone: Some("UTC".to_string()),
+        }
+    }
+
+    // 1. CSV 导出原始会话
+    #[test]
+    fn test_export_records_csv() {
+        let storage = seeded_storage();
+        let mut output = Vec::new();
+        let count = export_records(&storage, &query(), ExportFormat::Csv, &mut output).unwrap();
+        assert_eq!(count, 3);
+
+        let text = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "app_name,state,start_time,end_time,duration,window_title,window_class,exe_path,pid,utc_offset");
+        assert_eq!(lines[1], "editor,active,2026-03-01T09:00:00Z,2026-03-01T10:00:00Z,3600,main.rs,,,42,0");
+        assert_eq!(lines[2], "browser,idle,2026-03-01T10:00:00Z,2026-03-01T10:10:00Z,600,,,,42,0");
+        assert_eq!(lines.len(), 4);
+    }
+
+    // 2. JSON Lines 导出统计，可以逐行读回
+    #[test]
+    fn test_export_stats_jsonl() {
+        let storage = seeded_storage();
+        let mut output = Vec::new();
+        let count = export_stats(&storage, &query(), ExportFormat::Jsonl, &mut output).unwrap();
+        assert_eq!(count, 2);
+
+        let rows: Vec<StatsRow> = String::from_utf8(output).unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(rows, vec![
+            StatsRow { app_name: "editor".into(), date: NaiveDate::from_ymd_opt(2026, 3, 1).unwrap(), duration: 3600 },
+            StatsRow { app_name: "editor".into(), date: NaiveDate::from_ymd_opt(2026, 3, 2).unwrap(), duration: 1200 },
+        ]);
+    }
+
+    // 3. Parquet 导出到文件后能被读回
+    #[test]
+    fn test_export_parquet_to_path() {
+        let storage = seeded_storage();
+        let path = std::env::temp_dir().join(format!("time-whisper-export-{}.parquet", std::process::id()));
+        let count = export_to_path(&storage, &query(), ExportKind::Records, ExportFormat::Parquet, &path).unwrap();
+        assert_eq!(count, 3);
+
+        let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(&path).unwrap())
+            .unwrap()
+            .build()
+            .unwrap();
+        let batches: Vec<RecordBatch> = reader.map(|batch| batch.unwrap()).collect();
+        assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 3);
+        assert_eq!(batches[0].schema(), SessionRecord::schema());
+        let apps = batches[0].column(0).as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!(apps.value(2), "editor");
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    // 4. 查询无效时不留下空文件
+    #[test]
+    fn test_failed_export_removes_file() {
+        let storage = seeded_storage();
+        let path = std::env::temp_dir().join(format!("time-whisper-export-{}.csv", std::process::id()));
+        let mut invalid = query();
+        invalid.limit = Some(0);
+        let result = export_to_path(&storage, &invalid, ExportKind::Stats, ExportFormat::Csv, &path);
+        assert!(matches!(result, Err(ExportError::Storage(StorageError::InvalidQuery(_)))));
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,443 @@
+use std::collections::HashMap;
+use chrono::{DateTime, Duration, Utc};
+use rusqlite::OptionalExtension;
+use super::storage::{format_timestamp, parse_timestamp, validate_query, Storage, StorageError};
+use super::types::{DistractingApp, FocusHistory, FocusInterruption, FocusSession, FocusStatus, UsageQuery};
+
+const DEFAULT_TOP_DISTRACTIONS: usize = 5;
+
+struct ActiveFocus {
+    id: i64,
+    start: DateTime<Utc>,
+    target_secs: u64,
+    current_interruption: Option<i64>,
+}
+
+impl Storage {
+    pub fn start_focus_session(
+        &self,
+        target_duration: u64,
+        allowed_apps: &[String],
+        now: DateTime<Utc>,
+    ) -> Result<FocusSession, StorageError> {
+        if target_duration == 0 {
+            return Err(StorageError::InvalidQuery("focus duration must be positive".to_string()));
+        }
+        self.finish_expired_focus_session(now)?;
+        if self.active_focus()?.is_some() {
+            return Err(StorageError::InvalidQuery("a focus session is already running".to_string()));
+        }
+
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute(
+            "INSERT INTO focus_sessions (start_time, target_secs) VALUES (?1, ?2)",
+            (format_timestamp(now), target_duration as i64),
+        )?;
+        let id = tx.last_insert_rowid();
+        for app_name in allowed_apps.iter().map(|app| app.trim()).filter(|app| !app.is_empty()) {
+            tx.execute(
+                "INSERT OR IGNORE INTO focus_allowed_apps (session_id, app_name) VALUES (?1, ?2)",
+                (id, app_name),
+            )?;
+        }
+        tx.commit()?;
+        tracing::info!("Started focus session {} for {} seconds", id, target_duration);
+        self.load_focus_session(id)
+    }
+
+    // 手动结束专注，未到目标时长的记为取消
+    pub fn stop_focus_session(&self, now: DateTime<Utc>) -> Result<FocusSession, StorageError> {
+        if let Some(session) = self.finish_expired_focus_session(now)? {
+            return Ok(session);
+        }
+        let active = self.active_focus()?
+            .ok_or_else(|| StorageError::NotFound("active focus session".to_string()))?;
+        self.close_focus_session(active.id, now.max(active.start), FocusStatus::Cancelled)?;
+        self.load_focus_session(active.id)
+    }
+
+    pub fn get_active_focus_session(&self, now: DateTime<Utc>) -> Result<Option<FocusSession>, StorageError> {
+        self.finish_expired_focus_session(now)?;
+        match self.active_focus()? {
+            Some(active) => self.load_focus_session(active.id).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    // 到达目标时长的专注标记为完成，结束时间记为目标时间点，应用关闭期间到期的也一样
+    pub fn finish_expired_focus_session(&self, now: DateTime<Utc>) -> Result<Option<FocusSession>, StorageError> {
+        let active = match self.active_focus()? {
+            Some(active) => active,
+            None => return Ok(None),
+        };
+        let end = active.start + Duration::seconds(active.target_secs as i64);
+        if now < end {
+            return Ok(None);
+        }
+        self.close_focus_session(active.id, end, FocusStatus::Completed)?;
+        tracing::info!("Focus session {} completed", active.id);
+        self.load_focus_session(active.id).map(Some)
+    }
+
+    // 监控每个活跃时刻调用一次：允许的应用计入专注时长，其他应用计为打断
+    // 返回这次调用中完成的专注会话
+    pub fn record_focus_tick(
+        &self,
+        app_name: &str,
+        timestamp: DateTime<Utc>,
+        duration: u64,
+    ) -> Result<Option<FocusSession>, StorageError> {
+        if let Some(session) = self.finish_expired_focus_session(timestamp)? {
+            return Ok(Some(session));
+        }
+        let active = match self.active_focus()? {
+            Some(active) => active,
+            None => return Ok(None),
+        };
+        // 只计入与 [专注开始, 目标时间点) 重叠的部分
+        let span_end = timestamp + Duration::seconds(duration as i64);
+        let start = timestamp.max(active.start);
+        let end = span_end.min(active.start + Duration::seconds(active.target_secs as i64));
+        if end <= start {
+            return Ok(None);
+        }
+        let counted = (end - start).num_seconds();
+
+        let tx = self.conn.unchecked_transaction()?;
+        if self.is_focus_app_allowed(active.id, app_name)? {
+            tx.execute(
+                "UPDATE focus_sessions SET focused_secs = focused_secs + ?1, current_interruption = NULL WHERE id = ?2",
+                (counted, active.id),
+            )?;
+        } else {
+            let end = format_timestamp(end);
+            // 仍停留在同一个应用时延长当前的打断
+            let current = match active.current_interruption {
+                Some(id) => tx.query_row(
+                    "SELECT app_name FROM focus_interruptions WHERE id = ?1",
+                    [id],
+                    |row| row.get::<_, String>(0),
+                ).optional()?.filter(|name| name == app_name).map(|_| id),
+                None => None,
+            };
+            let interruption = match current {
+                Some(id) => {
+                    tx.execute("UPDATE focus_interruptions SET end_time = ?1 WHERE id = ?2", (&end, id))?;
+                    id
+                }
+                None => {
+                    tx.execute(
+                        "INSERT INTO focus_interruptions (session_id, app_name, start_time, end_time) VALUES (?1, ?2, ?3, ?4)",
+                        (active.id, app_name, format_timestamp(start), &end),
+                    )?;
+                    tracing::info!("Focus session {} interrupted by {}", active.id, app_name);
+                    tx.last_insert_rowid()
+                }
+            };
+            tx.execute(
+                "UPDATE focus_sessions SET interrupted_secs = interrupted_secs + ?1, current_interruption = ?2 WHERE id = ?3",
+                (counted, interruption, active.id),
+            )?;
+        }
+        tx.commit()?;
+        // 跨过目标时间点的时间段结束时专注已经完成
+        self.finish_expired_focus_session(span_end)
+    }
+
+    // 查询区间内开始的专注会话，以及打断最多的应用
+    pub fn get_focus_history(&self, query: &UsageQuery) -> Result<FocusHistory, StorageError> {
+        validate_query(query)?;
+        let ids = {
+            let mut stmt = self.conn.prepare(
+                "SELECT id FROM focus_sessions WHERE start_time >= ?1 AND start_time < ?2 ORDER BY start_time, id"
+            )?;
+            let rows = stmt.query_map([format_timestamp(query.from), format_timestamp(query.to)], |row| row.get::<_, i64>(0))?;
+            rows.collect::<Result<Vec<_>, _>>()?
+        };
+
+        let mut history = FocusHistory {
+            sessions: Vec::with_capacity(ids.len()),
+            completed: 0,
+            cancelled: 0,
+            focused_time: 0,
+            interrupted_time: 0,
+            top_distractions: Vec::new(),
+        };
+        let mut distractions: HashMap<String, DistractingApp> = HashMap::new();
+        for id in ids {
+            let session = self.load_focus_session(id)?;
+            match session.status {
+                FocusStatus::Completed => history.completed += 1,
+                FocusStatus::Cancelled => history.cancelled += 1,
+                FocusStatus::Active => {}
+            }
+            history.focused_time += session.focused_time;
+            history.interrupted_time += session.interrupted_time;
+            for interruption in &session.interruptions {
+                let app = distractions.entry(interruption.app_name.clone())
+                    .or_insert_with(|| DistractingApp {
+                        name: interruption.app_name.clone(),
+                        total_time: 0,
+                        interruptions: 0,
+                    });
+                app.total_time += interruption.duration;
+                app.interruptions += 1;
+            }
+            history.sessions.push(session);
+        }
+
+        let mut top_distractions: Vec<DistractingApp> = distractions.into_values().collect();
+        top_distractions.sort_by(|a, b| b.total_time.cmp(&a.total_time).then_with(|| a.name.cmp(&b.name)));
+        top_distractions.truncate(query.limit.unwrap_or(DEFAULT_TOP_DISTRACTIONS));
+        history.top_distractions = top_distractions;
+        Ok(history)
+    }
+
+    fn active_focus(&self) -> Result<Option<ActiveFocus>, StorageError> {
+        let row = self.conn.query_row(
+            "SELECT id, start_time, target_secs, current_interruption FROM focus_sessions
+             WHERE status = 'active' ORDER BY id DESC LIMIT 1",
+            [],
+            |row| Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, i64>(2)?,
+                row.get::<_, Option<i64>>(3)?,
+            )),
+        ).optional()?;
+        let (id, start_time, target_secs, current_interruption) = match row {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+        match parse_timestamp(&start_time) {
+            Some(start) => Ok(Some(ActiveFocus { id, start, target_secs: target_secs as u64, current_interruption })),
+            None => {
+                // 无法解析的会话直接取消，避免挡住新的专注
+                tracing::warn!("Cancelling focus session {} with invalid start time: {}", id, start_time);
+                self.conn.execute("UPDATE focus_sessions SET status = 'cancelled' WHERE id = ?1", [id])?;
+                Ok(None)
+            }
+        }
+    }
+
+    fn close_focus_session(&self, id: i64, end: DateTime<Utc>, status: FocusStatus) -> Result<(), StorageError> {
+        self.conn.execute(
+            "UPDATE focus_sessions SET status = ?1, end_time = ?2, current_interruption = NULL WHERE id = ?3",
+            (status.as_str(), format_timestamp(end), id),
+        )?;
+        Ok(())
+    }
+
+    // 允许列表为空时不限制应用，应用名不区分大小写
+    fn is_focus_app_allowed(&self, session_id: i64, app_name: &str) -> Result<bool, StorageError> {
+        let (total, matched): (i64, Option<i64>) = self.conn.query_row(
+            "SELECT COUNT(*), SUM(app_name = ?2 COLLATE NOCASE) FROM focus_allowed_apps WHERE session_id = ?1",
+            (session_id, app_name),
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        Ok(total == 0 || matched.unwrap_or(0) > 0)
+    }
+
+    fn load_focus_session(&self, id: i64) -> Result<FocusSession, StorageError> {
+        let (start_time, end_time, target_secs, status, focused_secs, interrupted_secs) = self.conn.query_row(
+            "SELECT start_time, end_time, target_secs, status, focused_secs, interrupted_secs FROM focus_sessions WHERE id = ?1",
+            [id],
+            |row| Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, Option<String>>(1)?,
+                row.get::<_, i64>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, i64>(4)?,
+                row.get::<_, i64>(5)?,
+            )),
+        ).optional()?
+            .ok_or_else(|| StorageError::NotFound(format!("focus session {}", id)))?;
+
+        let allowed_apps = {
+            let mut stmt = self.conn.prepare("SELECT app_name FROM focus_allowed_apps WHERE session_id = ?1 ORDER BY app_name")?;
+            let rows = stmt.query_map([id], |row| row.get::<_, String>(0))?;
+            rows.collect::<Result<Vec<_>, _>>()?
+        };
+
+        let mut interruptions = Vec::new();
+        let mut stmt = self.conn.prepare(
+            "SELECT app_name, start_time, end_time FROM focus_interruptions WHERE session_id = ?1 ORDER BY id"
+        )?;
+        let rows = stmt.query_map([id], |row| Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, String>(2)?,
+        )))?;
+        for row in rows {
+            let (app_name, start, end) = row?;
+            match (parse_timestamp(&start), parse_timestamp(&end)) {
+                (Some(start_time), Some(end_time)) => interruptions.push(FocusInterruption {
+                    app_name,
+                    start_time,
+                    end_time,
+                    duration: (end_time - start_time).num_seconds().max(0) as u64,
+                }),
+                _ => tracing::warn!("Skipping invalid focus interruption: {} {} - {}", app_name, start, end),
+            }
+        }
+
+        let start_time = parse_timestamp(&start_time)
+            .ok_or_else(|| StorageError::InvalidQuery(format!("invalid start time for focus session {}", id)))?;
+        Ok(FocusSession {
+            id,
+            start_time,
+            end_time: end_time.as_deref().and_then(parse_timestamp),
+            target_duration: target_secs as u64,
+            allowed_apps,
+            status: FocusStatus::parse(&status).unwrap_or(FocusStatus::Cancelled),
+            focused_time: focused_secs as u64,
+            interrupted_time: interrupted_secs as u64,
+            interruptions,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::types::Granularity;
+
+    fn utc(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(value).unwrap().with_timezone(&Utc)
+    }
+
+    fn apps(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    // 从 start 开始每秒一个时刻，依次在给定应用中停留若干秒
+    fn ticks(storage: &Storage, start: DateTime<Utc>, plan: &[(&str, i64)]) -> Vec<FocusSession> {
+        let mut finished = Vec::new();
+        let mut offset = 0;
+        for (app_name, seconds) in plan {
+            for _ in 0..*seconds {
+                if let Some(session) = storage.record_focus_tick(app_name, start + Duration::seconds(offset), 1).unwrap() {
+                    finished.push(session);
+                }
+                offset += 1;
+            }
+        }
+        finished
+    }
+
+    // 1. 允许的应用计入专注时长，连续停留在同一个其他应用只算一次打断
+    #[test]
+    fn test_focus_ticks_and_interruptions() {
+        let storage = Storage::open_in_memory().unwrap();
+        let start = utc("2026-03-02T09:00:00Z");
+        let session = storage.start_focus_session(1500, &apps(&["Code", " ", "code"]), start).unwrap();
+        assert_eq!(session.allowed_apps, vec!["Code".to_string(), "code".to_string()]);
+        assert_eq!(session.status, FocusStatus::Active);
+
+        ticks(&storage, start, &[("code", 10), ("slack", 3), ("firefox", 2), ("code", 5), ("slack", 4)]);
+        let session = storage.get_active_focus_session(start + Duration::seconds(24)).unwrap().unwrap();
+        assert_eq!((session.focused_time, session.interrupted_time), (15, 9));
+        let interruptions: Vec<(&str, u64)> = session.interruptions.iter()
+            .map(|i| (i.app_name.as_str(), i.duration))
+            .collect();
+        assert_eq!(interruptions, vec![("slack", 3), ("firefox", 2), ("slack", 4)]);
+        assert_eq!(session.interruptions[2].start_time, start + Duration::seconds(20));
+    }
+
+    // 2. 到达目标时长自动完成，同时只能有一个进行中的专注
+    #[test]
+    fn test_focus_completion() {
+        let storage = Storage::open_in_memory().unwrap();
+        let start = utc("2026-03-02T09:00:00Z");
+        storage.start_focus_session(10, &[], start).unwrap();
+        assert!(matches!(storage.start_focus_session(10, &[], start), Err(StorageError::InvalidQuery(_))));
+
+        // 允许列表为空时所有应用都算专注
+        let finished = ticks(&storage, start, &[("slack", 12)]);
+        assert_eq!(finished.len(), 1);
+        assert_eq!(finished[0].status, FocusStatus::Completed);
+        assert_eq!(finished[0].end_time, Some(start + Duration::seconds(10)));
+        assert_eq!(finished[0].focused_time, 10);
+        assert!(storage.get_active_focus_session(start + Duration::seconds(12)).unwrap().is_none());
+
+        // 应用关闭期间到期的专注在下次查询时完成
+        let later = start + Duration::hours(1);
+        storage.start_focus_session(60, &[], later).unwrap();
+        assert!(storage.get_active_focus_session(later + Duration::hours(1)).unwrap().is_none());
+        let session = storage.start_focus_session(60, &[], later + Duration::hours(1)).unwrap();
+        assert_eq!(session.id, 3);
+    }
+
+    // 3. 提前结束的专注记为取消
+    #[test]
+    fn test_stop_focus_session() {
+        let storage = Storage::open_in_memory().unwrap();
+        let start = utc("2026-03-02T09:00:00Z");
+        assert!(matches!(storage.stop_focus_session(start), Err(StorageError::NotFound(_))));
+        assert!(storage.start_focus_session(0, &[], start).is_err());
+
+        storage.start_focus_session(1500, &apps(&["code"]), start).unwrap();
+        ticks(&storage, start, &[("code", 5), ("steam", 5)]);
+        let session = storage.stop_focus_session(start + Duration::seconds(10)).unwrap();
+        assert_eq!(session.status, FocusStatus::Cancelled);
+        assert_eq!(session.end_time, Some(start + Duration::seconds(10)));
+        // 结束后的时刻不再计入
+        ticks(&storage, start + Duration::seconds(10), &[("steam", 5)]);
+        assert!(storage.get_active_focus_session(start + Duration::seconds(15)).unwrap().is_none());
+    }
+
+    // 4. 历史中按打断时长排列分心最多的应用
+    #[test]
+    fn test_focus_history() {
+        let storage = Storage::open_in_memory().unwrap();
+        let first = utc("2026-03-02T09:00:00Z");
+        storage.start_focus_session(30, &apps(&["code"]), first).unwrap();
+        ticks(&storage, first, &[("code", 10), ("slack", 5), ("code", 5), ("steam", 8), ("code", 3)]);
+
+        let second = utc("2026-03-02T10:00:00Z");
+        storage.start_focus_session(1500, &apps(&["code"]), second).unwrap();
+        ticks(&storage, second, &[("slack", 6), ("code", 2), ("slack", 1)]);
+        storage.stop_focus_session(second + Duration::seconds(9)).unwrap();
+
+        let query = UsageQuery {
+            from: utc("2026-03-02T00:00:00Z"),
+            to: utc("2026-03-03T00:00:00Z"),
+            granularity: Granularity::Day,
+            apps: Vec::new(),
+            limit: None,
+            time_zone: None,
+        };
+        let history = storage.get_focus_history(&query).unwrap();
+        assert_eq!(history.sessions.len(), 2);
+        assert_eq!((history.completed, history.cancelled), (1, 1));
+        assert_eq!((history.focused_time, history.interrupted_time), (19, 20));
+        assert_eq!(history.top_distractions, vec![
+            DistractingApp { name: "slack".to_string(), total_time: 12, interruptions: 3 },
+            DistractingApp { name: "steam".to_string(), total_time: 8, interruptions: 1 },
+        ]);
+
+        let history = storage.get_focus_history(&UsageQuery { from: second + Duration::seconds(1), ..query }).unwrap();
+        assert!(history.sessions.is_empty());
+    }
+
+    // 5. 跨过专注开始或目标时间点的时间段只计入重叠的部分
+    #[test]
+    fn test_focus_tick_clipped_to_session() {
+        let storage = Storage::open_in_memory().unwrap();
+        let start = utc("2026-03-02T09:00:00Z");
+        storage.start_focus_session(60, &apps(&["code"]), start).unwrap();
+
+        // 开始前 20 秒就在使用的应用只计入开始后的 10 秒
+        assert!(storage.record_focus_tick("code", start - Duration::seconds(20), 30).unwrap().is_none());
+        storage.record_focus_tick("slack", start + Duration::seconds(10), 20).unwrap();
+        let session = storage.get_active_focus_session(start + Duration::seconds(30)).unwrap().unwrap();
+        assert_eq!((session.focused_time, session.interrupted_time), (10, 20));
+
+        // 超过目标时间点的部分不计入，时间段结束时专注完成
+        let finished = storage.record_focus_tick("slack", start + Duration::seconds(30), 120).unwrap().unwrap();
+        assert_eq!(finished.status, FocusStatus::Completed);
+        assert_eq!((finished.focused_time, finished.interrupted_time), (10, 50));
+        assert_eq!(finished.interruptions.len(), 1);
+        assert_eq!(finished.interruptions[0].duration, 50);
+    }
+}
@@ -0,0 +1,375 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use super::storage::{Storage, StorageError};
+use super::types::{AppUsageRecord, UsageState};
+
+// ActivityWatch 中记录前台窗口的 bucket 类型，其他类型（如 afkstatus）不导入
+const AW_WINDOW_BUCKET_TYPE: &str = "currentwindow";
+
+// CSV 表头的别名，RescueTime 的导出可以直接导入
+const TIMESTAMP_COLUMNS: &[&str] = &["timestamp", "date", "start", "start_time"];
+const APP_COLUMNS: &[&str] = &["app", "app_name", "application", "activity"];
+const DURATION_COLUMNS: &[&str] = &["duration", "seconds", "time spent (seconds)"];
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportSource {
+    // aw-server 导出的 bucket JSON
+    ActivityWatch,
+    // timestamp, app, duration（秒）
+    Csv,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ImportSummary {
+    pub inserted: usize,
+    // 与已有数据重复或时长为 0
+    pub skipped: usize,
+    // 无法解析的行
+    pub invalid: usize,
+}
+
+#[derive(Debug)]
+pub enum ImportError {
+    Storage(StorageError),
+    Io(io::Error),
+    Csv(csv::Error),
+    Json(serde_json::Error),
+    MissingColumn(&'static str),
+}
+
+impl From<StorageError> for ImportError {
+    fn from(err: StorageError) -> Self {
+        ImportError::Storage(err)
+    }
+}
+
+impl From<io::Error> for ImportError {
+    fn from(err: io::Error) -> Self {
+        ImportError::Io(err)
+    }
+}
+
+impl From<csv::Error> for ImportError {
+    fn from(err: csv::Error) -> Self {
+        ImportError::Csv(err)
+    }
+}
+
+impl From<serde_json::Error> for ImportError {
+    fn from(err: serde_json::Error) -> Self {
+        ImportError::Json(err)
+    }
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImportError::Storage(err) => write!(f, "{}", err),
+            ImportError::Io(err) => write!(f, "IO Error: {}", err),
+            ImportError::Csv(err) => write!(f, "CSV Error: {}", err),
+            ImportError::Json(err) => write!(f, "JSON Error: {}", err),
+            ImportError::MissingColumn(name) => write!(f, "CSV is missing a '{}' column", name),
+        }
+    }
+}
+
+// 解析后的记录以及无法解析的行数
+#[derive(Debug, Default)]
+pub struct ParsedRecords {
+    pub records: Vec<AppUsageRecord>,
+    pub invalid: usize,
+}
+
+#[derive(Deserialize)]
+struct AwExport {
+    buckets: HashMap<String, AwBucket>,
+}
+
+#[derive(Deserialize)]
+struct AwBucket {
+    #[serde(rename = "type")]
+    bucket_type: String,
+    #[serde(default)]
+    events: Vec<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct AwEvent {
+    timestamp: DateTime<Utc>,
+    duration: f64,
+    data: AwWindowData,
+}
+
+#[derive(Deserialize)]
+struct AwWindowData {
+    app: String,
+    title: Option<String>,
+}
+
+fn imported_record(timestamp: DateTime<Utc>, app_name: &str, duration: u64, title: Option<String>) -> AppUsageRecord {
+    AppUsageRecord {
+        timestamp,
+        app_name: app_name.to_string(),
+        duration,
+        state: UsageState::Active,
+        window_title: title.filter(|t| !t.is_empty()),
+        window_class: None,
+        exe_path: None,
+        pid: None,
+        utc_offset: None,
+    }
+}
+
+pub fn parse_activitywatch<R: Read>(reader: R) -> Result<ParsedRecords, ImportError> {
+    let export: AwExport = serde_json::from_reader(reader)?;
+    let mut parsed = ParsedRecords::default();
+    for (id, bucket) in export.buckets {
+        if bucket.bucket_type != AW_WINDOW_BUCKET_TYPE {
+            tracing::info!("Skipping ActivityWatch bucket {} of type {}", id, bucket.bucket_type);
+            continue;
+        }
+        // 逐个事件解析，单个事件损坏不影响其他事件
+        for event in bucket.events {
+            match serde_json::from_value::<AwEvent>(event) {
+                Ok(event) if event.duration.is_finite() && event.duration >= 0.0 && !event.data.app.trim().is_empty() => {
+                    parsed.records.push(imported_record(
+                        event.timestamp,
+                        event.data.app.trim(),
+                        event.duration.round() as u64,
+                        event.data.title,
+                    ));
+                }
+                _ => parsed.invalid += 1,
+            }
+        }
+    }
+    Ok(parsed)
+}
+
+// 不带时区的时间按本机时区解释
+fn parse_csv_timestamp(value: &str) -> Option<DateTime<Utc>> {
+    let value = value.trim();
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(value) {
+        return Some(timestamp.with_timezone(&Utc));
+    }
+    ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M:%S"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+        .and_then(|naive| Local.from_local_datetime(&naive).earliest())
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+}
+
+fn find_column(headers: &csv::StringRecord, aliases: &[&str]) -> Option<usize> {
+    headers.iter().position(|header| {
+        let header = header.trim().to_lowercase();
+        aliases.contains(&header.as_str())
+    })
+}
+
+pub fn parse_csv<R: Read>(reader: R) -> Result<ParsedRecords, ImportError> {
+    let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(reader);
+    let headers = reader.headers()?.clone();
+    let timestamp_column = find_column(&headers, TIMESTAMP_COLUMNS).ok_or(ImportError::MissingColumn("timestamp"))?;
+    let app_column = find_column(&headers, APP_COLUMNS).ok_or(ImportError::MissingColumn("app"))?;
+    let duration_column = find_column(&headers, DURATION_COLUMNS).ok_or(ImportError::MissingColumn("duration"))?;
+
+    let mut parsed = ParsedRecords::default();
+    for row in reader.records() {
+        let row = match row {
+            Ok(row) => row,
+            Err(e) => {
+                tracing::warn!("Skipping unreadable CSV row: {}", e);
+                parsed.invalid += 1;
+                continue;
+            }
+        };
+        let timestamp = row.get(timestamp_column).and_then(parse_csv_timestamp);
+        let app_name = row.get(app_column).map(str::trim).filter(|app| !app.is_empty());
+        let duration = row.get(duration_column)
+            .and_then(|value| value.trim().parse::<f64>().ok())
+            .filter(|duration| duration.is_finite() && *duration >= 0.0);
+        match (timestamp, app_name, duration) {
+            (Some(timestamp), Some(app_name), Some(duration)) => {
+                parsed.records.push(imported_record(timestamp, app_name, duration.round() as u64, None));
+            }
+            _ => parsed.invalid += 1,
+        }
+    }
+    Ok(parsed)
+}
+
+pub fn import_reader<R: Read>(storage: &Storage, source: ImportSource, reader: R) -> Result<ImportSummary, ImportError> {
+    let parsed = match source {
+        ImportSource::ActivityWatch => parse_activitywatch(reader)?,
+        ImportSource::Csv => parse_csv(reader)?,
+    };
+    let (inserted, skipped) = storage.import_records(parsed.records)?;
+    Ok(ImportSummary {
+        inserted,
+        skipped,
+        invalid: parsed.invalid,
+    })
+}
+
+pub fn import_file(storage: &Storage, source: ImportSource, path: &Path) -> Result<ImportSummary, ImportError> {
+    let summary = import_reader(storage, source, BufReader::new(File::open(path)?))?;
+    tracing::info!(
+        "Imported {}: {} inserted, {} skipped, {} invalid",
+        path.display(), summary.inserted, summary.skipped, summary.invalid
+    );
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::types::{Granularity, UsageQuery};
+
+    const AW_EXPORT: &str = r#"{
+        "buckets": {
+            "aw-watcher-window_laptop": {
+                "id": "aw-watcher-window_laptop",
+                "type": "currentwindow",
+                "hostname": "laptop",
+                "events": [
+                    {"timestamp": "2024-05-01T09:00:00.123000+00:00", "duration": 1800.4, "data": {"app": "firefox", "title": "Docs"}},
+                    {"timestamp": "2024-05-01T09:30:00+00:00", "duration": 600.0, "data": {"app": "code", "title": ""}},
+                    {"timestamp": "2024-05-01T09:40:00+00:00", "duration": 0.0, "data": {"app": "code", "title": "x"}},
+                    {"timestamp": "not a time", "duration": 5.0, "data": {"app": "code"}},
+                    {"timestamp": "2024-05-01T09:45:00+00:00", "duration": 5.0, "data": {"status": "afk"}}
+                ]
+            },
+            "aw-watcher-afk_laptop": {
+                "id": "aw-watcher-afk_laptop",
+                "type": "afkstatus",
+                "events": [
+                    {"timestamp": "2024-05-01T09:00:00+00:00", "duration": 60.0, "data": {"status": "not-afk"}}
+                ]
+            }
+        }
+    }"#;
+
+    fn setup_test_storage() -> Storage {
+        Storage::open_in_memory().unwrap()
+    }
+
+    fn total_for(storage: &Storage, app_name: &str) -> u64 {
+        let query = UsageQuery {
+            from: "2024-01-01T00:00:00Z".parse().unwrap(),
+            to: "2025-01-01T00:00:00Z".parse().unwrap(),
+            granularity: Granularity::Day,
+            apps: vec![app_name.to_string()],
+            limit: None,
+            time_zone: Some("UTC".to_string()),
+        };
+        storage.get_usage_stats_range(&query).unwrap()
+            .first()
+            .map(|stat| stat.total_time)
+            .unwrap_or(0)
+    }
+
+    // 1. ActivityWatch 导出只导入窗口 bucket，损坏的事件计为无效
+    #[test]
+    fn test_parse_activitywatch() {
+        let parsed = parse_activitywatch(AW_EXPORT.as_bytes()).unwrap();
+        assert_eq!(parsed.invalid, 2);
+        assert_eq!(parsed.records.len(), 3);
+
+        let firefox = parsed.records.iter().find(|r| r.app_name == "firefox").unwrap();
+        assert_eq!(firefox.duration, 1800);
+        assert_eq!(firefox.window_title.as_deref(), Some("Docs"));
+        let code = parsed.records.iter().find(|r| r.duration == 600).unwrap();
+        assert_eq!(code.window_title, None);
+    }
+
+    // 2. CSV 按表头找列，支持别名
+    #[test]
+    fn test_parse_csv() {
+        let csv = "Date,Time Spent (seconds),Number of People,Activity\n\
+                   2024-05-01T09:00:00Z,120,1,slack\n\
+                   2024-05-01T10:00:00+02:00,60,1,terminal\n\
+                   yesterday,60,1,terminal\n\
+                   2024-05-01T11:00:00Z,-5,1,terminal\n";
+        let parsed = parse_csv(csv.as_bytes()).unwrap();
+        assert_eq!(parsed.invalid, 2);
+        assert_eq!(parsed.records.len(), 2);
+        assert_eq!(parsed.records[0].app_name, "slack");
+        assert_eq!(parsed.records[1].timestamp, "2024-05-01T08:00:00Z".parse::<DateTime<Utc>>().unwrap());
+
+        assert!(matches!(parse_csv("when,what\n".as_bytes()), Err(ImportError::MissingColumn("timestamp"))));
+    }
+
+    // 3. 重复导入时跳过已有数据
+    #[test]
+    fn test_import_deduplicates() {
+        let storage = setup_test_storage();
+        let summary = import_reader(&storage, ImportSource::ActivityWatch, AW_EXPORT.as_bytes()).unwrap();
+        assert_eq!(summary, ImportSummary { inserted: 2, skipped: 1, invalid: 2 });
+        assert_eq!(total_for(&storage, "firefox"), 1800);
+
+        let summary = import_reader(&storage, ImportSource::ActivityWatch, AW_EXPORT.as_bytes()).unwrap();
+        assert_eq!(summary, ImportSummary { inserted: 0, skipped: 3, invalid: 2 });
+        assert_eq!(total_for(&storage, "firefox"), 1800);
+        assert_eq!(total_for(&storage, "code"), 600);
+    }
+
+    // 4. 与本地记录重叠的导入数据不重复计算
+    #[test]
+    fn test_import_skips_overlap_with_recorded_sessions() {
+        let storage = setup_test_storage();
+        storage.record_usage(imported_record("2024-05-01T09:10:00Z".parse().unwrap(), "firefox", 60, None)).unwrap();
+
+        let csv = "timestamp,app,duration\n\
+                   2024-05-01T09:00:00Z,firefox,900\n\
+                   2024-05-01T09:00:00Z,code,900\n";
+        let summary = import_reader(&storage, ImportSource::Csv, csv.as_bytes()).unwrap();
+        assert_eq!(summary, ImportSummary { inserted: 1, skipped: 1, invalid: 0 });
+        assert_eq!(total_for(&storage, "firefox"), 60);
+        assert_eq!(total_for(&storage, "code"), 900);
+    }
+
+    // 5. 已经汇总到按小时和按天统计的数据不会被再次导入
+    #[test]
+    fn test_import_skips_rolled_up_history() {
+        use crate::db::types::RetentionPolicy;
+
+        let storage = setup_test_storage();
+        import_reader(&storage, ImportSource::ActivityWatch, AW_EXPORT.as_bytes()).unwrap();
+        for hourly_days in [365, 7] {
+            storage.set_retention_policy(&RetentionPolicy { raw_days: 7, hourly_days, delete_after_months: None }).unwrap();
+            storage.run_maintenance("2024-06-01T00:00:00Z".parse().unwrap()).unwrap();
+
+            let summary = import_reader(&storage, ImportSource::ActivityWatch, AW_EXPORT.as_bytes()).unwrap();
+            assert_eq!(summary, ImportSummary { inserted: 0, skipped: 3, invalid: 2 });
+            assert_eq!(total_for(&storage, "firefox"), 1800);
+        }
+    }
+
+    // 6. 导入的数据同样经过隐私规则处理
+    #[test]
+    fn test_import_applies_privacy_rules() {
+        use crate::db::privacy::PRIVATE_APP_NAME;
+        use crate::db::types::{MatchField, MatchKind, PrivacyAction};
+
+        let storage = setup_test_storage();
+        storage.add_privacy_rule(MatchField::ProcessName, MatchKind::Glob, "keepass*", PrivacyAction::Drop).unwrap();
+        storage.add_privacy_rule(MatchField::ProcessName, MatchKind::Regex, "(?i)^bank", PrivacyAction::Private).unwrap();
+
+        let csv = "timestamp,app,duration\n\
+                   2024-05-01T09:00:00Z,keepassxc,300\n\
+                   2024-05-01T09:10:00Z,BankClient,600\n\
+                   2024-05-01T09:30:00Z,code,900\n";
+        let summary = import_reader(&storage, ImportSource::Csv, csv.as_bytes()).unwrap();
+        assert_eq!(summary, ImportSummary { inserted: 2, skipped: 1, invalid: 0 });
+        assert_eq!(total_for(&storage, "keepassxc"), 0);
+        assert_eq!(total_for(&storage, "BankClient"), 0);
+        assert_eq!(total_for(&storage, PRIVATE_APP_NAME), 600);
+        assert_eq!(total_for(&storage, "code"), 900);
+    }
+}
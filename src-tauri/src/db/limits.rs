@@ -0,0 +1,306 @@
+use std::collections::HashMap;
+use chrono::{DateTime, Utc};
+use rusqlite::OptionalExtension;
+use super::storage::{Storage, StorageError};
+use super::types::{LimitAlert, LimitStatus, LimitTarget, UsageLimit, UsageQuery};
+
+const LIMIT_THRESHOLDS_KEY: &str = "limit_thresholds";
+// 达到限额的百分之多少时提醒
+const DEFAULT_LIMIT_THRESHOLDS: &[u32] = &[80, 100];
+
+impl Storage {
+    pub fn list_limits(&self) -> Result<Vec<UsageLimit>, StorageError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, app_name, category_id, daily_limit_secs FROM usage_limits ORDER BY id"
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, Option<String>>(1)?,
+                row.get::<_, Option<i64>>(2)?,
+                row.get::<_, i64>(3)?,
+            ))
+        })?;
+
+        let mut limits = Vec::new();
+        for row in rows {
+            let (id, app_name, category_id, daily_limit) = row?;
+            let target = match (app_name, category_id) {
+                (Some(app_name), None) => LimitTarget::App(app_name),
+                (None, Some(category_id)) => LimitTarget::Category(category_id),
+                _ => {
+                    tracing::warn!("Skipping usage limit {} without a single target", id);
+                    continue;
+                }
+            };
+            limits.push(UsageLimit { id, target, daily_limit: daily_limit as u64 });
+        }
+        Ok(limits)
+    }
+
+    // 同一个应用或分类只保留一条限额，重复设置时更新时长
+    pub fn set_limit(&self, target: &LimitTarget, daily_limit: u64) -> Result<UsageLimit, StorageError> {
+        if daily_limit == 0 {
+            return Err(StorageError::InvalidQuery("daily limit must be positive".to_string()));
+        }
+        let (app_name, category_id) = match target {
+            LimitTarget::App(app_name) if app_name.trim().is_empty() => {
+                return Err(StorageError::InvalidQuery("app name must not be empty".to_string()));
+            }
+            LimitTarget::App(app_name) => (Some(app_name.as_str()), None),
+            LimitTarget::Category(category_id) => {
+                let exists: bool = self.conn.query_row(
+                    "SELECT EXISTS(SELECT 1 FROM categories WHERE id = ?1)",
+                    [category_id],
+                    |row| row.get(0),
+                )?;
+                if !exists {
+                    return Err(StorageError::NotFound(format!("category {}", category_id)));
+                }
+                (None, Some(*category_id))
+            }
+        };
+
+        let existing: Option<i64> = self.conn.query_row(
+            "SELECT id FROM usage_limits WHERE app_name = ?1 OR category_id = ?2",
+            (app_name, category_id),
+            |row| row.get(0),
+        ).optional()?;
+        let id = match existing {
+            Some(id) => {
+                self.conn.execute(
+                    "UPDATE usage_limits SET daily_limit_secs = ?1 WHERE id = ?2",
+                    (daily_limit as i64, id),
+                )?;
+                id
+            }
+            None => {
+                self.conn.execute(
+                    "INSERT INTO usage_limits (app_name, category_id, daily_limit_secs) VALUES (?1, ?2, ?3)",
+                    (app_name, category_id, daily_limit as i64),
+                )?;
+                self.conn.last_insert_rowid()
+            }
+        };
+        Ok(UsageLimit { id, target: target.clone(), daily_limit })
+    }
+
+    pub fn delete_limit(&self, id: i64) -> Result<(), StorageError> {
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute("DELETE FROM limit_alerts WHERE limit_id = ?1", [id])?;
+        if tx.execute("DELETE FROM usage_limits WHERE id = ?1", [id])? == 0 {
+            return Err(StorageError::NotFound(format!("usage limit {}", id)));
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn get_limit_thresholds(&self) -> Result<Vec<u32>, StorageError> {
+        let thresholds = self.get_setting(LIMIT_THRESHOLDS_KEY)?
+            .map(|value| value.split(',').filter_map(|t| t.trim().parse().ok()).collect::<Vec<u32>>())
+            .filter(|thresholds| !thresholds.is_empty());
+        Ok(thresholds.unwrap_or_else(|| DEFAULT_LIMIT_THRESHOLDS.to_vec()))
+    }
+
+    pub fn set_limit_thresholds(&self, thresholds: &[u32]) -> Result<(), StorageError> {
+        let mut thresholds = thresholds.to_vec();
+        thresholds.sort_unstable();
+        thresholds.dedup();
+        if thresholds.is_empty() || thresholds[0] == 0 {
+            return Err(StorageError::InvalidQuery("thresholds must be positive percentages".to_string()));
+        }
+        let value: Vec<String> = thresholds.iter().map(|t| t.to_string()).collect();
+        self.set_setting(LIMIT_THRESHOLDS_KEY, &value.join(","))
+    }
+
+    // 从本地今天零点到 now 的使用情况
+    pub fn get_limit_status(&self, now: DateTime<Utc>) -> Result<Vec<LimitStatus>, StorageError> {
+        let limits = self.list_limits()?;
+        if limits.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let time_zone = self.configured_time_zone()?;
+        let query = UsageQuery::from_range("daily", now, &time_zone)
+            .ok_or_else(|| StorageError::InvalidQuery("Unknown range: daily".to_string()))?;
+
+        let app_totals: HashMap<String, u64> = self.get_usage_stats_range(&query)?
+            .into_iter()
+            .map(|stat| (stat.name, stat.total_time))
+            .collect();
+        let mut category_totals: HashMap<i64, u64> = HashMap::new();
+        if limits.iter().any(|limit| matches!(limit.target, LimitTarget::Category(_))) {
+            for stat in self.get_category_stats(&query)? {
+                if let Some(id) = stat.category_id {
+                    category_totals.insert(id, stat.total_time);
+                }
+            }
+        }
+        let category_names: HashMap<i64, String> = self.list_categories()?
+            .into_iter()
+            .map(|category| (category.id, category.name))
+            .collect();
+
+        Ok(limits.into_iter()
+            .map(|limit| {
+                let (name, used) = match &limit.target {
+                    LimitTarget::App(app_name) => {
+                        (app_name.clone(), app_totals.get(app_name).copied().unwrap_or(0))
+                    }
+                    LimitTarget::Category(id) => (
+                        category_names.get(id).cloned().unwrap_or_else(|| format!("Category {}", id)),
+                        category_totals.get(id).copied().unwrap_or(0),
+                    ),
+                };
+                LimitStatus {
+                    name,
+                    used,
+                    remaining: limit.daily_limit.saturating_sub(used),
+                    percent: (used * 100 / limit.daily_limit) as u32,
+                    limit,
+                }
+            })
+            .collect())
+    }
+
+    // 返回今天新越过提醒阈值的限额，同一条限额同时越过多个阈值时只提醒最高的一个
+    pub fn check_limits(&self, now: DateTime<Utc>) -> Result<Vec<LimitAlert>, StorageError> {
+        let statuses = self.get_limit_status(now)?;
+        if statuses.is_empty() {
+            return Ok(Vec::new());
+        }
+        let thresholds = self.get_limit_thresholds()?;
+        let today = now.with_timezone(&self.configured_time_zone()?).date_naive().to_string();
+
+        let mut alerts = Vec::new();
+        for status in statuses {
+            let mut crossed = None;
+            for threshold in thresholds.iter().filter(|t| status.percent >= **t) {
+                let inserted = self.conn.execute(
+                    "INSERT OR IGNORE INTO limit_alerts (limit_id, date, threshold) VALUES (?1, ?2, ?3)",
+                    (status.limit.id, &today, threshold),
+                )?;
+                if inserted > 0 {
+                    crossed = Some(*threshold);
+                }
+            }
+            if let Some(threshold) = crossed {
+                alerts.push(LimitAlert {
+                    limit_id: status.limit.id,
+                    name: status.name,
+                    threshold,
+                    used: status.used,
+                    daily_limit: status.limit.daily_limit,
+                });
+            }
+        }
+        Ok(alerts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::types::{AppUsageRecord, UsageState};
+    use chrono::Duration;
+
+    fn utc(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(value).unwrap().with_timezone(&Utc)
+    }
+
+    fn setup_test_storage() -> Storage {
+        let storage = Storage::open_in_memory().unwrap();
+        storage.set_time_zone("UTC").unwrap();
+        storage
+    }
+
+    fn record(storage: &Storage, start: DateTime<Utc>, app_name: &str, duration: u64) {
+        storage.record_usage(AppUsageRecord {
+            timestamp: start,
+            app_name: app_name.to_string(),
+            duration,
+            state: UsageState::Active,
+            window_title: None,
+            window_class: None,
+            exe_path: None,
+            pid: None,
+            utc_offset: None,
+        }).unwrap();
+    }
+
+    // 1. 限额的增删改，同一目标只保留一条
+    #[test]
+    fn test_limit_crud() {
+        let storage = setup_test_storage();
+        let game = storage.set_limit(&LimitTarget::App("game".to_string()), 3600).unwrap();
+        let updated = storage.set_limit(&LimitTarget::App("game".to_string()), 1800).unwrap();
+        assert_eq!(updated.id, game.id);
+        let entertainment = storage.set_limit(&LimitTarget::Category(3), 7200).unwrap();
+        assert_eq!(storage.list_limits().unwrap(), vec![updated, entertainment.clone()]);
+
+        assert!(matches!(storage.set_limit(&LimitTarget::App("game".to_string()), 0), Err(StorageError::InvalidQuery(_))));
+        assert!(matches!(storage.set_limit(&LimitTarget::Category(999), 60), Err(StorageError::NotFound(_))));
+
+        // 删除分类时一并删除它的限额
+        storage.delete_category(3).unwrap();
+        assert_eq!(storage.list_limits().unwrap().len(), 1);
+        storage.delete_limit(game.id).unwrap();
+        assert!(matches!(storage.delete_limit(game.id), Err(StorageError::NotFound(_))));
+    }
+
+    // 2. 只统计今天的使用时长，分类限额按规则汇总
+    #[test]
+    fn test_limit_status() {
+        let storage = setup_test_storage();
+        let now = utc("2026-03-02T12:00:00Z");
+        record(&storage, utc("2026-03-01T20:00:00Z"), "game", 3600);   // 昨天
+        record(&storage, utc("2026-03-02T09:00:00Z"), "game", 2700);
+        record(&storage, utc("2026-03-02T10:00:00Z"), "spotify", 600);
+
+        storage.set_limit(&LimitTarget::App("game".to_string()), 3600).unwrap();
+        storage.set_limit(&LimitTarget::Category(3), 1200).unwrap();
+
+        let status = storage.get_limit_status(now).unwrap();
+        assert_eq!((status[0].name.as_str(), status[0].used, status[0].remaining, status[0].percent), ("game", 2700, 900, 75));
+        assert_eq!((status[1].name.as_str(), status[1].used, status[1].percent), ("Entertainment", 600, 50));
+    }
+
+    // 3. 每个阈值每天只提醒一次
+    #[test]
+    fn test_check_limits_alerts_once() {
+        let storage = setup_test_storage();
+        let start = utc("2026-03-02T09:00:00Z");
+        let limit = storage.set_limit(&LimitTarget::App("game".to_string()), 1000).unwrap();
+
+        record(&storage, start, "game", 700);
+        assert!(storage.check_limits(start + Duration::seconds(700)).unwrap().is_empty());
+
+        record(&storage, start + Duration::seconds(700), "game", 100);
+        let alerts = storage.check_limits(start + Duration::seconds(800)).unwrap();
+        assert_eq!(alerts, vec![LimitAlert { limit_id: limit.id, name: "game".into(), threshold: 80, used: 800, daily_limit: 1000 }]);
+        assert!(storage.check_limits(start + Duration::seconds(800)).unwrap().is_empty());
+
+        // 一次越过多个阈值时只提醒最高的
+        storage.set_limit_thresholds(&[50, 90, 100]).unwrap();
+        record(&storage, start + Duration::seconds(800), "game", 300);
+        let alerts = storage.check_limits(start + Duration::seconds(1100)).unwrap();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].threshold, 100);
+
+        // 第二天重新计算
+        record(&storage, start + Duration::days(1), "game", 1000);
+        let alerts = storage.check_limits(start + Duration::days(1) + Duration::seconds(1000)).unwrap();
+        assert_eq!(alerts[0].threshold, 100);
+    }
+
+    // 4. 阈值去重排序，拒绝空列表和 0
+    #[test]
+    fn test_limit_thresholds_setting() {
+        let storage = setup_test_storage();
+        assert_eq!(storage.get_limit_thresholds().unwrap(), vec![80, 100]);
+        storage.set_limit_thresholds(&[100, 50, 50]).unwrap();
+        assert_eq!(storage.get_limit_thresholds().unwrap(), vec![50, 100]);
+        assert!(storage.set_limit_thresholds(&[]).is_err());
+        assert!(storage.set_limit_thresholds(&[0, 100]).is_err());
+    }
+}
@@ -1,2 +1,14 @@
 pub mod types;
-pub mod storage;
\ No newline at end of file
+pub mod storage;
+pub mod app_storage;
+pub mod timezone;
+pub mod export;
+pub mod import;
+pub mod categories;
+pub mod limits;
+pub mod focus;
+pub mod retention;
+pub mod tracking;
+pub mod privacy;
+pub mod encryption;
+pub mod backup;
@@ -0,0 +1,388 @@
+use std::collections::HashMap;
+use regex::Regex;
+use super::categories::compile_pattern;
+use super::storage::{Storage, StorageError};
+use super::types::{AppUsageRecord, MatchField, MatchKind, PrivacyAction, PrivacyRule, PurgeReport};
+use crate::platform::ActiveWindow;
+
+// 按 Private 规则记录时使用的应用名
+pub const PRIVATE_APP_NAME: &str = "Private";
+
+struct CompiledRule {
+    field: MatchField,
+    regex: Regex,
+    action: PrivacyAction,
+}
+
+// 在记录写入数据库之前按隐私规则过滤
+#[derive(Default)]
+pub struct PrivacyFilter {
+    rules: Vec<CompiledRule>,
+}
+
+impl PrivacyFilter {
+    pub fn new(rules: &[PrivacyRule]) -> Result<Self, StorageError> {
+        let rules = rules.iter()
+            .map(|rule| Ok(CompiledRule {
+                field: rule.field,
+                regex: compile_rule_pattern(rule.kind, &rule.pattern)?,
+                action: rule.action,
+            }))
+            .collect::<Result<Vec<_>, StorageError>>()?;
+        Ok(Self { rules })
+    }
+
+    // 所有匹配的规则中最严格的处理方式
+    pub fn action_for(&self, process_name: &str, window_class: Option<&str>, window_title: Option<&str>) -> Option<PrivacyAction> {
+        self.rules.iter()
+            .filter(|rule| {
+                let value = match rule.field {
+                    MatchField::ProcessName => Some(process_name),
+                    MatchField::WindowClass => window_class,
+                    MatchField::WindowTitle => window_title,
+                };
+                value.is_some_and(|value| rule.regex.is_match(value))
+            })
+            .map(|rule| rule.action)
+            .max()
+    }
+
+    // 返回 None 表示这个窗口不应记录
+    pub fn apply_window(&self, mut window: ActiveWindow) -> Option<ActiveWindow> {
+        match self.action_for(&window.process_name, window.window_class.as_deref(), window.window_title.as_deref()) {
+            Some(PrivacyAction::Drop) => return None,
+            Some(PrivacyAction::Private) => {
+                window = ActiveWindow { process_name: PRIVATE_APP_NAME.to_string(), ..Default::default() };
+            }
+            Some(PrivacyAction::StripTitle) => window.window_title = None,
+            None => {}
+        }
+        Some(window)
+    }
+
+    // 返回 None 表示这条记录不应写入
+    pub fn apply_record(&self, mut record: AppUsageRecord) -> Option<AppUsageRecord> {
+        match self.action_for(&record.app_name, record.window_class.as_deref(), record.window_title.as_deref()) {
+            Some(PrivacyAction::Drop) => return None,
+            Some(PrivacyAction::Private) => {
+                record.app_name = PRIVATE_APP_NAME.to_string();
+                record.window_title = None;
+                record.window_class = None;
+                record.exe_path = None;
+                record.pid = None;
+            }
+            Some(PrivacyAction::StripTitle) => record.window_title = None,
+            None => {}
+        }
+        Some(record)
+    }
+}
+
+// 规则的模式与分类规则相同，错误按无效查询返回
+fn compile_rule_pattern(kind: MatchKind, pattern: &str) -> Result<Regex, StorageError> {
+    compile_pattern(kind, pattern).map_err(|e| match e {
+        StorageError::InvalidCategory(msg) => StorageError::InvalidQuery(msg),
+        e => e,
+    })
+}
+
+// 汇总表中按新的应用名和标题重新累加的一行
+type RollupRow = (String, String, String, String, String, i32);
+
+impl Storage {
+    pub fn list_privacy_rules(&self) -> Result<Vec<PrivacyRule>, StorageError> {
+        let mut stmt = self.conn.prepare("SELECT id, field, kind, pattern, action FROM privacy_rules ORDER BY id")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, String>(4)?,
+            ))
+        })?;
+
+        let mut rules = Vec::new();
+        for row in rows {
+            let (id, field, kind, pattern, action) = row?;
+            match (MatchField::parse(&field), MatchKind::parse(&kind), PrivacyAction::parse(&action)) {
+                (Some(field), Some(kind), Some(action)) => rules.push(PrivacyRule { id, field, kind, pattern, action }),
+                _ => tracing::warn!("Skipping privacy rule {} with unknown field {}, kind {} or action {}", id, field, kind, action),
+            }
+        }
+        Ok(rules)
+    }
+
+    pub fn add_privacy_rule(
+        &self,
+        field: MatchField,
+        kind: MatchKind,
+        pattern: &str,
+        action: PrivacyAction,
+    ) -> Result<PrivacyRule, StorageError> {
+        compile_rule_pattern(kind, pattern)?;
+        self.conn.execute(
+            "INSERT INTO privacy_rules (field, kind, pattern, action) VALUES (?1, ?2, ?3, ?4)",
+            (field.as_str(), kind.as_str(), pattern, action.as_str()),
+        )?;
+        Ok(PrivacyRule {
+            id: self.conn.last_insert_rowid(),
+            field,
+            kind,
+            pattern: pattern.to_string(),
+            action,
+        })
+    }
+
+    pub fn delete_privacy_rule(&self, id: i64) -> Result<(), StorageError> {
+        if self.conn.execute("DELETE FROM privacy_rules WHERE id = ?1", [id])? == 0 {
+            return Err(StorageError::NotFound(format!("privacy rule {}", id)));
+        }
+        Ok(())
+    }
+
+    pub fn privacy_filter(&self) -> Result<PrivacyFilter, StorageError> {
+        PrivacyFilter::new(&self.list_privacy_rules()?)
+    }
+
+    // 按当前规则处理已有的会话和汇总数据。开启 secure_delete 并截断 WAL，删除的内容不会留在数据库文件中
+    pub fn purge_private_history(&self) -> Result<PurgeReport, StorageError> {
+        let filter = self.privacy_filter()?;
+        let mut report = PurgeReport::default();
+        self.conn.pragma_update(None, "secure_delete", true)?;
+        let result = self.purge_with(&filter, &mut report);
+        self.conn.pragma_update(None, "secure_delete", false)?;
+        result?;
+        self.conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+        tracing::info!(
+            "Purged private history: {} deleted, {} anonymized, {} titles removed",
+            report.deleted, report.anonymized, report.stripped
+        );
+        Ok(report)
+    }
+
+    fn purge_with(&self, filter: &PrivacyFilter, report: &mut PurgeReport) -> Result<(), StorageError> {
+        let tx = self.conn.unchecked_transaction()?;
+
+        let sessions = {
+            let mut stmt = tx.prepare("SELECT id, app_name, window_class, window_title FROM app_sessions")?;
+            let rows = stmt.query_map([], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                    row.get::<_, Option<String>>(3)?,
+                ))
+            })?;
+            rows.collect::<Result<Vec<_>, _>>()?
+        };
+        for (id, app_name, window_class, window_title) in sessions {
+            match filter.action_for(&app_name, window_class.as_deref(), window_title.as_deref()) {
+                Some(PrivacyAction::Drop) => {
+                    tx.execute("DELETE FROM app_sessions WHERE id = ?1", [id])?;
+                    report.deleted += 1;
+                }
+                Some(PrivacyAction::Private) if app_name != PRIVATE_APP_NAME || window_title.is_some() => {
+                    tx.execute(
+                        "UPDATE app_sessions SET app_name = ?1, window_title = NULL, window_class = NULL,
+                         exe_path = NULL, pid = NULL WHERE id = ?2",
+                        (PRIVATE_APP_NAME, id),
+                    )?;
+                    report.anonymized += 1;
+                }
+                Some(PrivacyAction::StripTitle) if window_title.is_some() => {
+                    tx.execute("UPDATE app_sessions SET window_title = NULL WHERE id = ?1", [id])?;
+                    report.stripped += 1;
+                }
+                _ => {}
+            }
+        }
+
+        // 汇总表中应用名和标题是主键的一部分，处理后与相同的行合并
+        for (table, has_title) in [("usage_hourly", true), ("usage_daily", false)] {
+            let title_column = if has_title { "window_title" } else { "''" };
+            let rows = {
+                let mut stmt = tx.prepare(&format!(
+                    "SELECT rowid, bucket_start, app_name, state, {}, window_class, utc_offset, duration FROM {}",
+                    title_column, table
+                ))?;
+                let rows = stmt.query_map([], |row| {
+                    Ok((
+                        row.get::<_, i64>(0)?,
+                        (
+                            row.get::<_, String>(1)?,
+                            row.get::<_, String>(2)?,
+                            row.get::<_, String>(3)?,
+                            row.get::<_, String>(4)?,
+                            row.get::<_, String>(5)?,
+                            row.get::<_, i32>(6)?,
+                        ),
+                        row.get::<_, i64>(7)?,
+                    ))
+                })?;
+                rows.collect::<Result<Vec<_>, _>>()?
+            };
+
+            let mut merged: HashMap<RollupRow, i64> = HashMap::new();
+            for (rowid, (bucket, app_name, state, title, class, offset), duration) in rows {
+                let non_empty = |value: &str| Some(value.to_string()).filter(|v| !v.is_empty());
+                let (app_name, title, class) = match filter.action_for(&app_name, non_empty(&class).as_deref(), non_empty(&title).as_deref()) {
+                    Some(PrivacyAction::Drop) => {
+                        report.deleted += 1;
+                        (None, title, class)
+                    }
+                    Some(PrivacyAction::Private) if app_name != PRIVATE_APP_NAME || !title.is_empty() => {
+                        report.anonymized += 1;
+                        (Some(PRIVATE_APP_NAME.to_string()), String::new(), String::new())
+                    }
+                    Some(PrivacyAction::StripTitle) if !title.is_empty() => {
+                        report.stripped += 1;
+                        (Some(app_name), String::new(), class)
+                    }
+                    _ => continue,
+                };
+                tx.execute(&format!("DELETE FROM {} WHERE rowid = ?1", table), [rowid])?;
+                if let Some(app_name) = app_name {
+                    *merged.entry((bucket, app_name, state, title, class, offset)).or_insert(0) += duration;
+                }
+            }
+
+            for ((bucket, app_name, state, title, class, offset), duration) in merged {
+                if has_title {
+                    tx.execute(
+                        "INSERT INTO usage_hourly (bucket_start, app_name, state, window_title, window_class, utc_offset, duration)
+                         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                         ON CONFLICT DO UPDATE SET duration = duration + excluded.duration",
+                        (bucket, app_name, state, title, class, offset, duration),
+                    )?;
+                } else {
+                    tx.execute(
+                        "INSERT INTO usage_daily (bucket_start, app_name, state, window_class, utc_offset, duration)
+                         VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                         ON CONFLICT DO UPDATE SET duration = duration + excluded.duration",
+                        (bucket, app_name, state, class, offset, duration),
+                    )?;
+                }
+            }
+        }
+
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, Utc};
+    use crate::db::types::{RetentionPolicy, UsageState};
+
+    fn utc(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(value).unwrap().with_timezone(&Utc)
+    }
+
+    fn record(start: &str, app_name: &str, title: &str, duration: u64) -> AppUsageRecord {
+        AppUsageRecord {
+            timestamp: utc(start),
+            app_name: app_name.to_string(),
+            duration,
+            state: UsageState::Active,
+            window_title: Some(title.to_string()),
+            window_class: Some(format!("{}-class", app_name)),
+            exe_path: Some(format!("/usr/bin/{}", app_name)),
+            pid: Some(42),
+            utc_offset: Some(0),
+        }
+    }
+
+    fn rows(storage: &Storage, sql: &str) -> Vec<(String, Option<String>)> {
+        let mut stmt = storage.conn.prepare(sql).unwrap();
+        stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .map(|r| r.unwrap())
+            .collect()
+    }
+
+    // 1. 默认规则去掉隐私窗口的标题，多条规则匹配时取最严格的
+    #[test]
+    fn test_filter_actions() {
+        let storage = Storage::open_in_memory().unwrap();
+        storage.add_privacy_rule(MatchField::ProcessName, MatchKind::Glob, "keepass*", PrivacyAction::Drop).unwrap();
+        storage.add_privacy_rule(MatchField::WindowTitle, MatchKind::Regex, "(?i)bank", PrivacyAction::Private).unwrap();
+        storage.add_privacy_rule(MatchField::ProcessName, MatchKind::Glob, "firefox", PrivacyAction::StripTitle).unwrap();
+        let filter = storage.privacy_filter().unwrap();
+
+        let chrome = filter.apply_record(record("2026-05-01T09:00:00Z", "chrome", "New Tab - Google Chrome (Incognito)", 1)).unwrap();
+        assert_eq!((chrome.app_name.as_str(), chrome.window_title), ("chrome", None));
+
+        assert!(filter.apply_record(record("2026-05-01T09:00:00Z", "keepassxc", "Passwords.kdbx", 1)).is_none());
+
+        let bank = filter.apply_record(record("2026-05-01T09:00:00Z", "firefox", "My Bank - Accounts", 1)).unwrap();
+        assert_eq!(bank.app_name, PRIVATE_APP_NAME);
+        assert_eq!((bank.window_title, bank.window_class, bank.exe_path, bank.pid), (None, None, None, None));
+
+        let other = filter.apply_record(record("2026-05-01T09:00:00Z", "code", "main.rs", 1)).unwrap();
+        assert_eq!(other.window_title.as_deref(), Some("main.rs"));
+
+        assert!(matches!(
+            storage.add_privacy_rule(MatchField::WindowTitle, MatchKind::Regex, "(", PrivacyAction::Drop),
+            Err(StorageError::InvalidQuery(_))
+        ));
+    }
+
+    // 2. 按新规则清理已有的会话
+    #[test]
+    fn test_purge_sessions() {
+        let storage = Storage::open_in_memory().unwrap();
+        for r in [
+            record("2026-05-01T09:00:00Z", "keepassxc", "Passwords.kdbx", 60),
+            record("2026-05-01T09:01:00Z", "firefox", "My Bank - Accounts", 60),
+            record("2026-05-01T09:02:00Z", "chrome", "Docs (Incognito)", 60),
+            record("2026-05-01T09:03:00Z", "code", "main.rs", 60),
+        ] {
+            storage.record_usage(r).unwrap();
+        }
+        storage.add_privacy_rule(MatchField::ProcessName, MatchKind::Glob, "keepass*", PrivacyAction::Drop).unwrap();
+        storage.add_privacy_rule(MatchField::WindowTitle, MatchKind::Glob, "*bank*", PrivacyAction::Private).unwrap();
+
+        let report = storage.purge_private_history().unwrap();
+        assert_eq!(report, PurgeReport { deleted: 1, anonymized: 1, stripped: 1 });
+        assert_eq!(
+            rows(&storage, "SELECT app_name, window_title FROM app_sessions ORDER BY id"),
+            vec![
+                (PRIVATE_APP_NAME.to_string(), None),
+                ("chrome".to_string(), None),
+                ("code".to_string(), Some("main.rs".to_string())),
+            ]
+        );
+        // 再次清理没有需要处理的行
+        assert_eq!(storage.purge_private_history().unwrap(), PurgeReport::default());
+    }
+
+    // 3. 汇总数据中的行处理后与相同的行合并
+    #[test]
+    fn test_purge_rollups() {
+        let storage = Storage::open_in_memory().unwrap();
+        for r in [
+            record("2026-01-01T09:00:00Z", "firefox", "Bank A", 600),
+            record("2026-01-01T09:10:00Z", "firefox", "Bank B", 300),
+            record("2026-01-01T09:15:00Z", "firefox", "News", 300),
+        ] {
+            storage.record_usage(r).unwrap();
+        }
+        storage.set_retention_policy(&RetentionPolicy { raw_days: 7, hourly_days: 365, delete_after_months: None }).unwrap();
+        storage.run_maintenance(utc("2026-05-01T00:00:00Z")).unwrap();
+        storage.add_privacy_rule(MatchField::WindowTitle, MatchKind::Glob, "bank*", PrivacyAction::Private).unwrap();
+
+        let report = storage.purge_private_history().unwrap();
+        assert_eq!(report, PurgeReport { deleted: 0, anonymized: 2, stripped: 0 });
+        let hourly: Vec<(String, String, i64)> = {
+            let mut stmt = storage.conn.prepare("SELECT app_name, window_title, duration FROM usage_hourly ORDER BY app_name").unwrap();
+            stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?))).unwrap().map(|r| r.unwrap()).collect()
+        };
+        assert_eq!(hourly, vec![
+            (PRIVATE_APP_NAME.to_string(), String::new(), 900),
+            ("firefox".to_string(), "News".to_string(), 300),
+        ]);
+    }
+}
@@ -0,0 +1,376 @@
+use std::collections::HashMap;
+use chrono::{DateTime, Duration, DurationRound, FixedOffset, Local, Months, TimeZone, Utc};
+use rusqlite::Transaction;
+use super::storage::{format_timestamp, parse_timestamp, with_app_filter, SessionSpan, Storage, StorageError};
+use super::types::{MaintenanceReport, RetentionPolicy, UsageQuery};
+
+const RETENTION_RAW_DAYS_KEY: &str = "retention_raw_days";
+const RETENTION_HOURLY_DAYS_KEY: &str = "retention_hourly_days";
+const RETENTION_DELETE_MONTHS_KEY: &str = "retention_delete_months";
+const DEFAULT_RAW_DAYS: u32 = 90;
+const DEFAULT_HOURLY_DAYS: u32 = 365;
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        RetentionPolicy {
+            raw_days: DEFAULT_RAW_DAYS,
+            hourly_days: DEFAULT_HOURLY_DAYS,
+            delete_after_months: None,
+        }
+    }
+}
+
+// 汇总表中的一行：(开始时间, 应用, 状态, 窗口标题, 窗口类, utc_offset)
+type RollupKey = (DateTime<Utc>, String, String, String, String, i32);
+
+// 把 [start, end) 按整点拆开
+fn split_by_hour(start: DateTime<Utc>, end: DateTime<Utc>) -> Vec<(DateTime<Utc>, u64)> {
+    let mut parts = Vec::new();
+    let mut cursor = start;
+    while cursor < end {
+        let hour = cursor.duration_trunc(Duration::hours(1)).unwrap_or(cursor);
+        let next = (hour + Duration::hours(1)).min(end);
+        parts.push((hour, (next - cursor).num_seconds() as u64));
+        cursor = next;
+    }
+    parts
+}
+
+// 按记录时的偏移计算本地日期零点对应的 UTC 时间
+fn local_day_start(timestamp: DateTime<Utc>, utc_offset: i32) -> DateTime<Utc> {
+    let offset = FixedOffset::east_opt(utc_offset).unwrap_or(FixedOffset::east_opt(0).unwrap());
+    let date = timestamp.with_timezone(&offset).date_naive();
+    offset.from_local_datetime(&date.and_hms_opt(0, 0, 0).unwrap())
+        .single()
+        .map(|t| t.with_timezone(&Utc))
+        .unwrap_or(timestamp)
+}
+
+impl Storage {
+    pub fn get_retention_policy(&self) -> Result<RetentionPolicy, StorageError> {
+        let number = |key: &str| -> Result<Option<u32>, StorageError> {
+            Ok(self.get_setting(key)?.and_then(|value| value.parse().ok()))
+        };
+        let defaults = RetentionPolicy::default();
+        Ok(RetentionPolicy {
+            raw_days: number(RETENTION_RAW_DAYS_KEY)?.unwrap_or(defaults.raw_days),
+            hourly_days: number(RETENTION_HOURLY_DAYS_KEY)?.unwrap_or(defaults.hourly_days),
+            delete_after_months: number(RETENTION_DELETE_MONTHS_KEY)?.filter(|months| *months > 0),
+        })
+    }
+
+    // 今天的限额和专注统计需要原始会话，所以原始数据至少保留一天
+    pub fn set_retention_policy(&self, policy: &RetentionPolicy) -> Result<(), StorageError> {
+        if policy.raw_days == 0 {
+            return Err(StorageError::InvalidQuery("raw data must be kept for at least one day".to_string()));
+        }
+        if policy.hourly_days < policy.raw_days {
+            return Err(StorageError::InvalidQuery("hourly rollups must be kept at least as long as raw data".to_string()));
+        }
+        if policy.delete_after_months == Some(0) {
+            return Err(StorageError::InvalidQuery("delete_after_months must be positive".to_string()));
+        }
+        self.set_setting(RETENTION_RAW_DAYS_KEY, &policy.raw_days.to_string())?;
+        self.set_setting(RETENTION_HOURLY_DAYS_KEY, &policy.hourly_days.to_string())?;
+        match policy.delete_after_months {
+            Some(months) => self.set_setting(RETENTION_DELETE_MONTHS_KEY, &months.to_string()),
+            None => {
+                self.conn.execute("DELETE FROM settings WHERE key = ?1", [RETENTION_DELETE_MONTHS_KEY])?;
+                Ok(())
+            }
+        }
+    }
+
+    // 按保留策略删除过期数据，再把旧会话汇总为小时、把旧的小时汇总为天，全部在一个事务中完成
+    pub fn run_maintenance(&self, now: DateTime<Utc>) -> Result<MaintenanceReport, StorageError> {
+        let policy = self.get_retention_policy()?;
+        let tx = self.conn.unchecked_transaction()?;
+        let mut report = MaintenanceReport::default();
+
+        if let Some(cutoff) = policy.delete_after_months.and_then(|months| now.checked_sub_months(Months::new(months))) {
+            let cutoff = format_timestamp(cutoff);
+            report.deleted += tx.execute("DELETE FROM app_sessions WHERE end_time <= ?1", [&cutoff])?;
+            report.deleted += tx.execute("DELETE FROM usage_hourly WHERE bucket_start < ?1", [&cutoff])?;
+            report.deleted += tx.execute("DELETE FROM usage_daily WHERE bucket_start < ?1", [&cutoff])?;
+        }
+
+        report.sessions_rolled_up = roll_up_sessions(&tx, now - Duration::days(policy.raw_days as i64))?;
+        report.hourly_rolled_up = roll_up_hours(&tx, now - Duration::days(policy.hourly_days as i64))?;
+        tx.commit()?;
+
+        if report != MaintenanceReport::default() {
+            tracing::info!(
+                "Maintenance rolled up {} sessions and {} hourly rows, deleted {} rows",
+                report.sessions_rolled_up, report.hourly_rolled_up, report.deleted
+            );
+        }
+        Ok(report)
+    }
+
+    // 与查询区间有重叠的汇总数据，每行视为从桶开始时连续使用了 duration 秒
+    pub(super) fn load_rollup_spans(&self, query: &UsageQuery) -> Result<Vec<SessionSpan>, StorageError> {
+        let mut spans = Vec::new();
+        // 本地日期最长 26 小时（时区偏移加夏令时），往前多查两天的桶
+        for (table, title_column, lookback) in [
+            ("usage_hourly", "window_title", Duration::hours(1)),
+            ("usage_daily", "''", Duration::days(2)),
+        ] {
+            let (filter, params) = with_app_filter(
+                query,
+                String::from("bucket_start >= ?1 AND bucket_start < ?2"),
+                vec![format_timestamp(query.from - lookback), format_timestamp(query.to)],
+            );
+            let sql = format!(
+                "SELECT app_name, bucket_start, duration, {}, window_class, utc_offset
+                 FROM {}
+                 WHERE state = 'active' AND {}",
+                title_column, table, filter
+            );
+
+            let mut stmt = self.conn.prepare(&sql)?;
+            let rows = stmt.query_map(rusqlite::params_from_iter(params), |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, i64>(2)?,
+                    row.get::<_, String>(3)?,
+                    row.get::<_, String>(4)?,
+                    row.get::<_, i32>(5)?,
+                ))
+            })?;
+            for row in rows {
+                let (app_name, bucket_start, duration, window_title, window_class, utc_offset) = row?;
+                let Some(start) = parse_timestamp(&bucket_start) else {
+                    tracing::warn!("Skipping {} row with invalid bucket: {}", table, bucket_start);
+                    continue;
+                };
+                spans.push(SessionSpan {
+                    app_name,
+                    window_title: Some(window_title).filter(|t| !t.is_empty()),
+                    window_class: Some(window_class).filter(|c| !c.is_empty()),
+                    utc_offset: Some(utc_offset),
+                    start,
+                    end: start + Duration::seconds(duration),
+                });
+            }
+        }
+        Ok(spans)
+    }
+}
+
+// 把在 cutoff 之前结束的会话按小时累加到 usage_hourly 后删除
+fn roll_up_sessions(tx: &Transaction, cutoff: DateTime<Utc>) -> Result<usize, StorageError> {
+    let cutoff = format_timestamp(cutoff);
+    let mut totals: HashMap<RollupKey, u64> = HashMap::new();
+    {
+        let mut stmt = tx.prepare(
+            "SELECT app_name, state, start_time, end_time, window_title, window_class, utc_offset
+             FROM app_sessions WHERE end_time <= ?1"
+        )?;
+        let mut rows = stmt.query([&cutoff])?;
+        while let Some(row) = rows.next()? {
+            let start_time: String = row.get(2)?;
+            let end_time: String = row.get(3)?;
+            let (Some(start), Some(end)) = (parse_timestamp(&start_time), parse_timestamp(&end_time)) else {
+                tracing::warn!("Dropping session with invalid timestamps: {} - {}", start_time, end_time);
+                continue;
+            };
+            let utc_offset = row.get::<_, Option<i32>>(6)?
+                .unwrap_or_else(|| Local.offset_from_utc_datetime(&start.naive_utc()).local_minus_utc());
+            let app_name: String = row.get(0)?;
+            let state: String = row.get(1)?;
+            let window_title = row.get::<_, Option<String>>(4)?.unwrap_or_default();
+            let window_class = row.get::<_, Option<String>>(5)?.unwrap_or_default();
+            for (hour, duration) in split_by_hour(start, end) {
+                let key = (hour, app_name.clone(), state.clone(), window_title.clone(), window_class.clone(), utc_offset);
+                *totals.entry(key).or_insert(0) += duration;
+            }
+        }
+    }
+
+    {
+        let mut insert = tx.prepare(
+            "INSERT INTO usage_hourly (bucket_start, app_name, state, window_title, window_class, utc_offset, duration)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+             ON CONFLICT DO UPDATE SET duration = duration + excluded.duration"
+        )?;
+        for ((hour, app_name, state, window_title, window_class, utc_offset), duration) in totals {
+            insert.execute((format_timestamp(hour), app_name, state, window_title, window_class, utc_offset, duration as i64))?;
+        }
+    }
+    Ok(tx.execute("DELETE FROM app_sessions WHERE end_time <= ?1", [&cutoff])?)
+}
+
+// 把 cutoff 之前结束的整点汇总按本地日期累加到 usage_daily 后删除
+fn roll_up_hours(tx: &Transaction, cutoff: DateTime<Utc>) -> Result<usize, StorageError> {
+    let cutoff = format_timestamp(cutoff - Duration::hours(1));
+    let mut totals: HashMap<RollupKey, u64> = HashMap::new();
+    {
+        let mut stmt = tx.prepare(
+            "SELECT bucket_start, app_name, state, window_class, utc_offset, duration
+             FROM usage_hourly WHERE bucket_start <= ?1"
+        )?;
+        let mut rows = stmt.query([&cutoff])?;
+        while let Some(row) = rows.next()? {
+            let bucket_start: String = row.get(0)?;
+            let Some(hour) = parse_timestamp(&bucket_start) else {
+                tracing::warn!("Dropping hourly row with invalid bucket: {}", bucket_start);
+                continue;
+            };
+            let utc_offset: i32 = row.get(4)?;
+            let key = (local_day_start(hour, utc_offset), row.get(1)?, row.get(2)?, String::new(), row.get(3)?, utc_offset);
+            *totals.entry(key).or_insert(0) += row.get::<_, i64>(5)? as u64;
+        }
+    }
+
+    {
+        let mut insert = tx.prepare(
+            "INSERT INTO usage_daily (bucket_start, app_name, state, window_class, utc_offset, duration)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+             ON CONFLICT DO UPDATE SET duration = duration + excluded.duration"
+        )?;
+        for ((day, app_name, state, _, window_class, utc_offset), duration) in totals {
+            insert.execute((format_timestamp(day), app_name, state, window_class, utc_offset, duration as i64))?;
+        }
+    }
+    Ok(tx.execute("DELETE FROM usage_hourly WHERE bucket_start <= ?1", [&cutoff])?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::types::{AppUsageRecord, AppUsageStats, Granularity, UsageState};
+    use chrono::NaiveDate;
+
+    fn utc(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(value).unwrap().with_timezone(&Utc)
+    }
+
+    fn record(start: &str, app_name: &str, title: &str, duration: u64, state: UsageState) -> AppUsageRecord {
+        AppUsageRecord {
+            timestamp: utc(start),
+            app_name: app_name.to_string(),
+            duration,
+            state,
+            window_title: Some(title.to_string()),
+            window_class: None,
+            exe_path: None,
+            pid: None,
+            utc_offset: Some(0),
+        }
+    }
+
+    fn query(from: &str, to: &str, granularity: Granularity) -> UsageQuery {
+        UsageQuery {
+            from: utc(from),
+            to: utc(to),
+            granularity,
+            apps: Vec::new(),
+            limit: None,
+            time_zone: Some("UTC".to_string()),
+        }
+    }
+
+    type StatsSummary = Vec<(String, u64, Vec<(NaiveDate, u64)>)>;
+
+    fn summary(stats: &[AppUsageStats]) -> StatsSummary {
+        stats.iter()
+            .map(|s| (s.name.clone(), s.total_time, s.daily_usage.iter().map(|d| (d.date, d.duration)).collect()))
+            .collect()
+    }
+
+    fn count(storage: &Storage, table: &str) -> i64 {
+        storage.conn.query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| row.get(0)).unwrap()
+    }
+
+    fn seed(storage: &Storage) {
+        let records = [
+            // 跨整点和跨天的会话
+            record("2026-01-10T23:30:00Z", "editor", "main.rs", 5400, UsageState::Active),
+            record("2026-01-11T01:00:00Z", "browser", "docs", 600, UsageState::Active),
+            record("2026-01-11T01:10:00Z", "browser", "docs", 300, UsageState::Idle),
+            record("2026-03-01T09:00:00Z", "editor", "lib.rs", 1200, UsageState::Active),
+        ];
+        for record in records {
+            storage.record_usage(record).unwrap();
+        }
+    }
+
+    // 1. 汇总前后的统计结果相同
+    #[test]
+    fn test_rollups_keep_stats() {
+        let storage = Storage::open_in_memory().unwrap();
+        seed(&storage);
+        let daily = query("2026-01-01T00:00:00Z", "2026-04-01T00:00:00Z", Granularity::Day);
+        let monthly = query("2026-01-01T00:00:00Z", "2026-04-01T00:00:00Z", Granularity::Month);
+        let before = (storage.get_usage_stats_range(&daily).unwrap(), storage.get_usage_stats_range(&monthly).unwrap());
+
+        storage.set_retention_policy(&RetentionPolicy { raw_days: 7, hourly_days: 30, delete_after_months: None }).unwrap();
+        let report = storage.run_maintenance(utc("2026-03-01T12:00:00Z")).unwrap();
+        assert_eq!(report, MaintenanceReport { sessions_rolled_up: 3, hourly_rolled_up: 4, deleted: 0 });
+        // 最近的会话保留原样
+        assert_eq!(count(&storage, "app_sessions"), 1);
+        assert_eq!(count(&storage, "usage_hourly"), 0);
+        assert_eq!(count(&storage, "usage_daily"), 4);
+
+        let after = (storage.get_usage_stats_range(&daily).unwrap(), storage.get_usage_stats_range(&monthly).unwrap());
+        assert_eq!(summary(&after.0), summary(&before.0));
+        assert_eq!(summary(&after.1), summary(&before.1));
+        assert_eq!(after.0[0].total_time, 5400 + 1200);
+
+        // 再次维护不会重复累加
+        assert_eq!(storage.run_maintenance(utc("2026-03-01T12:00:00Z")).unwrap(), MaintenanceReport::default());
+        assert_eq!(summary(&storage.get_usage_stats_range(&daily).unwrap()), summary(&before.0));
+    }
+
+    // 2. 小时汇总保留窗口标题，查询区间按小时裁剪
+    #[test]
+    fn test_hourly_rollups_keep_titles() {
+        let storage = Storage::open_in_memory().unwrap();
+        seed(&storage);
+        storage.set_retention_policy(&RetentionPolicy { raw_days: 7, hourly_days: 365, delete_after_months: None }).unwrap();
+        storage.run_maintenance(utc("2026-03-01T12:00:00Z")).unwrap();
+        assert_eq!(count(&storage, "usage_hourly"), 4);
+
+        let stats = storage.get_usage_stats_range(&query("2026-01-11T00:00:00Z", "2026-01-11T02:00:00Z", Granularity::Day)).unwrap();
+        let editor = stats.iter().find(|s| s.name == "editor").unwrap();
+        assert_eq!(editor.total_time, 3600);
+        assert_eq!(editor.titles[0].title, "main.rs");
+        // 空闲时间不计入
+        assert_eq!(stats.iter().find(|s| s.name == "browser").unwrap().total_time, 600);
+    }
+
+    // 3. 超过删除期限的数据从所有表中删除
+    #[test]
+    fn test_delete_after_months() {
+        let storage = Storage::open_in_memory().unwrap();
+        seed(&storage);
+        storage.set_retention_policy(&RetentionPolicy { raw_days: 1, hourly_days: 1, delete_after_months: Some(2) }).unwrap();
+        let report = storage.run_maintenance(utc("2026-03-20T00:00:00Z")).unwrap();
+        assert_eq!(report.deleted, 3);
+        assert_eq!(report.sessions_rolled_up, 1);
+
+        let stats = storage.get_usage_stats_range(&query("2026-01-01T00:00:00Z", "2026-04-01T00:00:00Z", Granularity::Month)).unwrap();
+        assert_eq!(summary(&stats), vec![("editor".to_string(), 1200, vec![(NaiveDate::from_ymd_opt(2026, 3, 1).unwrap(), 1200)])]);
+    }
+
+    // 4. 无效的策略被拒绝，关闭删除后设置被清除
+    #[test]
+    fn test_retention_policy_validation() {
+        let storage = Storage::open_in_memory().unwrap();
+        assert_eq!(storage.get_retention_policy().unwrap(), RetentionPolicy::default());
+        for policy in [
+            RetentionPolicy { raw_days: 0, hourly_days: 30, delete_after_months: None },
+            RetentionPolicy { raw_days: 30, hourly_days: 7, delete_after_months: None },
+            RetentionPolicy { raw_days: 7, hourly_days: 30, delete_after_months: Some(0) },
+        ] {
+            assert!(matches!(storage.set_retention_policy(&policy), Err(StorageError::InvalidQuery(_))));
+        }
+
+        let policy = RetentionPolicy { raw_days: 7, hourly_days: 30, delete_after_months: Some(24) };
+        storage.set_retention_policy(&policy).unwrap();
+        assert_eq!(storage.get_retention_policy().unwrap(), policy);
+        storage.set_retention_policy(&RetentionPolicy { delete_after_months: None, ..policy }).unwrap();
+        assert_eq!(storage.get_retention_policy().unwrap().delete_after_months, None);
+    }
+}
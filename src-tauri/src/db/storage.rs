@@ -1,16 +1,37 @@
+use std::fs;
 use std::io;
 use std::fmt;
 use rusqlite;
-use chrono::{DateTime, Utc};
-use rusqlite::{Connection, Result};
-use tauri::{AppHandle, Manager};
+use chrono::{DateTime, Datelike, Duration, FixedOffset, Local, NaiveDate, SecondsFormat, TimeZone, Utc};
+use chrono_tz::Tz;
+use rusqlite::{Connection, OptionalExtension, Result, Transaction};
 use std::collections::HashMap;
-use super::types::{AppUsageRecord, AppUsageStats, DailyUsage};
+use std::path::{Path, PathBuf};
+use super::encryption::{self, DatabaseKey};
+use super::timezone::{local_midnight, parse_zone_setting, system_time_zone, ZoneSetting};
+use super::types::{AppUsageRecord, AppUsageStats, DailyUsage, Granularity, SessionRecord, TitleUsage, UsageQuery, UsageState};
+
+const TIME_ZONE_KEY: &str = "time_zone";
+
+pub const DATABASE_FILE: &str = "usage_stats.db";
+// 与 tauri.conf.json 中的 identifier 一致，Tauri 的 app_data_dir 就是 数据目录/identifier
+const APP_IDENTIFIER: &str = "com.time-whisper.dev";
+
+// 与上一个会话的结束时间相差不超过该秒数时，视为同一个会话的延续
+const SESSION_GAP_TOLERANCE_SECS: i64 = 2;
 
 #[derive(Debug)]
 pub enum StorageError {
     Io(io::Error),
     Sqlite(rusqlite::Error),
+    // 数据库由更新版本的程序创建，无法安全打开
+    UnsupportedSchemaVersion { found: i32, supported: i32 },
+    InvalidQuery(String),
+    InvalidTimeZone(String),
+    InvalidCategory(String),
+    NotFound(String),
+    // 数据库加密不可用、密钥缺失或密钥错误
+    Encryption(String),
 }
 
 impl From<io::Error> for StorageError {
@@ -30,136 +51,773 @@ impl fmt::Display for StorageError {
         match self {
             StorageError::Io(err) => write!(f, "IO Error: {}", err),
             StorageError::Sqlite(err) => write!(f, "SQLite Error: {}", err),
+            StorageError::UnsupportedSchemaVersion { found, supported } => write!(
+                f,
+                "Database schema version {} is newer than the supported version {}, please upgrade Time Whisper",
+                found, supported
+            ),
+            StorageError::InvalidQuery(msg) => write!(f, "Invalid query: {}", msg),
+            StorageError::InvalidTimeZone(name) => write!(f, "Unknown time zone: {}", name),
+            StorageError::InvalidCategory(msg) => write!(f, "Invalid category: {}", msg),
+            StorageError::NotFound(what) => write!(f, "Not found: {}", what),
+            StorageError::Encryption(msg) => write!(f, "Encryption error: {}", msg),
+        }
+    }
+}
+
+pub(super) fn format_timestamp(timestamp: DateTime<Utc>) -> String {
+    timestamp.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+pub(super) fn parse_timestamp(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value)
+        .ok()
+        .map(|t| t.with_timezone(&Utc))
+}
+
+// 数据库结构版本记录在 PRAGMA user_version 中，每个迁移把数据库从上一版本升级到 version
+struct Migration {
+    version: i32,
+    description: &'static str,
+    apply: fn(&Transaction) -> Result<(), StorageError>,
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration { version: 1, description: "create app_usage table", apply: migrate_v1_app_usage },
+    Migration { version: 2, description: "add idle state and settings", apply: migrate_v2_idle_state },
+    Migration { version: 3, description: "collapse app_usage into sessions", apply: migrate_v3_sessions },
+    Migration { version: 4, description: "add window details to sessions", apply: migrate_v4_window_details },
+    Migration { version: 5, description: "index sessions by end time", apply: migrate_v5_end_time_index },
+    Migration { version: 6, description: "store utc offset with sessions", apply: migrate_v6_utc_offset },
+    Migration { version: 7, description: "add categories and matching rules", apply: migrate_v7_categories },
+    Migration { version: 8, description: "add daily usage limits", apply: migrate_v8_usage_limits },
+    Migration { version: 9, description: "add focus sessions", apply: migrate_v9_focus_sessions },
+    Migration { version: 10, description: "add hourly and daily usage rollups", apply: migrate_v10_usage_rollups },
+    Migration { version: 11, description: "record tracking pauses", apply: migrate_v11_tracking_pauses },
+    Migration { version: 12, description: "add privacy rules", apply: migrate_v12_privacy_rules },
+];
+
+pub const SCHEMA_VERSION: i32 = MIGRATIONS.len() as i32;
+
+fn has_table(conn: &Connection, table: &str) -> Result<bool, StorageError> {
+    let count: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ?1",
+        [table],
+        |row| row.get(0),
+    )?;
+    Ok(count > 0)
+}
+
+fn has_column(conn: &Connection, table: &str, column: &str) -> Result<bool, StorageError> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let names = stmt.query_map([], |row| row.get::<_, String>(1))?;
+    for name in names {
+        if name? == column {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+// 引入版本号之前创建的数据库 user_version 都是 0，根据表结构推断实际版本
+fn detect_legacy_version(conn: &Connection) -> Result<i32, StorageError> {
+    if has_table(conn, "app_sessions")? {
+        Ok(3)
+    } else if has_table(conn, "app_usage")? {
+        if has_column(conn, "app_usage", "state")? { Ok(2) } else { Ok(1) }
+    } else {
+        Ok(0)
+    }
+}
+
+fn migrate(conn: &mut Connection) -> Result<(), StorageError> {
+    let mut version: i32 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    if version == 0 {
+        version = detect_legacy_version(conn)?;
+    }
+
+    if version > SCHEMA_VERSION {
+        return Err(StorageError::UnsupportedSchemaVersion {
+            found: version,
+            supported: SCHEMA_VERSION,
+        });
+    }
+
+    for migration in MIGRATIONS.iter().filter(|m| m.version > version) {
+        tracing::info!("Applying migration v{}: {}", migration.version, migration.description);
+        let tx = conn.transaction()?;
+        (migration.apply)(&tx)?;
+        tx.pragma_update(None, "user_version", migration.version)?;
+        tx.commit()?;
+    }
+
+    // 旧数据库推断出版本后也要写回
+    conn.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+    Ok(())
+}
+
+fn migrate_v1_app_usage(tx: &Transaction) -> Result<(), StorageError> {
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS app_usage (
+            id INTEGER PRIMARY KEY,
+            timestamp TEXT NOT NULL,
+            app_name TEXT NOT NULL,
+            duration INTEGER NOT NULL
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+fn migrate_v2_idle_state(tx: &Transaction) -> Result<(), StorageError> {
+    // 原有记录都视为活跃时间
+    tx.execute(
+        "ALTER TABLE app_usage ADD COLUMN state TEXT NOT NULL DEFAULT 'active'",
+        [],
+    )?;
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS settings (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+// 查询统计时从数据库读出的一段会话
+pub(super) struct SessionSpan {
+    pub(super) app_name: String,
+    pub(super) window_title: Option<String>,
+    pub(super) window_class: Option<String>,
+    pub(super) utc_offset: Option<i32>,
+    pub(super) start: DateTime<Utc>,
+    pub(super) end: DateTime<Utc>,
+}
+
+// 会话及其在各时间桶中的时长
+pub(super) type BucketedSpan = (SessionSpan, Vec<(NaiveDate, u64)>);
+
+pub(super) fn validate_query(query: &UsageQuery) -> Result<(), StorageError> {
+    if query.from >= query.to {
+        return Err(StorageError::InvalidQuery(format!(
+            "from ({}) must be earlier than to ({})",
+            query.from, query.to
+        )));
+    }
+    if query.limit == Some(0) {
+        return Err(StorageError::InvalidQuery("limit must be greater than 0".to_string()));
+    }
+    if query.apps.iter().any(|app| app.trim().is_empty()) {
+        return Err(StorageError::InvalidQuery("app names must not be empty".to_string()));
+    }
+    Ok(())
+}
+
+// 时间点所在的本地时间桶：返回桶的起始日期和下一个桶开始的 UTC 时间
+fn local_bucket<Z: TimeZone>(tz: &Z, timestamp: DateTime<Utc>, granularity: Granularity) -> (NaiveDate, DateTime<Utc>) {
+    let date = timestamp.with_timezone(tz).date_naive();
+    let start = match granularity {
+        Granularity::Day => date,
+        // ISO 周从周一开始
+        Granularity::Week => date - Duration::days(date.weekday().num_days_from_monday() as i64),
+        Granularity::Month => NaiveDate::from_ymd_opt(date.year(), date.month(), 1).unwrap(),
+    };
+    let next = match granularity {
+        Granularity::Day => start + Duration::days(1),
+        Granularity::Week => start + Duration::weeks(1),
+        Granularity::Month => {
+            let (year, month) = if start.month() == 12 {
+                (start.year() + 1, 1)
+            } else {
+                (start.year(), start.month() + 1)
+            };
+            NaiveDate::from_ymd_opt(year, month, 1).unwrap()
+        }
+    };
+    (start, local_midnight(tz, next))
+}
+
+// 把 [start, end) 按本地时间桶拆开
+fn split_by_bucket<Z: TimeZone>(
+    tz: &Z,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    granularity: Granularity,
+) -> Vec<(NaiveDate, u64)> {
+    let mut parts = Vec::new();
+    let mut cursor = start;
+    while cursor < end {
+        let (bucket, next) = local_bucket(tz, cursor, granularity);
+        let next = next.min(end);
+        parts.push((bucket, (next - cursor).num_seconds() as u64));
+        cursor = next;
+    }
+    parts
+}
+
+// 桌面应用使用的数据库位置，不依赖 AppHandle
+pub fn default_database_path() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join(APP_IDENTIFIER).join(DATABASE_FILE))
+}
+
+// 用 staged 替换数据库文件，原文件的 WAL 和共享内存文件不能用于新文件
+pub(super) fn replace_database(staged: &Path, path: &Path) -> Result<(), StorageError> {
+    for suffix in ["-wal", "-shm"] {
+        remove_if_exists(&with_suffix(path, suffix))?;
+    }
+    fs::rename(staged, path)?;
+    Ok(())
+}
+
+pub(super) fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+pub(super) fn remove_if_exists(path: &Path) -> Result<(), StorageError> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+// 与查询区间 [from, to) 有重叠的会话的过滤条件及参数
+fn overlap_filter(query: &UsageQuery) -> (String, Vec<String>) {
+    let filter = String::from("end_time > ?1 AND start_time < ?2");
+    let params: Vec<String> = vec![format_timestamp(query.from), format_timestamp(query.to)];
+    with_app_filter(query, filter, params)
+}
+
+// 查询指定了应用时在前两个参数之后追加 app_name 条件
+pub(super) fn with_app_filter(query: &UsageQuery, mut filter: String, mut params: Vec<String>) -> (String, Vec<String>) {
+    if !query.apps.is_empty() {
+        let placeholders: Vec<String> = (0..query.apps.len())
+            .map(|i| format!("?{}", i + 3))
+            .collect();
+        filter.push_str(&format!(" AND app_name IN ({})", placeholders.join(", ")));
+        params.extend(query.apps.iter().cloned());
+    }
+    (filter, params)
+}
+
+fn session_from_row(row: &rusqlite::Row) -> Result<Option<SessionRecord>, StorageError> {
+    let state: String = row.get(1)?;
+    let start_time: String = row.get(2)?;
+    let end_time: String = row.get(3)?;
+    let (state, start, end) = match (
+        UsageState::parse(&state),
+        parse_timestamp(&start_time),
+        parse_timestamp(&end_time),
+    ) {
+        (Some(state), Some(start), Some(end)) => (state, start, end),
+        _ => {
+            tracing::warn!("Skipping invalid session: {} {} - {}", state, start_time, end_time);
+            return Ok(None);
+        }
+    };
+    Ok(Some(SessionRecord {
+        app_name: row.get(0)?,
+        state,
+        start_time: start,
+        end_time: end,
+        duration: (end - start).num_seconds().max(0) as u64,
+        window_title: row.get(4)?,
+        window_class: row.get(5)?,
+        exe_path: row.get(6)?,
+        pid: row.get(7)?,
+        utc_offset: row.get(8)?,
+    }))
+}
+
+// 迁移过程中正在合并的会话
+struct PendingSession {
+    app_name: String,
+    state: String,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+}
+
+fn migrate_v3_sessions(tx: &Transaction) -> Result<(), StorageError> {
+    tx.execute(
+        "CREATE TABLE app_sessions (
+            id INTEGER PRIMARY KEY,
+            app_name TEXT NOT NULL,
+            state TEXT NOT NULL DEFAULT 'active',
+            start_time TEXT NOT NULL,
+            end_time TEXT NOT NULL
+        )",
+        [],
+    )?;
+    tx.execute(
+        "CREATE INDEX idx_app_sessions_start ON app_sessions (start_time)",
+        [],
+    )?;
+
+    // 每秒一行的 app_usage 合并为会话后删除
+    let mut sessions = Vec::new();
+    {
+        let mut stmt = tx.prepare(
+            "SELECT timestamp, app_name, duration, state FROM app_usage ORDER BY timestamp, id"
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, i64>(2)?,
+                row.get::<_, String>(3)?,
+            ))
+        })?;
+
+        let mut current: Option<PendingSession> = None;
+        for row in rows {
+            let (timestamp, app_name, duration, state) = row?;
+            let start = match parse_timestamp(&timestamp) {
+                Some(start) => start,
+                None => {
+                    tracing::warn!("Skipping app_usage row with invalid timestamp: {}", timestamp);
+                    continue;
+                }
+            };
+            let end = start + Duration::seconds(duration.max(0));
+
+            if let Some(session) = current.as_mut() {
+                if session.app_name == app_name
+                    && session.state == state
+                    && (start - session.end).num_seconds() <= SESSION_GAP_TOLERANCE_SECS
+                {
+                    session.end = session.end.max(end);
+                    continue;
+                }
+            }
+            if let Some(session) = current.replace(PendingSession { app_name, state, start, end }) {
+                sessions.push(session);
+            }
         }
+        sessions.extend(current);
+    }
+
+    for session in &sessions {
+        tx.execute(
+            "INSERT INTO app_sessions (app_name, state, start_time, end_time) VALUES (?1, ?2, ?3, ?4)",
+            (
+                &session.app_name,
+                &session.state,
+                format_timestamp(session.start),
+                format_timestamp(session.end),
+            ),
+        )?;
     }
+    tx.execute("DROP TABLE app_usage", [])?;
+
+    tracing::info!("Migrated app_usage into {} sessions", sessions.len());
+    Ok(())
+}
+
+fn migrate_v4_window_details(tx: &Transaction) -> Result<(), StorageError> {
+    tx.execute_batch(
+        "ALTER TABLE app_sessions ADD COLUMN window_title TEXT;
+         ALTER TABLE app_sessions ADD COLUMN window_class TEXT;
+         ALTER TABLE app_sessions ADD COLUMN exe_path TEXT;
+         ALTER TABLE app_sessions ADD COLUMN pid INTEGER;"
+    )?;
+    Ok(())
+}
+
+fn migrate_v5_end_time_index(tx: &Transaction) -> Result<(), StorageError> {
+    tx.execute(
+        "CREATE INDEX idx_app_sessions_end ON app_sessions (end_time)",
+        [],
+    )?;
+    Ok(())
+}
+
+fn migrate_v6_utc_offset(tx: &Transaction) -> Result<(), StorageError> {
+    // 旧会话的偏移未知，统计时按设置的时区处理
+    tx.execute("ALTER TABLE app_sessions ADD COLUMN utc_offset INTEGER", [])?;
+    Ok(())
+}
+
+fn migrate_v7_categories(tx: &Transaction) -> Result<(), StorageError> {
+    tx.execute_batch(
+        "CREATE TABLE categories (
+            id INTEGER PRIMARY KEY,
+            name TEXT NOT NULL UNIQUE,
+            productivity TEXT NOT NULL DEFAULT 'neutral'
+        );
+        CREATE TABLE category_rules (
+            id INTEGER PRIMARY KEY,
+            category_id INTEGER NOT NULL REFERENCES categories (id),
+            field TEXT NOT NULL,
+            kind TEXT NOT NULL,
+            pattern TEXT NOT NULL,
+            priority INTEGER NOT NULL DEFAULT 0
+        );
+        CREATE INDEX idx_category_rules_category ON category_rules (category_id);
+
+        -- 默认分类，用户可以随意修改
+        INSERT INTO categories (id, name, productivity) VALUES
+            (1, 'Development', 'productive'),
+            (2, 'Communication', 'neutral'),
+            (3, 'Entertainment', 'distracting');
+        INSERT INTO category_rules (category_id, field, kind, pattern) VALUES
+            (1, 'process_name', 'glob', 'code*'),
+            (1, 'window_class', 'glob', 'jetbrains-*'),
+            (1, 'process_name', 'regex', '^(n?vim|emacs|git)$'),
+            (2, 'process_name', 'glob', 'slack*'),
+            (2, 'process_name', 'glob', 'discord*'),
+            (2, 'process_name', 'glob', 'thunderbird*'),
+            (3, 'process_name', 'glob', 'steam*'),
+            (3, 'process_name', 'glob', 'spotify*'),
+            (3, 'window_title', 'glob', '*YouTube*');"
+    )?;
+    Ok(())
+}
+
+fn migrate_v8_usage_limits(tx: &Transaction) -> Result<(), StorageError> {
+    // 每条限额只针对一个应用或一个分类
+    tx.execute_batch(
+        "CREATE TABLE usage_limits (
+            id INTEGER PRIMARY KEY,
+            app_name TEXT UNIQUE,
+            category_id INTEGER UNIQUE REFERENCES categories (id),
+            daily_limit_secs INTEGER NOT NULL,
+            CHECK ((app_name IS NULL) != (category_id IS NULL))
+        );
+        -- 已经发出过的提醒，避免同一天重复通知
+        CREATE TABLE limit_alerts (
+            limit_id INTEGER NOT NULL,
+            date TEXT NOT NULL,
+            threshold INTEGER NOT NULL,
+            PRIMARY KEY (limit_id, date, threshold)
+        );"
+    )?;
+    Ok(())
+}
+
+fn migrate_v9_focus_sessions(tx: &Transaction) -> Result<(), StorageError> {
+    // current_interruption 指向正在进行的打断，回到允许的应用后清空
+    tx.execute_batch(
+        "CREATE TABLE focus_sessions (
+            id INTEGER PRIMARY KEY,
+            start_time TEXT NOT NULL,
+            end_time TEXT,
+            target_secs INTEGER NOT NULL,
+            status TEXT NOT NULL DEFAULT 'active',
+            focused_secs INTEGER NOT NULL DEFAULT 0,
+            interrupted_secs INTEGER NOT NULL DEFAULT 0,
+            current_interruption INTEGER
+        );
+        CREATE TABLE focus_allowed_apps (
+            session_id INTEGER NOT NULL REFERENCES focus_sessions (id),
+            app_name TEXT NOT NULL,
+            PRIMARY KEY (session_id, app_name)
+        );
+        CREATE TABLE focus_interruptions (
+            id INTEGER PRIMARY KEY,
+            session_id INTEGER NOT NULL REFERENCES focus_sessions (id),
+            app_name TEXT NOT NULL,
+            start_time TEXT NOT NULL,
+            end_time TEXT NOT NULL
+        );
+        CREATE INDEX idx_focus_sessions_start_time ON focus_sessions (start_time);
+        CREATE INDEX idx_focus_interruptions_session ON focus_interruptions (session_id);"
+    )?;
+    Ok(())
+}
+
+fn migrate_v10_usage_rollups(tx: &Transaction) -> Result<(), StorageError> {
+    // 超过保留期的会话汇总到这两张表，bucket_start 是小时或本地日期（按 utc_offset）的开始时间。
+    // 按天汇总时不再区分窗口标题
+    tx.execute_batch(
+        "CREATE TABLE usage_hourly (
+            bucket_start TEXT NOT NULL,
+            app_name TEXT NOT NULL,
+            state TEXT NOT NULL,
+            window_title TEXT NOT NULL DEFAULT '',
+            window_class TEXT NOT NULL DEFAULT '',
+            utc_offset INTEGER NOT NULL,
+            duration INTEGER NOT NULL,
+            PRIMARY KEY (bucket_start, app_name, state, window_title, window_class, utc_offset)
+        );
+        CREATE TABLE usage_daily (
+            bucket_start TEXT NOT NULL,
+            app_name TEXT NOT NULL,
+            state TEXT NOT NULL,
+            window_class TEXT NOT NULL DEFAULT '',
+            utc_offset INTEGER NOT NULL,
+            duration INTEGER NOT NULL,
+            PRIMARY KEY (bucket_start, app_name, state, window_class, utc_offset)
+        );"
+    )?;
+    Ok(())
+}
+
+fn migrate_v11_tracking_pauses(tx: &Transaction) -> Result<(), StorageError> {
+    // 暂停期间不记录使用情况，这里记下暂停的时间段，统计中的空白可以和没有使用电脑区分开
+    tx.execute_batch(
+        "CREATE TABLE tracking_pauses (
+            id INTEGER PRIMARY KEY,
+            start_time TEXT NOT NULL,
+            end_time TEXT,
+            resume_at TEXT
+        );
+        CREATE INDEX idx_tracking_pauses_start ON tracking_pauses (start_time);"
+    )?;
+    Ok(())
+}
+
+fn migrate_v12_privacy_rules(tx: &Transaction) -> Result<(), StorageError> {
+    tx.execute_batch(
+        "CREATE TABLE privacy_rules (
+            id INTEGER PRIMARY KEY,
+            field TEXT NOT NULL,
+            kind TEXT NOT NULL,
+            pattern TEXT NOT NULL,
+            action TEXT NOT NULL
+        );
+
+        -- 默认不记录浏览器隐私窗口的标题，用户可以删除
+        INSERT INTO privacy_rules (field, kind, pattern, action) VALUES
+            ('window_title', 'glob', '*Incognito*', 'strip_title'),
+            ('window_title', 'glob', '*InPrivate*', 'strip_title'),
+            ('window_title', 'glob', '*Private Browsing*', 'strip_title');"
+    )?;
+    Ok(())
 }
 
 pub struct Storage {
-    conn: Connection,
+    pub(super) conn: Connection,
 }
 
 impl Storage {
-    pub fn new(app_handle: &AppHandle) -> Result<Self, StorageError> {
-        let data_dir = app_handle
-            .path().app_data_dir()
-            .expect("Failed to get app data dir");
-        
-        tracing::info!("Creating data directory: {:?}", data_dir);
-        std::fs::create_dir_all(&data_dir)?;
-        
-        let db_path = data_dir.join("usage_stats.db");
-        tracing::info!("Database path: {:?}", db_path);
-        
-        let conn = Connection::open(&db_path)?;
-        tracing::info!("Database connection established");
-        
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS app_usage (
-                id INTEGER PRIMARY KEY,
-                timestamp TEXT NOT NULL,
-                app_name TEXT NOT NULL,
-                duration INTEGER NOT NULL
-            )",
-            [],
-        )?;
-        tracing::info!("Database table created/verified");
+    // Tauri 中的数据库路径见 app_storage，这里只负责打开给定的文件
+    pub fn open(path: &Path) -> Result<Self, StorageError> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        tracing::debug!("Database path: {:?}", path);
+
+        let key = encryption::database_key(path)?;
+        Self::open_with_key(path, key.as_ref())
+    }
+
+    // key 为空时按未加密的数据库打开
+    pub fn open_with_key(path: &Path, key: Option<&DatabaseKey>) -> Result<Self, StorageError> {
+        let conn = Connection::open(path)?;
+        if let Some(key) = key {
+            encryption::apply_key(&conn, key)?;
+        }
+        tracing::debug!("Database connection established");
+
+        Self::with_connection(conn)
+    }
+
+    // 仅存在于内存中，供测试和一次性的计算使用
+    pub fn open_in_memory() -> Result<Self, StorageError> {
+        Self::with_connection(Connection::open_in_memory()?)
+    }
+
+    fn with_connection(mut conn: Connection) -> Result<Self, StorageError> {
+        // 监控写入和界面查询使用不同的连接，遇到锁时等待而不是立即失败
+        conn.busy_timeout(std::time::Duration::from_secs(5))?;
+        // WAL 模式下读写互不阻塞，内存数据库会保持 memory 模式
+        let journal_mode: String = conn.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get(0))?;
+        if journal_mode.eq_ignore_ascii_case("wal") {
+            conn.pragma_update(None, "synchronous", "NORMAL")?;
+        }
+        migrate(&mut conn)?;
+        tracing::debug!("Database schema at version {}", SCHEMA_VERSION);
         
         Ok(Self { conn })
     }
     
     pub fn record_usage(&self, record: AppUsageRecord) -> Result<(), StorageError> {
-        tracing::info!("Recording usage for: {} at {}", record.app_name, record.timestamp);
+        tracing::debug!("Recording {} usage for: {} at {}", record.state.as_str(), record.app_name, record.timestamp);
+        let start = record.timestamp;
+        let end = start + Duration::seconds(record.duration as i64);
+        let utc_offset = record.utc_offset
+            .unwrap_or_else(|| Local.offset_from_utc_datetime(&start.naive_utc()).local_minus_utc());
+
+        // 同一窗口仍在前台时延长最近的会话，否则开启新会话
+        let last_session = self.conn.query_row(
+            "SELECT id, app_name, state, end_time, window_title, utc_offset FROM app_sessions ORDER BY id DESC LIMIT 1",
+            [],
+            |row| Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, Option<String>>(4)?,
+                row.get::<_, Option<i32>>(5)?,
+            )),
+        ).optional()?;
+
+        if let Some((id, app_name, state, end_time, window_title, last_offset)) = last_session {
+            if let Some(last_end) = parse_timestamp(&end_time) {
+                let gap = (start - last_end).num_seconds();
+                if app_name == record.app_name
+                    && state == record.state.as_str()
+                    && window_title == record.window_title
+                    && last_offset == Some(utc_offset)
+                    && gap.abs() <= SESSION_GAP_TOLERANCE_SECS
+                {
+                    self.conn.execute(
+                        "UPDATE app_sessions SET end_time = ?1 WHERE id = ?2",
+                        (format_timestamp(last_end.max(end)), id),
+                    )?;
+                    return Ok(());
+                }
+            }
+        }
+
+        self.insert_session(&record, utc_offset)?;
+        tracing::info!("Started new {} session for: {}", record.state.as_str(), record.app_name);
+        Ok(())
+    }
+
+    fn insert_session(&self, record: &AppUsageRecord, utc_offset: i32) -> Result<(), StorageError> {
+        let end = record.timestamp + Duration::seconds(record.duration as i64);
         self.conn.execute(
-            "INSERT INTO app_usage (timestamp, app_name, duration) VALUES (?1, ?2, ?3)",
+            "INSERT INTO app_sessions (app_name, state, start_time, end_time, window_title, window_class, exe_path, pid, utc_offset)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
             (
-                record.timestamp.to_rfc3339(),
                 &record.app_name,
-                record.duration,
+                record.state.as_str(),
+                format_timestamp(record.timestamp),
+                format_timestamp(end),
+                &record.window_title,
+                &record.window_class,
+                &record.exe_path,
+                record.pid,
+                utc_offset,
             ),
         )?;
-        tracing::info!("Usage recorded successfully");
         Ok(())
     }
+
+    // 在一个事务中依次记录，规则与 record_usage 相同
+    pub fn record_batch(&self, records: &[AppUsageRecord]) -> Result<(), StorageError> {
+        let tx = self.conn.unchecked_transaction()?;
+        for record in records {
+            self.record_usage(record.clone())?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    // 导入外部历史记录，每条记录单独成为一个会话，不与正在记录的会话合并。
+    // 与同一应用已有的会话有重叠的记录视为重复并跳过，返回 (插入数, 跳过数)
+    pub fn import_records(&self, mut records: Vec<AppUsageRecord>) -> Result<(usize, usize), StorageError> {
+        records.sort_by_key(|r| r.timestamp);
+        // 导入的数据和本地记录一样先按隐私规则处理
+        let filter = self.privacy_filter()?;
+        let tx = self.conn.unchecked_transaction()?;
+        let mut inserted = 0;
+        let mut skipped = 0;
+        for record in records {
+            // 零时长的事件没有可统计的内容
+            let Some(record) = filter.apply_record(record).filter(|record| record.duration > 0) else {
+                skipped += 1;
+                continue;
+            };
+            let end = record.timestamp + Duration::seconds(record.duration as i64);
+            // 超过保留期的会话已经汇总，同一应用的汇总桶与导入区间重叠也算重复。
+            // 按天的桶从本地日期开始，夏令时切换当天最长 25 小时
+            let duplicate: bool = self.conn.query_row(
+                "SELECT EXISTS(
+                    SELECT 1 FROM app_sessions
+                    WHERE app_name = ?1 AND start_time < ?2 AND end_time > ?3
+                ) OR EXISTS(
+                    SELECT 1 FROM usage_hourly
+                    WHERE app_name = ?1 AND bucket_start < ?2 AND bucket_start > ?4
+                ) OR EXISTS(
+                    SELECT 1 FROM usage_daily
+                    WHERE app_name = ?1 AND bucket_start < ?2 AND bucket_start > ?5
+                )",
+                (
+                    &record.app_name,
+                    format_timestamp(end),
+                    format_timestamp(record.timestamp),
+                    format_timestamp(record.timestamp - Duration::hours(1)),
+                    format_timestamp(record.timestamp - Duration::hours(25)),
+                ),
+                |row| row.get(0),
+            )?;
+            if duplicate {
+                skipped += 1;
+                continue;
+            }
+            let utc_offset = record.utc_offset
+                .unwrap_or_else(|| Local.offset_from_utc_datetime(&record.timestamp.naive_utc()).local_minus_utc());
+            self.insert_session(&record, utc_offset)?;
+            inserted += 1;
+        }
+        tx.commit()?;
+        Ok((inserted, skipped))
+    }
     
     pub fn get_usage_stats(&self, range: &str) -> Result<Vec<AppUsageStats>, StorageError> {
-        let sql = match range {
-            "daily" => "
-                SELECT app_name, 
-                       strftime('%Y-%m-%d', timestamp) as date,
-                       SUM(duration) as daily_duration
-                FROM app_usage 
-                WHERE date(timestamp) = date('now', 'localtime')
-                GROUP BY app_name, date
-                ORDER BY daily_duration DESC
-            ",
-            "3days" => "
-                SELECT app_name, 
-                       strftime('%Y-%m-%d', timestamp) as date,
-                       SUM(duration) as daily_duration
-                FROM app_usage 
-                WHERE timestamp >= datetime('now', '-3 days', 'localtime')
-                GROUP BY app_name, date
-                ORDER BY daily_duration DESC
-            ",
-            "weekly" => "
-                SELECT app_name, 
-                       strftime('%Y-%m-%d', timestamp) as date,
-                       SUM(duration) as daily_duration
-                FROM app_usage 
-                WHERE timestamp >= datetime('now', '-7 days', 'localtime')
-                GROUP BY app_name, date
-                ORDER BY daily_duration DESC
-            ",
-            "monthly" => "
-                SELECT app_name, 
-                       strftime('%Y-%m-%d', timestamp) as date,
-                       SUM(duration) as daily_duration
-                FROM app_usage 
-                WHERE timestamp >= datetime('now', '-30 days', 'localtime')
-                GROUP BY app_name, date
-                ORDER BY daily_duration DESC
-            ",
-            _ => "
-                SELECT app_name, 
-                       strftime('%Y-%m-%d', timestamp) as date,
-                       SUM(duration) as daily_duration
-                FROM app_usage 
-                WHERE date(timestamp) = date('now', 'localtime')
-                GROUP BY app_name, date
-                ORDER BY daily_duration DESC
-            ",
-        };
+        self.get_usage_stats_range(&self.range_query(range)?)
+    }
 
-        let mut stmt = self.conn.prepare(sql)?;
-        let rows = stmt.query_map([], |row| {
-            Ok((
-                row.get::<_, String>(0)?,
-                row.get::<_, String>(1)?,
-                row.get::<_, i64>(2)?,
-            ))
-        })?;
+    // daily/3days/weekly/monthly 对应的查询，截止到当前时间
+    pub fn range_query(&self, range: &str) -> Result<UsageQuery, StorageError> {
+        UsageQuery::from_range(range, Utc::now(), &self.configured_time_zone()?)
+            .ok_or_else(|| StorageError::InvalidQuery(format!("Unknown range: {}", range)))
+    }
+
+    pub fn get_usage_stats_range(&self, query: &UsageQuery) -> Result<Vec<AppUsageStats>, StorageError> {
+        // 按应用和本地时间桶累计，跨桶的会话按边界拆开
+        let mut bucket_totals: HashMap<(String, NaiveDate), u64> = HashMap::new();
+        let mut title_totals: HashMap<(String, String), (Option<String>, u64)> = HashMap::new();
+
+        for (span, parts) in self.bucketed_spans(query)? {
+            let duration: u64 = parts.iter().map(|(_, d)| d).sum();
+            for (bucket, duration) in parts {
+                *bucket_totals.entry((span.app_name.clone(), bucket)).or_insert(0) += duration;
+            }
+
+            if let Some(title) = span.window_title.filter(|t| !t.is_empty()) {
+                let entry = title_totals
+                    .entry((span.app_name.clone(), title))
+                    .or_insert((None, 0));
+                if span.window_class.is_some() {
+                    entry.0 = span.window_class;
+                }
+                entry.1 += duration;
+            }
+        }
         
         // 组织数据
         let mut app_stats: HashMap<String, AppUsageStats> = HashMap::new();
         
-        for row in rows {
-            let (app_name, date_str, duration) = row?;
-            let date = DateTime::parse_from_rfc3339(&format!("{}T00:00:00Z", date_str))
-                .unwrap()
-                .with_timezone(&Utc);
-                
+        for ((app_name, date), duration) in bucket_totals {
             app_stats.entry(app_name.clone())
                 .or_insert_with(|| AppUsageStats {
                     name: app_name.clone(),
                     total_time: 0,
                     daily_usage: Vec::new(),
+                    titles: Vec::new(),
                 })
                 .daily_usage
                 .push(DailyUsage {
                     date,
-                    duration: duration as u64,
+                    duration,
                 });
         }
+
+        // 按窗口标题细分
+        for ((app_name, title), (window_class, duration)) in title_totals {
+            if let Some(stat) = app_stats.get_mut(&app_name) {
+                stat.titles.push(TitleUsage {
+                    title,
+                    window_class,
+                    duration,
+                });
+            }
+        }
         
         // 计算总时长并排序
         let mut stats: Vec<AppUsageStats> = app_stats.into_values()
@@ -167,127 +825,788 @@ impl Storage {
                 stat.total_time = stat.daily_usage.iter()
                     .map(|d| d.duration)
                     .sum();
+                stat.daily_usage.sort_by_key(|d| d.date);
+                stat.titles.sort_by(|a, b| b.duration.cmp(&a.duration).then_with(|| a.title.cmp(&b.title)));
                 stat
             })
             .collect();
             
-        stats.sort_by(|a, b| b.total_time.cmp(&a.total_time));
+        stats.sort_by(|a, b| b.total_time.cmp(&a.total_time).then_with(|| a.name.cmp(&b.name)));
+        if let Some(limit) = query.limit {
+            stats.truncate(limit);
+        }
         
         Ok(stats)
     }
+
+    // 裁剪到查询区间内的活跃会话，以及按查询时区和粒度拆分到各时间桶的时长
+    pub(super) fn bucketed_spans(&self, query: &UsageQuery) -> Result<Vec<BucketedSpan>, StorageError> {
+        validate_query(query)?;
+        let zone = match &query.time_zone {
+            Some(name) => parse_zone_setting(name, self.configured_time_zone()?)
+                .ok_or_else(|| StorageError::InvalidTimeZone(name.clone()))?,
+            None => self.zone_setting()?,
+        };
+
+        let mut result = Vec::new();
+        for span in self.load_active_spans(query)? {
+            let start = span.start.max(query.from);
+            let end = span.end.min(query.to);
+            if end <= start {
+                continue;
+            }
+
+            let parts = match zone {
+                ZoneSetting::Named(tz) => split_by_bucket(&tz, start, end, query.granularity),
+                ZoneSetting::Recorded(fallback) => match span.utc_offset.and_then(FixedOffset::east_opt) {
+                    Some(offset) => split_by_bucket(&offset, start, end, query.granularity),
+                    None => split_by_bucket(&fallback, start, end, query.granularity),
+                },
+            };
+            result.push((span, parts));
+        }
+        Ok(result)
+    }
+
+    // 与查询区间有重叠的活跃会话
+    fn load_active_spans(&self, query: &UsageQuery) -> Result<Vec<SessionSpan>, StorageError> {
+        let (filter, params) = overlap_filter(query);
+        let sql = format!(
+            "SELECT app_name, start_time, end_time, window_title, window_class, utc_offset
+             FROM app_sessions
+             WHERE state = 'active' AND {}",
+            filter
+        );
+
+        let mut stmt = self.conn.prepare(&sql)?;
+        let rows = stmt.query_map(rusqlite::params_from_iter(params), |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, Option<String>>(3)?,
+                row.get::<_, Option<String>>(4)?,
+                row.get::<_, Option<i32>>(5)?,
+            ))
+        })?;
+
+        let mut spans = Vec::new();
+        for row in rows {
+            let (app_name, start_time, end_time, window_title, window_class, utc_offset) = row?;
+            match (parse_timestamp(&start_time), parse_timestamp(&end_time)) {
+                (Some(start), Some(end)) => spans.push(SessionSpan {
+                    app_name,
+                    window_title,
+                    window_class,
+                    utc_offset,
+                    start,
+                    end,
+                }),
+                _ => tracing::warn!("Skipping session with invalid timestamps: {} - {}", start_time, end_time),
+            }
+        }
+        // 已经汇总的旧数据
+        spans.extend(self.load_rollup_spans(query)?);
+        Ok(spans)
+    }
+
+    // 按开始时间依次处理与查询区间有重叠的全部会话（包括空闲），不会一次性读入内存
+    pub fn visit_sessions<E, F>(&self, query: &UsageQuery, mut visit: F) -> Result<(), E>
+    where
+        E: From<StorageError>,
+        F: FnMut(SessionRecord) -> Result<(), E>,
+    {
+        validate_query(query)?;
+        let (filter, params) = overlap_filter(query);
+        let sql = format!(
+            "SELECT app_name, state, start_time, end_time, window_title, window_class, exe_path, pid, utc_offset
+             FROM app_sessions
+             WHERE {}
+             ORDER BY start_time, id",
+            filter
+        );
+
+        let mut stmt = self.conn.prepare(&sql).map_err(StorageError::from)?;
+        let mut rows = stmt.query(rusqlite::params_from_iter(params)).map_err(StorageError::from)?;
+        while let Some(row) = rows.next().map_err(StorageError::from)? {
+            if let Some(record) = session_from_row(row)? {
+                visit(record)?;
+            }
+        }
+        Ok(())
+    }
+
+    // 设置中的时区，未设置时使用系统时区
+    pub(super) fn configured_time_zone(&self) -> Result<Tz, StorageError> {
+        Ok(self.get_setting(TIME_ZONE_KEY)?
+            .and_then(|name| name.parse().ok())
+            .unwrap_or_else(system_time_zone))
+    }
+
+    fn zone_setting(&self) -> Result<ZoneSetting, StorageError> {
+        let value = self.get_setting(TIME_ZONE_KEY)?;
+        let system = system_time_zone();
+        Ok(value
+            .and_then(|name| parse_zone_setting(&name, system))
+            .unwrap_or(ZoneSetting::Named(system)))
+    }
+
+    pub fn get_time_zone(&self) -> Result<String, StorageError> {
+        Ok(self.get_setting(TIME_ZONE_KEY)?
+            .unwrap_or_else(|| system_time_zone().name().to_string()))
+    }
+
+    // 传入空字符串恢复为系统时区
+    pub fn set_time_zone(&self, name: &str) -> Result<(), StorageError> {
+        if name.is_empty() {
+            self.conn.execute("DELETE FROM settings WHERE key = ?1", [TIME_ZONE_KEY])?;
+            return Ok(());
+        }
+        if parse_zone_setting(name, Tz::UTC).is_none() {
+            return Err(StorageError::InvalidTimeZone(name.to_string()));
+        }
+        self.set_setting(TIME_ZONE_KEY, name)
+    }
+
+    pub fn get_setting(&self, key: &str) -> Result<Option<String>, StorageError> {
+        let mut stmt = self.conn.prepare("SELECT value FROM settings WHERE key = ?1")?;
+        let mut rows = stmt.query([key])?;
+        match rows.next()? {
+            Some(row) => Ok(Some(row.get(0)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn set_setting(&self, key: &str, value: &str) -> Result<(), StorageError> {
+        self.conn.execute(
+            "INSERT INTO settings (key, value) VALUES (?1, ?2)
+             ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+            [key, value],
+        )?;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::db::types::{Granularity, UsageQuery, UsageState};
     use chrono::{Datelike, Duration, Timelike, Utc};
     use rusqlite::Connection;
+    use crate::db::timezone::RECORDED_TIME_ZONE;
 
-    fn setup_test_db() -> Connection {
-        let conn = Connection::open_in_memory().unwrap();
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS app_usage (
-                id INTEGER PRIMARY KEY,
-                timestamp TEXT NOT NULL,
-                app_name TEXT NOT NULL,
-                duration INTEGER NOT NULL
-            )",
-            [],
+    fn setup_test_storage() -> Storage {
+        Storage::open_in_memory().unwrap()
+    }
+
+    fn usage_record(timestamp: DateTime<Utc>, app_name: &str, duration: u64, state: UsageState) -> AppUsageRecord {
+        AppUsageRecord {
+            timestamp,
+            app_name: app_name.to_string(),
+            duration,
+            state,
+            window_title: None,
+            window_class: None,
+            exe_path: None,
+            pid: None,
+            utc_offset: None,
+        }
+    }
+
+    fn session_rows(storage: &Storage) -> Vec<(String, String, String, String)> {
+        let mut stmt = storage.conn.prepare(
+            "SELECT app_name, state, start_time, end_time FROM app_sessions ORDER BY id"
         ).unwrap();
-        conn
+        stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))
+            .unwrap()
+            .map(|r| r.unwrap())
+            .collect()
     }
 
-    // 1. 基础插入和查询测试
+    // 0. 会话存储测试
     #[test]
-    fn test_basic_record_insert() {
-        let conn = setup_test_db();
-        conn.execute(
-            "INSERT INTO app_usage (timestamp, app_name, duration) VALUES (?1, ?2, ?3)",
-            [
-                Utc::now().to_rfc3339(),
-                "test_app".to_string(),
-                3600i64.to_string(),
-            ],
-        ).unwrap();
+    fn test_session_extended_while_app_in_foreground() {
+        let storage = setup_test_storage();
+        let start = Utc::now().with_nanosecond(0).unwrap();
 
-        let count: i64 = conn.query_row(
-            "SELECT COUNT(*) FROM app_usage",
-            [],
-            |row| row.get(0)
-        ).unwrap();
-        assert_eq!(count, 1);
+        // 同一应用连续 60 秒只产生一个会话
+        for i in 0..60 {
+            storage.record_usage(usage_record(start + Duration::seconds(i), "editor", 1, UsageState::Active)).unwrap();
+        }
+
+        let sessions = session_rows(&storage);
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].2, format_timestamp(start));
+        assert_eq!(sessions[0].3, format_timestamp(start + Duration::seconds(60)));
     }
 
     #[test]
-    fn test_multiple_records() {
-        let conn = setup_test_db();
-        let now = Utc::now();
-        
-        // 插入多条记录
+    fn test_session_split_on_focus_change_and_gap() {
+        let storage = setup_test_storage();
+        let start = Utc::now().with_nanosecond(0).unwrap();
+
         let records = vec![
-            (now, "app1", 3600),
-            (now, "app2", 7200),
-            (now, "app3", 1800),
+            (0, "editor", UsageState::Active),
+            (1, "editor", UsageState::Active),
+            (2, "browser", UsageState::Active),   // 切换应用
+            (3, "browser", UsageState::Idle),     // 进入空闲
+            (4, "browser", UsageState::Idle),
+            (60, "browser", UsageState::Idle),    // 中间有间隔
         ];
 
-        for (timestamp, app_name, duration) in records {
+        for (offset, app_name, state) in records {
+            storage.record_usage(usage_record(start + Duration::seconds(offset), app_name, 1, state)).unwrap();
+        }
+
+        let sessions = session_rows(&storage);
+        let summary: Vec<(&str, &str)> = sessions.iter()
+            .map(|s| (s.0.as_str(), s.1.as_str()))
+            .collect();
+        assert_eq!(summary, vec![
+            ("editor", "active"),
+            ("browser", "active"),
+            ("browser", "idle"),
+            ("browser", "idle"),
+        ]);
+    }
+
+    #[test]
+    fn test_record_batch_in_wal_mode() {
+        let path = std::env::temp_dir().join(format!("time-whisper-wal-{}.db", std::process::id()));
+        let writer = Storage::open(&path).unwrap();
+        let journal_mode: String = writer.conn.query_row("PRAGMA journal_mode", [], |row| row.get(0)).unwrap();
+        assert_eq!(journal_mode, "wal");
+
+        // 一批记录与逐条记录的合并规则相同，另一个连接可以读到
+        let start = Utc::now().with_nanosecond(0).unwrap();
+        let records: Vec<_> = (0..10)
+            .map(|i| usage_record(start + Duration::seconds(i), if i < 6 { "editor" } else { "browser" }, 1, UsageState::Active))
+            .collect();
+        writer.record_batch(&records).unwrap();
+
+        let reader = Storage::open(&path).unwrap();
+        let sessions = session_rows(&reader);
+        let summary: Vec<&str> = sessions.iter().map(|s| s.0.as_str()).collect();
+        assert_eq!(summary, vec!["editor", "browser"]);
+
+        drop(writer);
+        drop(reader);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+    }
+
+    #[test]
+    fn test_usage_stats_from_sessions() {
+        let storage = setup_test_storage();
+        let start = Utc::now().with_nanosecond(0).unwrap() - Duration::hours(1);
+
+        let records = vec![
+            (0, "editor", 1800, UsageState::Active),
+            (1800, "browser", 600, UsageState::Active),
+            (2400, "browser", 300, UsageState::Idle),
+        ];
+
+        for (offset, app_name, duration, state) in records {
+            storage.record_usage(usage_record(start + Duration::seconds(offset), app_name, duration, state)).unwrap();
+        }
+
+        let stats = storage.get_usage_stats("weekly").unwrap();
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[0].name, "editor");
+        assert_eq!(stats[0].total_time, 1800);
+        assert_eq!(stats[1].name, "browser");
+        assert_eq!(stats[1].total_time, 600);
+    }
+
+    #[test]
+    fn test_window_titles_tracked_per_session() {
+        let storage = setup_test_storage();
+        let start = Utc::now().with_nanosecond(0).unwrap() - Duration::hours(1);
+
+        let titles = vec![
+            (0, 600, "Docs - Firefox"),
+            (600, 300, "Mail - Firefox"),
+            (900, 600, "Docs - Firefox"),
+        ];
+
+        for (offset, duration, title) in titles {
+            let mut record = usage_record(start + Duration::seconds(offset), "firefox", duration, UsageState::Active);
+            record.window_title = Some(title.to_string());
+            record.window_class = Some("Firefox".to_string());
+            record.pid = Some(4242);
+            storage.record_usage(record).unwrap();
+        }
+
+        // 标题变化时开启新会话
+        assert_eq!(session_rows(&storage).len(), 3);
+
+        let stats = storage.get_usage_stats("weekly").unwrap();
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].total_time, 1500);
+        assert_eq!(stats[0].titles.len(), 2);
+        assert_eq!(stats[0].titles[0].title, "Docs - Firefox");
+        assert_eq!(stats[0].titles[0].duration, 1200);
+        assert_eq!(stats[0].titles[0].window_class.as_deref(), Some("Firefox"));
+        assert_eq!(stats[0].titles[1].title, "Mail - Firefox");
+        assert_eq!(stats[0].titles[1].duration, 300);
+    }
+
+    fn utc(value: &str) -> DateTime<Utc> {
+        parse_timestamp(value).unwrap()
+    }
+
+    fn range_query(from: &str, to: &str, granularity: Granularity) -> UsageQuery {
+        UsageQuery {
+            from: utc(from),
+            to: utc(to),
+            granularity,
+            apps: Vec::new(),
+            limit: None,
+            time_zone: Some("UTC".to_string()),
+        }
+    }
+
+    fn date(value: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(value, "%Y-%m-%d").unwrap()
+    }
+
+    fn seed_sessions(storage: &Storage, sessions: &[(&str, &str, u64)]) {
+        for (start, app_name, duration) in sessions {
+            storage.record_usage(usage_record(utc(start), app_name, *duration, UsageState::Active)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_range_query_by_day_splits_at_midnight() {
+        let storage = setup_test_storage();
+        seed_sessions(&storage, &[
+            ("2026-03-01T23:30:00Z", "editor", 3600),   // 跨越午夜
+            ("2026-03-02T10:00:00Z", "browser", 600),
+            ("2026-03-20T10:00:00Z", "browser", 600),   // 不在区间内
+        ]);
+
+        let query = range_query("2026-03-01T00:00:00Z", "2026-03-16T00:00:00Z", Granularity::Day);
+        let stats = storage.get_usage_stats_range(&query).unwrap();
+
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[0].name, "editor");
+        assert_eq!(stats[0].total_time, 3600);
+        let days: Vec<(NaiveDate, u64)> = stats[0].daily_usage.iter()
+            .map(|d| (d.date, d.duration))
+            .collect();
+        assert_eq!(days, vec![
+            (date("2026-03-01"), 1800),
+            (date("2026-03-02"), 1800),
+        ]);
+        assert_eq!(stats[1].name, "browser");
+        assert_eq!(stats[1].total_time, 600);
+    }
+
+    #[test]
+    fn test_range_query_clips_to_bounds() {
+        let storage = setup_test_storage();
+        seed_sessions(&storage, &[("2026-03-01T09:00:00Z", "editor", 7200)]);
+
+        let query = range_query("2026-03-01T10:00:00Z", "2026-03-01T10:30:00Z", Granularity::Day);
+        let stats = storage.get_usage_stats_range(&query).unwrap();
+        assert_eq!(stats[0].total_time, 1800);
+    }
+
+    #[test]
+    fn test_range_query_by_week_and_month() {
+        let storage = setup_test_storage();
+        seed_sessions(&storage, &[
+            ("2026-01-05T10:00:00Z", "editor", 600),    // 周一
+            ("2026-01-11T10:00:00Z", "editor", 600),    // 同一周的周日
+            ("2026-01-12T10:00:00Z", "editor", 600),    // 下一周
+            ("2026-02-15T10:00:00Z", "editor", 600),
+        ]);
+
+        let query = range_query("2026-01-01T00:00:00Z", "2026-04-01T00:00:00Z", Granularity::Week);
+        let weeks: Vec<(NaiveDate, u64)> = storage.get_usage_stats_range(&query).unwrap()[0]
+            .daily_usage.iter()
+            .map(|d| (d.date, d.duration))
+            .collect();
+        assert_eq!(weeks, vec![
+            (date("2026-01-05"), 1200),
+            (date("2026-01-12"), 600),
+            (date("2026-02-09"), 600),
+        ]);
+
+        let query = range_query("2026-01-01T00:00:00Z", "2026-04-01T00:00:00Z", Granularity::Month);
+        let months: Vec<(NaiveDate, u64)> = storage.get_usage_stats_range(&query).unwrap()[0]
+            .daily_usage.iter()
+            .map(|d| (d.date, d.duration))
+            .collect();
+        assert_eq!(months, vec![
+            (date("2026-01-01"), 1800),
+            (date("2026-02-01"), 600),
+        ]);
+    }
+
+    #[test]
+    fn test_range_query_apps_and_limit() {
+        let storage = setup_test_storage();
+        seed_sessions(&storage, &[
+            ("2026-03-01T09:00:00Z", "editor", 3600),
+            ("2026-03-01T10:00:00Z", "browser", 1800),
+            ("2026-03-01T11:00:00Z", "chat", 600),
+        ]);
+
+        let mut query = range_query("2026-03-01T00:00:00Z", "2026-03-02T00:00:00Z", Granularity::Day);
+        query.apps = vec!["browser".to_string(), "chat".to_string()];
+        let names: Vec<String> = storage.get_usage_stats_range(&query).unwrap()
+            .into_iter().map(|s| s.name).collect();
+        assert_eq!(names, vec!["browser", "chat"]);
+
+        query.apps.clear();
+        query.limit = Some(2);
+        let names: Vec<String> = storage.get_usage_stats_range(&query).unwrap()
+            .into_iter().map(|s| s.name).collect();
+        assert_eq!(names, vec!["editor", "browser"]);
+    }
+
+    #[test]
+    fn test_invalid_queries_rejected() {
+        let storage = setup_test_storage();
+
+        let query = range_query("2026-03-02T00:00:00Z", "2026-03-01T00:00:00Z", Granularity::Day);
+        assert!(matches!(storage.get_usage_stats_range(&query), Err(StorageError::InvalidQuery(_))));
+
+        let mut query = range_query("2026-03-01T00:00:00Z", "2026-03-02T00:00:00Z", Granularity::Day);
+        query.limit = Some(0);
+        assert!(matches!(storage.get_usage_stats_range(&query), Err(StorageError::InvalidQuery(_))));
+
+        query.limit = None;
+        query.apps = vec![" ".to_string()];
+        assert!(matches!(storage.get_usage_stats_range(&query), Err(StorageError::InvalidQuery(_))));
+
+        // 不再静默回退到 daily
+        assert!(matches!(storage.get_usage_stats("yearly"), Err(StorageError::InvalidQuery(_))));
+    }
+
+    fn daily_totals(stats: &[AppUsageStats]) -> Vec<(NaiveDate, u64)> {
+        stats[0].daily_usage.iter().map(|d| (d.date, d.duration)).collect()
+    }
+
+    #[test]
+    fn test_daily_buckets_across_dst_transitions() {
+        let storage = setup_test_storage();
+        // 纽约 2026-03-08 开始夏令时，当天只有 23 小时
+        seed_sessions(&storage, &[("2026-03-08T05:00:00Z", "editor", 86400)]);
+        let mut query = range_query("2026-03-08T00:00:00Z", "2026-03-10T00:00:00Z", Granularity::Day);
+        query.time_zone = Some("America/New_York".to_string());
+        assert_eq!(daily_totals(&storage.get_usage_stats_range(&query).unwrap()), vec![
+            (date("2026-03-08"), 82800),
+            (date("2026-03-09"), 3600),
+        ]);
+
+        // 2026-11-01 结束夏令时，当天有 25 小时
+        let storage = setup_test_storage();
+        seed_sessions(&storage, &[("2026-11-01T04:00:00Z", "editor", 25 * 3600 + 600)]);
+        let mut query = range_query("2026-10-31T00:00:00Z", "2026-11-03T00:00:00Z", Granularity::Day);
+        query.time_zone = Some("America/New_York".to_string());
+        assert_eq!(daily_totals(&storage.get_usage_stats_range(&query).unwrap()), vec![
+            (date("2026-11-01"), 25 * 3600),
+            (date("2026-11-02"), 600),
+        ]);
+    }
+
+    #[test]
+    fn test_daily_buckets_use_local_dates() {
+        let storage = setup_test_storage();
+        // 上海 08:00 之前的 UTC 时间已经是当地的第二天
+        seed_sessions(&storage, &[("2026-03-01T17:00:00Z", "editor", 600)]);
+        let mut query = range_query("2026-03-01T00:00:00Z", "2026-03-03T00:00:00Z", Granularity::Day);
+        assert_eq!(daily_totals(&storage.get_usage_stats_range(&query).unwrap()), vec![
+            (date("2026-03-01"), 600),
+        ]);
+        query.time_zone = Some("Asia/Shanghai".to_string());
+        assert_eq!(daily_totals(&storage.get_usage_stats_range(&query).unwrap()), vec![
+            (date("2026-03-02"), 600),
+        ]);
+
+        query.time_zone = Some("Mars/Olympus_Mons".to_string());
+        assert!(matches!(storage.get_usage_stats_range(&query), Err(StorageError::InvalidTimeZone(_))));
+    }
+
+    #[test]
+    fn test_recorded_offsets_follow_travel() {
+        let storage = setup_test_storage();
+        // 在东京（UTC+9）记录的会话，当地已是 3 月 2 日
+        let mut record = usage_record(utc("2026-03-01T16:00:00Z"), "editor", 600, UsageState::Active);
+        record.utc_offset = Some(9 * 3600);
+        storage.record_usage(record).unwrap();
+        storage.set_time_zone("Europe/London").unwrap();
+
+        let mut query = range_query("2026-03-01T00:00:00Z", "2026-03-03T00:00:00Z", Granularity::Day);
+        query.time_zone = None;
+        assert_eq!(daily_totals(&storage.get_usage_stats_range(&query).unwrap()), vec![
+            (date("2026-03-01"), 600),
+        ]);
+
+        storage.set_time_zone(RECORDED_TIME_ZONE).unwrap();
+        assert_eq!(daily_totals(&storage.get_usage_stats_range(&query).unwrap()), vec![
+            (date("2026-03-02"), 600),
+        ]);
+    }
+
+    #[test]
+    fn test_time_zone_setting() {
+        let storage = setup_test_storage();
+        assert_eq!(storage.get_time_zone().unwrap(), system_time_zone().name());
+
+        storage.set_time_zone("Asia/Tokyo").unwrap();
+        assert_eq!(storage.get_time_zone().unwrap(), "Asia/Tokyo");
+        assert!(matches!(storage.set_time_zone("Nowhere/City"), Err(StorageError::InvalidTimeZone(_))));
+        assert_eq!(storage.get_time_zone().unwrap(), "Asia/Tokyo");
+
+        // 空字符串恢复为系统时区
+        storage.set_time_zone("").unwrap();
+        assert_eq!(storage.get_time_zone().unwrap(), system_time_zone().name());
+    }
+
+    // 0.1 数据库版本迁移测试，夹具对应各历史版本的表结构
+    const FIXTURE_V1: &str = "
+        CREATE TABLE app_usage (
+            id INTEGER PRIMARY KEY,
+            timestamp TEXT NOT NULL,
+            app_name TEXT NOT NULL,
+            duration INTEGER NOT NULL
+        );
+    ";
+
+    const FIXTURE_V2: &str = "
+        CREATE TABLE app_usage (
+            id INTEGER PRIMARY KEY,
+            timestamp TEXT NOT NULL,
+            app_name TEXT NOT NULL,
+            duration INTEGER NOT NULL,
+            state TEXT NOT NULL DEFAULT 'active'
+        );
+        CREATE TABLE settings (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+        );
+        INSERT INTO settings (key, value) VALUES ('idle_threshold_secs', '120');
+    ";
+
+    const FIXTURE_V3: &str = "
+        CREATE TABLE app_sessions (
+            id INTEGER PRIMARY KEY,
+            app_name TEXT NOT NULL,
+            state TEXT NOT NULL DEFAULT 'active',
+            start_time TEXT NOT NULL,
+            end_time TEXT NOT NULL
+        );
+        CREATE INDEX idx_app_sessions_start ON app_sessions (start_time);
+        CREATE TABLE settings (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+        );
+        INSERT INTO app_sessions (app_name, state, start_time, end_time)
+            VALUES ('editor', 'active', '2024-05-01T09:00:00Z', '2024-05-01T10:00:00Z');
+    ";
+
+    fn fixture_db(sql: &str) -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(sql).unwrap();
+        conn
+    }
+
+    fn user_version(conn: &Connection) -> i32 {
+        conn.pragma_query_value(None, "user_version", |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn test_migrations_are_ordered() {
+        for (index, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.version, index as i32 + 1);
+        }
+        assert_eq!(SCHEMA_VERSION, MIGRATIONS.last().unwrap().version);
+    }
+
+    #[test]
+    fn test_migrate_fresh_database() {
+        let storage = setup_test_storage();
+        assert_eq!(user_version(&storage.conn), SCHEMA_VERSION);
+        assert!(has_table(&storage.conn, "app_sessions").unwrap());
+        assert!(has_table(&storage.conn, "settings").unwrap());
+        assert!(!has_table(&storage.conn, "app_usage").unwrap());
+    }
+
+    #[test]
+    fn test_migrate_from_v1() {
+        let conn = fixture_db(FIXTURE_V1);
+        let start = Utc::now().with_nanosecond(0).unwrap();
+        for i in 0..10 {
             conn.execute(
-                "INSERT INTO app_usage (timestamp, app_name, duration) VALUES (?1, ?2, ?3)",
-                [
-                    timestamp.to_rfc3339(),
-                    app_name.to_string(),
-                    duration.to_string(),
-                ],
+                "INSERT INTO app_usage (timestamp, app_name, duration) VALUES (?1, 'editor', 1)",
+                [(start + Duration::seconds(i)).to_rfc3339()],
             ).unwrap();
         }
 
-        // 验证总记录数
-        let count: i64 = conn.query_row(
-            "SELECT COUNT(*) FROM app_usage",
+        let storage = Storage::with_connection(conn).unwrap();
+        assert_eq!(user_version(&storage.conn), SCHEMA_VERSION);
+
+        let sessions = session_rows(&storage);
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].1, "active");
+        assert_eq!(sessions[0].3, format_timestamp(start + Duration::seconds(10)));
+        assert_eq!(storage.get_setting("idle_threshold_secs").unwrap(), None);
+    }
+
+    #[test]
+    fn test_migrate_from_v2() {
+        let conn = fixture_db(FIXTURE_V2);
+        let start = Utc::now().with_nanosecond(0).unwrap();
+
+        // 旧格式：每秒一行
+        let mut rows = Vec::new();
+        for i in 0..30 {
+            rows.push((start + Duration::seconds(i), "editor", "active"));
+        }
+        for i in 30..40 {
+            rows.push((start + Duration::seconds(i), "chat", "active"));
+        }
+        for i in 40..50 {
+            rows.push((start + Duration::seconds(i), "chat", "idle"));
+        }
+        rows.push((start + Duration::seconds(600), "editor", "active"));
+
+        for (timestamp, app_name, state) in rows {
+            conn.execute(
+                "INSERT INTO app_usage (timestamp, app_name, duration, state) VALUES (?1, ?2, 1, ?3)",
+                [timestamp.to_rfc3339(), app_name.to_string(), state.to_string()],
+            ).unwrap();
+        }
+
+        let storage = Storage::with_connection(conn).unwrap();
+        assert_eq!(user_version(&storage.conn), SCHEMA_VERSION);
+        assert!(!has_table(&storage.conn, "app_usage").unwrap());
+
+        let sessions = session_rows(&storage);
+        assert_eq!(sessions.len(), 4);
+        assert_eq!(sessions[0].0, "editor");
+        assert_eq!(sessions[0].3, format_timestamp(start + Duration::seconds(30)));
+        assert_eq!(sessions[1].0, "chat");
+        assert_eq!(sessions[2].1, "idle");
+        assert_eq!(sessions[3].2, format_timestamp(start + Duration::seconds(600)));
+
+        // 合并后总时长不变
+        let total: i64 = storage.conn.query_row(
+            "SELECT SUM(strftime('%s', end_time) - strftime('%s', start_time)) FROM app_sessions",
             [],
             |row| row.get(0)
         ).unwrap();
-        assert_eq!(count, 3);
+        assert_eq!(total, 51);
+
+        // 设置保留
+        assert_eq!(storage.get_setting("idle_threshold_secs").unwrap(), Some("120".to_string()));
+    }
+
+    #[test]
+    fn test_migrate_from_v3() {
+        let storage = Storage::with_connection(fixture_db(FIXTURE_V3)).unwrap();
+        assert_eq!(user_version(&storage.conn), SCHEMA_VERSION);
+
+        let sessions = session_rows(&storage);
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].2, "2024-05-01T09:00:00Z");
+    }
+
+    #[test]
+    fn test_reopen_migrated_database() {
+        let storage = Storage::with_connection(fixture_db(FIXTURE_V1)).unwrap();
+        storage.set_setting("idle_threshold_secs", "60").unwrap();
+
+        // 再次打开不应重复执行迁移
+        let storage = Storage::with_connection(storage.conn).unwrap();
+        assert_eq!(user_version(&storage.conn), SCHEMA_VERSION);
+        assert_eq!(storage.get_setting("idle_threshold_secs").unwrap(), Some("60".to_string()));
+    }
+
+    #[test]
+    fn test_refuse_newer_schema() {
+        let conn = fixture_db(FIXTURE_V3);
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION + 1).unwrap();
+
+        match Storage::with_connection(conn) {
+            Err(StorageError::UnsupportedSchemaVersion { found, supported }) => {
+                assert_eq!(found, SCHEMA_VERSION + 1);
+                assert_eq!(supported, SCHEMA_VERSION);
+            }
+            _ => panic!("expected UnsupportedSchemaVersion"),
+        }
+    }
+
+    #[test]
+    fn test_failed_migration_rolls_back() {
+        // 版本号写着 1，但表里已经有 state 列，v2 迁移会失败
+        let mut conn = fixture_db(FIXTURE_V2);
+        conn.pragma_update(None, "user_version", 1).unwrap();
+
+        assert!(migrate(&mut conn).is_err());
+        assert_eq!(user_version(&conn), 1);
+        assert!(has_table(&conn, "app_usage").unwrap());
+        assert!(!has_table(&conn, "app_sessions").unwrap());
+    }
+
+    // 1. 基础插入和查询测试
+    #[test]
+    fn test_basic_record_insert() {
+        let storage = setup_test_storage();
+        storage.record_usage(usage_record(utc("2026-03-01T09:00:00Z"), "test_app", 3600, UsageState::Active)).unwrap();
+
+        assert_eq!(session_rows(&storage).len(), 1);
+        let query = range_query("2026-03-01T00:00:00Z", "2026-03-02T00:00:00Z", Granularity::Day);
+        let stats = storage.get_usage_stats_range(&query).unwrap();
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].name, "test_app");
+        assert_eq!(stats[0].total_time, 3600);
+    }
+
+    #[test]
+    fn test_multiple_records() {
+        let storage = setup_test_storage();
+        
+        // 插入多条记录
+        seed_sessions(&storage, &[
+            ("2026-03-01T09:00:00Z", "app1", 3600),
+            ("2026-03-01T09:00:00Z", "app2", 7200),
+            ("2026-03-01T09:00:00Z", "app3", 1800),
+        ]);
+
+        // 验证总记录数
+        assert_eq!(session_rows(&storage).len(), 3);
+        let query = range_query("2026-03-01T00:00:00Z", "2026-03-02T00:00:00Z", Granularity::Day);
+        assert_eq!(storage.get_usage_stats_range(&query).unwrap().len(), 3);
     }
 
     // 2. 复杂查询测试
     #[test]
     fn test_daily_aggregation() {
-        let conn = setup_test_db();
-        let now = Utc::now();
+        let storage = setup_test_storage();
         
         // 插入同一天不同时间的记录
-        let records = vec![
-            (now, "app1", 3600),
-            (now + Duration::hours(1), "app1", 1800),
-            (now + Duration::hours(2), "app1", 2400),
-        ];
-
-        for (timestamp, app_name, duration) in records {
-            conn.execute(
-                "INSERT INTO app_usage (timestamp, app_name, duration) VALUES (?1, ?2, ?3)",
-                [
-                    timestamp.to_rfc3339(),
-                    app_name.to_string(),
-                    duration.to_string(),
-                ],
-            ).unwrap();
-        }
+        seed_sessions(&storage, &[
+            ("2026-03-01T08:00:00Z", "app1", 3600),
+            ("2026-03-01T09:00:00Z", "app1", 1800),
+            ("2026-03-01T10:00:00Z", "app1", 2400),
+        ]);
 
         // 验证日统计
-        let total_duration: i64 = conn.query_row(
-            "SELECT SUM(duration) FROM app_usage 
-             WHERE date(timestamp) = date(?1)",
-            [now.to_rfc3339()],
-            |row| row.get(0)
-        ).unwrap();
-        assert_eq!(total_duration, 7800); // 3600 + 1800 + 2400
+        let query = range_query("2026-03-01T00:00:00Z", "2026-03-02T00:00:00Z", Granularity::Day);
+        let stats = storage.get_usage_stats_range(&query).unwrap();
+        assert_eq!(daily_totals(&stats), vec![(date("2026-03-01"), 7800)]); // 3600 + 1800 + 2400
     }
 
     #[test]
     fn test_weekly_stats() {
-        let conn = setup_test_db();
-        let now = Utc::now();
+        let storage = setup_test_storage();
+        // 会话需要在当前时间之前结束才会被完整统计
+        let now = Utc::now() - Duration::hours(3);
         
         // 插入一周内的记录
         let records = vec![
@@ -299,30 +1618,17 @@ mod tests {
         ];
 
         for (timestamp, app_name, duration) in records {
-            conn.execute(
-                "INSERT INTO app_usage (timestamp, app_name, duration) VALUES (?1, ?2, ?3)",
-                [
-                    timestamp.to_rfc3339(),
-                    app_name.to_string(),
-                    duration.to_string(),
-                ],
-            ).unwrap();
+            storage.record_usage(usage_record(timestamp, app_name, duration, UsageState::Active)).unwrap();
         }
 
         // 验证7天内的统计
-        let total_duration: i64 = conn.query_row(
-            "SELECT SUM(duration) FROM app_usage 
-             WHERE timestamp >= datetime(?1, '-7 days')",
-            [now.to_rfc3339()],
-            |row| row.get(0)
-        ).unwrap();
-        assert_eq!(total_duration, 18000); // 所有7天内的记录总和
+        let stats = storage.get_usage_stats("weekly").unwrap();
+        assert_eq!(stats[0].total_time, 18000); // 所有7天内的记录总和
     }
 
     #[test]
     fn test_app_ranking() {
-        let conn = setup_test_db();
-        let now = Utc::now();
+        let storage = setup_test_storage();
         
         // 插入多个应用的使用记录
         let records = vec![
@@ -333,169 +1639,130 @@ mod tests {
             ("app4", 2400),  // 第三
         ];
 
+        let mut current_time = utc("2026-03-01T06:00:00Z");
         for (app_name, duration) in records {
-            conn.execute(
-                "INSERT INTO app_usage (timestamp, app_name, duration) VALUES (?1, ?2, ?3)",
-                [
-                    now.to_rfc3339(),
-                    app_name.to_string(),
-                    duration.to_string(),
-                ],
-            ).unwrap();
+            storage.record_usage(usage_record(current_time, app_name, duration, UsageState::Active)).unwrap();
+            current_time += Duration::seconds(duration as i64);
         }
 
         // 验证应用排名
-        let mut stmt = conn.prepare(
-            "SELECT app_name, SUM(duration) as total_duration 
-             FROM app_usage 
-             GROUP BY app_name 
-             ORDER BY total_duration DESC"
-        ).unwrap();
-
-        let app_stats: Vec<(String, i64)> = stmt.query_map([], |row| {
-            Ok((row.get(0)?, row.get(1)?))
-        })
-        .unwrap()
-        .map(|r| r.unwrap())
-        .collect();
+        let query = range_query("2026-03-01T00:00:00Z", "2026-03-02T00:00:00Z", Granularity::Day);
+        let app_stats: Vec<(String, u64)> = storage.get_usage_stats_range(&query).unwrap()
+            .into_iter()
+            .map(|stat| (stat.name, stat.total_time))
+            .collect();
 
         assert_eq!(app_stats.len(), 4);
-        assert_eq!(app_stats[0].0, "app2");  // 总时长 10800
-        assert_eq!(app_stats[1].0, "app1");  // 总时长 3600
-        assert_eq!(app_stats[2].0, "app4");  // 总时长 2400
-        assert_eq!(app_stats[3].0, "app3");  // 总时长 1800
+        assert_eq!(app_stats[0], ("app2".to_string(), 10800));
+        assert_eq!(app_stats[1], ("app1".to_string(), 3600));
+        assert_eq!(app_stats[2], ("app4".to_string(), 2400));
+        assert_eq!(app_stats[3], ("app3".to_string(), 1800));
+    }
+
+    #[test]
+    fn test_idle_time_excluded() {
+        let storage = setup_test_storage();
+
+        // 同一个应用的活跃时间和空闲时间
+        let records = vec![
+            ("editor", 3600, UsageState::Active),
+            ("editor", 1800, UsageState::Idle),
+            ("browser", 600, UsageState::Idle),
+        ];
+
+        let mut current_time = utc("2026-03-01T09:00:00Z");
+        for (app_name, duration, state) in records {
+            storage.record_usage(usage_record(current_time, app_name, duration, state)).unwrap();
+            current_time += Duration::seconds(duration as i64);
+        }
+
+        // 只统计活跃时间，空闲的 browser 不应出现
+        let query = range_query("2026-03-01T00:00:00Z", "2026-03-02T00:00:00Z", Granularity::Day);
+        let app_stats: Vec<(String, u64)> = storage.get_usage_stats_range(&query).unwrap()
+            .into_iter()
+            .map(|stat| (stat.name, stat.total_time))
+            .collect();
+        assert_eq!(app_stats, vec![("editor".to_string(), 3600)]);
+
+        // 空闲时间仍然保留在数据库中
+        let mut idle_total = 0;
+        storage.visit_sessions(&query, |session| -> Result<(), StorageError> {
+            if session.state == UsageState::Idle {
+                idle_total += session.duration;
+            }
+            Ok(())
+        }).unwrap();
+        assert_eq!(idle_total, 2400);
     }
 
     #[test]
     fn test_time_window_boundaries() {
-        let conn = setup_test_db();
-        let now = Utc::now();
+        let storage = setup_test_storage();
+        let now = utc("2026-03-10T12:00:00Z");
         
         // 插入边界时间的记录
         let records = vec![
-            (now, "app1", 1000),                                      // 现在
-            (now - Duration::days(2), "app1", 2000),                 // 2天前
-            (now - Duration::days(3), "app1", 3000),                 // 3天前
-            (now - Duration::days(3) + Duration::hours(23), "app1", 4000),  // 刚好在3天内
-            (now - Duration::days(3) - Duration::minutes(1), "app1", 5000), // 刚好超过3天
+            (now - Duration::seconds(1000), "app1", 1000),                 // 刚刚结束
+            (now - Duration::days(2), "app1", 2000),                       // 2天前
+            (now - Duration::days(3), "app1", 3000),                       // 刚好从区间起点开始
+            (now - Duration::days(3) + Duration::hours(23), "app1", 4000), // 在3天内
+            (now - Duration::days(3) - Duration::seconds(5000), "app1", 5000), // 刚好在区间起点结束
         ];
 
         for (timestamp, app_name, duration) in records {
-            conn.execute(
-                "INSERT INTO app_usage (timestamp, app_name, duration) VALUES (?1, ?2, ?3)",
-                [
-                    timestamp.to_rfc3339(),
-                    app_name.to_string(),
-                    duration.to_string(),
-                ],
-            ).unwrap();
+            storage.record_usage(usage_record(timestamp, app_name, duration, UsageState::Active)).unwrap();
         }
 
         // 验证3天内的记录
-        let total_duration: i64 = conn.query_row(
-            "SELECT SUM(duration) FROM app_usage 
-             WHERE timestamp >= datetime(?1, '-3 days')",
-            [now.to_rfc3339()],
-            |row| row.get(0)
-        ).unwrap();
-        assert_eq!(total_duration, 10000); // 1000 + 2000 + 3000 + 4000
+        let query = UsageQuery::from_range("3days", now, &Tz::UTC).unwrap();
+        let stats = storage.get_usage_stats_range(&query).unwrap();
+        assert_eq!(stats[0].total_time, 10000); // 1000 + 2000 + 3000 + 4000
     }
 
     #[test]
     fn test_complex_daily_patterns() {
-        let conn = setup_test_db();
-        let now = Utc::now();
+        let storage = setup_test_storage();
         
         // 模拟一天中不同时段的使用模式
-        let patterns = vec![
-            (now.with_hour(9).unwrap(), "work_app", 3600),     // 上午
-            (now.with_hour(13).unwrap(), "lunch_app", 1800),   // 午餐
-            (now.with_hour(15).unwrap(), "work_app", 7200),    // 下午
-            (now.with_hour(19).unwrap(), "game_app", 3600),    // 晚上
-            (now.with_hour(22).unwrap(), "social_app", 1800),  // 深夜
-        ];
-
-        for (timestamp, app_name, duration) in patterns {
-            conn.execute(
-                "INSERT INTO app_usage (timestamp, app_name, duration) VALUES (?1, ?2, ?3)",
-                [
-                    timestamp.to_rfc3339(),
-                    app_name.to_string(),
-                    duration.to_string(),
-                ],
-            ).unwrap();
-        }
-
-        // 按时段统计
-        let mut stmt = conn.prepare(
-            "SELECT 
-                app_name,
-                strftime('%H', timestamp) as hour,
-                SUM(duration) as total_duration
-             FROM app_usage 
-             GROUP BY app_name, hour
-             ORDER BY hour, total_duration DESC"
-        ).unwrap();
-
-        let time_patterns: Vec<(String, String, i64)> = stmt.query_map([], |row| {
-            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
-        })
-        .unwrap()
-        .map(|r| r.unwrap())
-        .collect();
+        seed_sessions(&storage, &[
+            ("2026-03-02T09:00:00Z", "work_app", 3600),     // 上午
+            ("2026-03-02T13:00:00Z", "lunch_app", 1800),   // 午餐
+            ("2026-03-02T15:00:00Z", "work_app", 7200),    // 下午
+            ("2026-03-02T19:00:00Z", "game_app", 3600),    // 晚上
+            ("2026-03-02T22:00:00Z", "social_app", 1800),  // 深夜
+        ]);
 
-        assert_eq!(time_patterns.len(), 5);
+        // 每个时段各是一个会话，同一个应用的时段合并统计
+        assert_eq!(session_rows(&storage).len(), 5);
+        let query = range_query("2026-03-02T00:00:00Z", "2026-03-03T00:00:00Z", Granularity::Day);
+        let stats = storage.get_usage_stats_range(&query).unwrap();
+        assert_eq!(stats.len(), 4);
+        assert_eq!(stats[0].name, "work_app");
+        assert_eq!(stats[0].total_time, 10800);
     }
 
     #[test]
     fn test_monthly_trends() {
-        let conn = setup_test_db();
-        let now = Utc::now();
+        let storage = setup_test_storage();
+        let now = utc("2026-03-31T12:00:00Z");
         
-        // 插入一个月内的使用记录
-        let mut records = Vec::new();
-        for days_ago in 0..30 {
+        // 插入一个月内的使用记录，时间从早到晚
+        for days_ago in (0..30).rev() {
             let timestamp = now - Duration::days(days_ago);
-            records.push((
-                timestamp,
-                "daily_app".to_string(),
-                1800 + (days_ago as i64 * 100), // 时长呈现趋势
-            ));
+            let duration = 1800 + (days_ago as u64 * 100); // 时长呈现趋势
+            storage.record_usage(usage_record(timestamp, "daily_app", duration, UsageState::Active)).unwrap();
         }
 
-        for (timestamp, app_name, duration) in records {
-            conn.execute(
-                "INSERT INTO app_usage (timestamp, app_name, duration) VALUES (?1, ?2, ?3)",
-                [
-                    timestamp.to_rfc3339(),
-                    app_name,
-                    duration.to_string(),
-                ],
-            ).unwrap();
-        }
+        let query = range_query("2026-03-01T00:00:00Z", "2026-04-01T00:00:00Z", Granularity::Day);
+        let stats = storage.get_usage_stats_range(&query).unwrap();
+        assert_eq!(stats[0].daily_usage.len(), 30);
 
         // 按周统计趋势
-        let mut stmt = conn.prepare(
-            "SELECT 
-                strftime('%W', timestamp) as week,
-                AVG(duration) as avg_duration,
-                MIN(duration) as min_duration,
-                MAX(duration) as max_duration
-             FROM app_usage 
-             GROUP BY week
-             ORDER BY week DESC"
-        ).unwrap();
-
-        let weekly_stats: Vec<(String, f64, i64, i64)> = stmt.query_map([], |row| {
-            Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
-        })
-        .unwrap()
-        .map(|r| r.unwrap())
-        .collect();
-
+        let query = range_query("2026-03-01T00:00:00Z", "2026-04-01T00:00:00Z", Granularity::Week);
+        let weekly_stats = daily_totals(&storage.get_usage_stats_range(&query).unwrap());
         assert!(!weekly_stats.is_empty());
         
-        // 验证趋势（每周的平均时长应该呈现变化）
+        // 验证趋势（每周的总时长应该呈现变化）
         for i in 1..weekly_stats.len() {
             assert!(weekly_stats[i-1].1 != weekly_stats[i].1);
         }
@@ -503,76 +1770,56 @@ mod tests {
 
     #[test]
     fn test_usage_patterns() {
-        let conn = setup_test_db();
-        let now = Utc::now();
-        
-        // 模拟一周的使用模式
-        let weekdays = vec!["Mon", "Tue", "Wed", "Thu", "Fri"];
-        let weekend = vec!["Sat", "Sun"];
+        let storage = setup_test_storage();
         
+        // 模拟一周的使用模式，2024-01-01 是周一
         // 工作日模式
-        for day in weekdays {
-            conn.execute(
-                "INSERT INTO app_usage (timestamp, app_name, duration) VALUES 
-                 (?1, 'work_app', 28800),
-                 (?1, 'browser', 3600),
-                 (?1, 'email', 1800)",
-                [format!("2024-01-{} 09:00:00Z", day)],
-            ).unwrap();
+        for day in 1..=5 {
+            let start = utc(&format!("2024-01-{:02}T09:00:00Z", day));
+            seed_sessions(&storage, &[
+                (&format_timestamp(start), "work_app", 28800),
+                (&format_timestamp(start + Duration::hours(8)), "browser", 3600),
+                (&format_timestamp(start + Duration::hours(9)), "email", 1800),
+            ]);
         }
         
         // 周末模式
-        for day in weekend {
-            conn.execute(
-                "INSERT INTO app_usage (timestamp, app_name, duration) VALUES 
-                 (?1, 'game_app', 14400),
-                 (?1, 'browser', 7200),
-                 (?1, 'media_player', 10800)",
-                [format!("2024-01-{} 10:00:00Z", day)],
-            ).unwrap();
+        for day in 6..=7 {
+            let start = utc(&format!("2024-01-{:02}T10:00:00Z", day));
+            seed_sessions(&storage, &[
+                (&format_timestamp(start), "game_app", 14400),
+                (&format_timestamp(start + Duration::hours(4)), "browser", 7200),
+                (&format_timestamp(start + Duration::hours(6)), "media_player", 10800),
+            ]);
         }
 
         // 分析工作日vs周末的使用模式
-        let mut stmt = conn.prepare(
-            "SELECT 
-                app_name,
-                CASE 
-                    WHEN strftime('%w', timestamp) IN ('0', '6') THEN 'weekend'
-                    ELSE 'weekday'
-                END as day_type,
-                AVG(duration) as avg_duration
-             FROM app_usage 
-             GROUP BY app_name, day_type
-             ORDER BY day_type, avg_duration DESC"
-        ).unwrap();
-
-        let patterns: Vec<(String, String, f64)> = stmt.query_map([], |row| {
-            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
-        })
-        .unwrap()
-        .map(|r| r.unwrap())
-        .collect();
+        let query = range_query("2024-01-01T00:00:00Z", "2024-01-08T00:00:00Z", Granularity::Day);
+        let stats = storage.get_usage_stats_range(&query).unwrap();
+        let apps_used_on = |weekend: bool| -> Vec<String> {
+            stats.iter()
+                .filter(|stat| stat.daily_usage.iter().any(|d| (d.date.weekday().num_days_from_monday() >= 5) == weekend))
+                .map(|stat| stat.name.clone())
+                .collect()
+        };
 
         // 验证工作日和周末的使用模式不同
-        let weekday_apps: Vec<_> = patterns.iter()
-            .filter(|p| p.1 == "weekday")
-            .map(|p| &p.0)
-            .collect();
-        let weekend_apps: Vec<_> = patterns.iter()
-            .filter(|p| p.1 == "weekend")
-            .map(|p| &p.0)
-            .collect();
+        let weekday_apps = apps_used_on(false);
+        let weekend_apps = apps_used_on(true);
 
-        assert!(weekday_apps.contains(&&"work_app".to_string()));
-        assert!(weekend_apps.contains(&&"game_app".to_string()));
+        assert!(weekday_apps.contains(&"work_app".to_string()));
+        assert!(!weekday_apps.contains(&"game_app".to_string()));
+        assert!(weekend_apps.contains(&"game_app".to_string()));
+        assert!(!weekend_apps.contains(&"work_app".to_string()));
+        assert!(weekday_apps.contains(&"browser".to_string()) && weekend_apps.contains(&"browser".to_string()));
     }
 
     #[test]
     fn test_concurrent_usage() {
-        let conn = setup_test_db();
-        let now = Utc::now();
+        let storage = setup_test_storage();
+        let now = utc("2026-03-01T09:00:00Z");
         
-        // 模拟同时运行的多个应用
+        // 模拟同时运行的多个应用（例如前端通过 record_app_usage 写入的记录）
         let concurrent_apps = vec![
             ("browser", now, now + Duration::hours(2)),
             ("editor", now, now + Duration::hours(1)),
@@ -581,55 +1828,22 @@ mod tests {
         ];
 
         for (app_name, start_time, end_time) in concurrent_apps {
-            let duration = (end_time - start_time).num_seconds() as i64;
-            conn.execute(
-                "INSERT INTO app_usage (timestamp, app_name, duration) VALUES (?1, ?2, ?3)",
-                [
-                    start_time.to_rfc3339(),
-                    app_name.to_string(),
-                    duration.to_string(),
-                ],
-            ).unwrap();
+            let duration = (end_time - start_time).num_seconds() as u64;
+            storage.record_usage(usage_record(start_time, app_name, duration, UsageState::Active)).unwrap();
         }
 
-        // 分析重叠时间段的应用数量
-        let mut stmt = conn.prepare(
-            "WITH RECURSIVE 
-             time_points AS (
-                SELECT DISTINCT timestamp as point
-                FROM app_usage
-                UNION
-                SELECT datetime(point, '+30 minutes')
-                FROM time_points
-                WHERE datetime(point, '+30 minutes') <= (
-                    SELECT MAX(timestamp) FROM app_usage
-                )
-             )
-             SELECT 
-                t.point,
-                COUNT(DISTINCT a.app_name) as active_apps
-             FROM time_points t
-             LEFT JOIN app_usage a ON datetime(t.point) >= datetime(a.timestamp)
-                AND datetime(t.point) <= datetime(a.timestamp, '+' || (a.duration/60) || ' minutes')
-             GROUP BY t.point
-             ORDER BY t.point"
-        ).unwrap();
-
-        let time_slices: Vec<(String, i64)> = stmt.query_map([], |row| {
-            Ok((row.get(0)?, row.get(1)?))
-        })
-        .unwrap()
-        .map(|r| r.unwrap())
-        .collect();
-
-        // 验证存在同时运行多个应用的时间段
-        assert!(time_slices.iter().any(|slice| slice.1 > 1));
+        // 每个应用各自计时，重叠的时间段会被多个应用重复计入
+        let query = range_query("2026-03-01T00:00:00Z", "2026-03-02T00:00:00Z", Granularity::Day);
+        let stats = storage.get_usage_stats_range(&query).unwrap();
+        assert_eq!(stats.len(), 4);
+        let total: u64 = stats.iter().map(|stat| stat.total_time).sum();
+        assert_eq!(total, 7200 + 3600 + 5400 + 7200);
+        assert!(total > 3 * 3600);
     }
 
     #[test]
     fn test_application_switching() {
-        let conn = setup_test_db();
-        let now = Utc::now();
+        let storage = setup_test_storage();
         
         // 模拟用户在应用间切换的行为
         let switches = vec![
@@ -641,119 +1855,77 @@ mod tests {
             ("browser", 300),    // 5分钟
         ];
 
-        let mut current_time = now;
+        let mut current_time = utc("2026-03-01T09:00:00Z");
         for (app_name, duration) in switches {
-            conn.execute(
-                "INSERT INTO app_usage (timestamp, app_name, duration) VALUES (?1, ?2, ?3)",
-                [
-                    current_time.to_rfc3339(),
-                    app_name.to_string(),
-                    duration.to_string(),
-                ],
-            ).unwrap();
-            current_time = current_time + Duration::seconds(duration);
+            storage.record_usage(usage_record(current_time, app_name, duration, UsageState::Active)).unwrap();
+            current_time += Duration::seconds(duration as i64);
         }
 
-        // 分析应用切换模式
-        let mut stmt = conn.prepare(
-            "WITH ordered_usage AS (
-                SELECT 
-                    app_name,
-                    timestamp,
-                    duration,
-                    LAG(app_name) OVER (ORDER BY timestamp) as prev_app
-                FROM app_usage
-            )
-            SELECT 
-                prev_app,
-                app_name as next_app,
-                COUNT(*) as switch_count
-            FROM ordered_usage
-            WHERE prev_app IS NOT NULL
-            GROUP BY prev_app, next_app
-            ORDER BY switch_count DESC"
-        ).unwrap();
+        // 每次切换都会开启新的会话
+        let sessions = session_rows(&storage);
+        assert_eq!(sessions.len(), 6);
 
-        let switches: Vec<(String, String, i64)> = stmt.query_map([], |row| {
-            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
-        })
-        .unwrap()
-        .map(|r| r.unwrap())
-        .collect();
+        // 分析应用切换模式
+        let mut switch_counts: HashMap<(String, String), usize> = HashMap::new();
+        for pair in sessions.windows(2) {
+            *switch_counts.entry((pair[0].0.clone(), pair[1].0.clone())).or_insert(0) += 1;
+        }
 
-        // 验证应用切换的存在
-        assert!(!switches.is_empty());
-        
         // 验证最频繁的切换
-        let most_frequent = &switches[0];
-        assert_eq!(most_frequent.2, 2); // browser -> editor 出现2次
+        let most_frequent = switch_counts.iter().max_by_key(|(_, count)| **count).unwrap();
+        assert_eq!(most_frequent.0, &("editor".to_string(), "browser".to_string()));
+        assert_eq!(*most_frequent.1, 2); // editor -> browser 出现2次
+
+        let query = range_query("2026-03-01T00:00:00Z", "2026-03-02T00:00:00Z", Granularity::Day);
+        let totals: Vec<(String, u64)> = storage.get_usage_stats_range(&query).unwrap()
+            .into_iter()
+            .map(|stat| (stat.name, stat.total_time))
+            .collect();
+        assert_eq!(totals, vec![
+            ("browser".to_string(), 1800),
+            ("editor".to_string(), 1500),
+            ("chat".to_string(), 180),
+        ]);
     }
 
     #[test]
     fn test_long_term_trends() {
-        let conn = setup_test_db();
+        let storage = setup_test_storage();
         
         // 模拟半年的使用数据
-        let start_date = Utc::now() - Duration::days(180);
+        let start_date = utc("2025-09-01T08:00:00Z");
         let mut current_date = start_date;
-        let end_date = Utc::now();
+        let end_date = start_date + Duration::days(180);
 
         let apps = vec!["browser", "editor", "terminal", "chat"];
         
         while current_date < end_date {
+            let mut current_time = current_date;
             for app in &apps {
                 // 模拟随时间变化的使用模式
                 let base_duration = 3600; // 基础1小时
                 let day_factor = current_date.day() as f64 / 31.0; // 根据月份日期变化
                 let month_factor = current_date.month() as f64 / 12.0; // 根据月份变化
-                let duration = (base_duration as f64 * (1.0 + day_factor + month_factor)) as i64;
-
-                conn.execute(
-                    "INSERT INTO app_usage (timestamp, app_name, duration) VALUES (?1, ?2, ?3)",
-                    [
-                        current_date.to_rfc3339(),
-                        app.to_string(),
-                        duration.to_string(),
-                    ],
-                ).unwrap();
+                let duration = (base_duration as f64 * (1.0 + day_factor + month_factor)) as u64;
+
+                storage.record_usage(usage_record(current_time, app, duration, UsageState::Active)).unwrap();
+                current_time += Duration::seconds(duration as i64);
             }
-            current_date = current_date + Duration::days(1);
+            current_date += Duration::days(1);
         }
 
         // 分析月度趋势
-        let mut stmt = conn.prepare(
-            "SELECT 
-                strftime('%Y-%m', timestamp) as month,
-                app_name,
-                SUM(duration) as total_duration,
-                AVG(duration) as avg_duration,
-                COUNT(*) as usage_days
-             FROM app_usage 
-             GROUP BY month, app_name
-             ORDER BY month, total_duration DESC"
-        ).unwrap();
-
-        let trends: Vec<(String, String, i64, f64, i64)> = stmt.query_map([], |row| {
-            Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?))
-        })
-        .unwrap()
-        .map(|r| r.unwrap())
-        .collect();
+        let query = range_query("2025-09-01T00:00:00Z", "2026-03-01T00:00:00Z", Granularity::Month);
+        let trends = storage.get_usage_stats_range(&query).unwrap();
 
         // 验证每个月都有数据
-        let months: Vec<_> = trends.iter()
-            .map(|t| &t.0)
-            .collect::<std::collections::HashSet<_>>()
-            .into_iter()
+        let months: std::collections::HashSet<NaiveDate> = trends.iter()
+            .flat_map(|stat| stat.daily_usage.iter().map(|d| d.date))
             .collect();
         assert!(months.len() >= 6);
 
         // 验证所有应用都有数据
-        let recorded_apps: Vec<_> = trends.iter()
-            .map(|t| &t.1)
-            .collect::<std::collections::HashSet<_>>()
-            .into_iter()
-            .collect();
-        assert_eq!(recorded_apps.len(), apps.len());
+        assert_eq!(trends.len(), apps.len());
+        assert!(trends.iter().all(|stat| stat.daily_usage.len() == months.len()));
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,77 @@
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
+
+// 按每条记录保存的 UTC 偏移划分日期，适合经常跨时区出差的用户
+pub const RECORDED_TIME_ZONE: &str = "recorded";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ZoneSetting {
+    Named(Tz),
+    // 旧记录没有保存偏移时使用内部的时区
+    Recorded(Tz),
+}
+
+pub fn system_time_zone() -> Tz {
+    iana_time_zone::get_timezone()
+        .ok()
+        .and_then(|name| name.parse().ok())
+        .unwrap_or(Tz::UTC)
+}
+
+pub fn parse_zone_setting(value: &str, fallback: Tz) -> Option<ZoneSetting> {
+    if value == RECORDED_TIME_ZONE {
+        Some(ZoneSetting::Recorded(fallback))
+    } else {
+        value.parse::<Tz>().ok().map(ZoneSetting::Named)
+    }
+}
+
+// 某个本地日期的 00:00 对应的 UTC 时间
+pub fn local_midnight<Z: TimeZone>(tz: &Z, date: NaiveDate) -> DateTime<Utc> {
+    let midnight = date.and_hms_opt(0, 0, 0).unwrap();
+    // 有些时区在午夜切换夏令时（如 America/Santiago），当天没有 00:00，取之后第一个存在的时刻
+    tz.from_local_datetime(&midnight)
+        .earliest()
+        .or_else(|| tz.from_local_datetime(&(midnight + Duration::hours(1))).earliest())
+        .map(|t| t.with_timezone(&Utc))
+        .unwrap_or_else(|| midnight.and_utc())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_zone_setting() {
+        assert_eq!(
+            parse_zone_setting("Asia/Shanghai", Tz::UTC),
+            Some(ZoneSetting::Named(Tz::Asia__Shanghai))
+        );
+        assert_eq!(
+            parse_zone_setting("recorded", Tz::Europe__London),
+            Some(ZoneSetting::Recorded(Tz::Europe__London))
+        );
+        assert_eq!(parse_zone_setting("Mars/Olympus_Mons", Tz::UTC), None);
+    }
+
+    #[test]
+    fn test_local_midnight() {
+        let date = NaiveDate::from_ymd_opt(2026, 3, 8).unwrap();
+        assert_eq!(
+            local_midnight(&Tz::America__New_York, date).to_rfc3339(),
+            "2026-03-08T05:00:00+00:00"
+        );
+        // 夏令时开始后午夜偏移变为 -4
+        let date = NaiveDate::from_ymd_opt(2026, 3, 9).unwrap();
+        assert_eq!(
+            local_midnight(&Tz::America__New_York, date).to_rfc3339(),
+            "2026-03-09T04:00:00+00:00"
+        );
+        // 圣地亚哥 2026-09-06 00:00 不存在，取 01:00
+        let date = NaiveDate::from_ymd_opt(2026, 9, 6).unwrap();
+        assert_eq!(
+            local_midnight(&Tz::America__Santiago, date).to_rfc3339(),
+            "2026-09-06T04:00:00+00:00"
+        );
+    }
+}
@@ -0,0 +1,175 @@
+use chrono::{DateTime, Utc};
+use rusqlite::OptionalExtension;
+use super::storage::{format_timestamp, parse_timestamp, validate_query, Storage, StorageError};
+use super::types::{TrackingPause, TrackingState, UsageQuery};
+
+// 进行中的暂停：(id, 计划恢复的时间)
+type OpenPause = (i64, Option<DateTime<Utc>>);
+
+// 记录状态保存在 tracking_pauses 中：没有进行中的暂停即为运行中
+impl Storage {
+    // 到期的定时暂停在计划的时间点结束，应用关闭期间到期的也一样
+    pub fn get_tracking_state(&self, now: DateTime<Utc>) -> Result<TrackingState, StorageError> {
+        let Some((id, resume_at)) = self.open_pause()? else {
+            return Ok(TrackingState::Running);
+        };
+        match resume_at {
+            Some(resume_at) if resume_at <= now => {
+                self.conn.execute(
+                    "UPDATE tracking_pauses SET end_time = ?1 WHERE id = ?2",
+                    (format_timestamp(resume_at), id),
+                )?;
+                tracing::info!("Scheduled pause ended at {}", resume_at);
+                Ok(TrackingState::Running)
+            }
+            Some(resume_at) => Ok(TrackingState::PausedUntil(resume_at)),
+            None => Ok(TrackingState::Paused),
+        }
+    }
+
+    // 从运行切换到暂停时开始一段暂停，恢复时结束；暂停中修改恢复时间不会开始新的一段
+    pub fn set_tracking_state(&self, state: TrackingState, now: DateTime<Utc>) -> Result<TrackingState, StorageError> {
+        if let TrackingState::PausedUntil(resume_at) = state {
+            if resume_at <= now {
+                return Err(StorageError::InvalidQuery("resume time must be in the future".to_string()));
+            }
+        }
+        let resume_at = match state {
+            TrackingState::PausedUntil(resume_at) => Some(format_timestamp(resume_at)),
+            _ => None,
+        };
+
+        self.get_tracking_state(now)?;
+        match (self.open_pause()?, state.is_paused()) {
+            (None, true) => {
+                self.conn.execute(
+                    "INSERT INTO tracking_pauses (start_time, resume_at) VALUES (?1, ?2)",
+                    (format_timestamp(now), resume_at),
+                )?;
+            }
+            (Some((id, _)), true) => {
+                self.conn.execute("UPDATE tracking_pauses SET resume_at = ?1 WHERE id = ?2", (resume_at, id))?;
+            }
+            (Some((id, _)), false) => {
+                self.conn.execute(
+                    "UPDATE tracking_pauses SET end_time = ?1 WHERE id = ?2",
+                    (format_timestamp(now), id),
+                )?;
+            }
+            (None, false) => {}
+        }
+        Ok(state)
+    }
+
+    // 与查询区间有重叠的暂停，按开始时间排序
+    pub fn list_tracking_pauses(&self, query: &UsageQuery) -> Result<Vec<TrackingPause>, StorageError> {
+        validate_query(query)?;
+        let mut stmt = self.conn.prepare(
+            "SELECT start_time, end_time, resume_at FROM tracking_pauses
+             WHERE start_time < ?2 AND (end_time IS NULL OR end_time > ?1)
+             ORDER BY start_time, id"
+        )?;
+        let rows = stmt.query_map([format_timestamp(query.from), format_timestamp(query.to)], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, Option<String>>(1)?,
+                row.get::<_, Option<String>>(2)?,
+            ))
+        })?;
+
+        let mut pauses = Vec::new();
+        for row in rows {
+            let (start_time, end_time, resume_at) = row?;
+            match parse_timestamp(&start_time) {
+                Some(start) => pauses.push(TrackingPause {
+                    start_time: start,
+                    end_time: end_time.as_deref().and_then(parse_timestamp),
+                    resume_at: resume_at.as_deref().and_then(parse_timestamp),
+                }),
+                None => tracing::warn!("Skipping tracking pause with invalid start: {}", start_time),
+            }
+        }
+        Ok(pauses)
+    }
+
+    fn open_pause(&self) -> Result<Option<OpenPause>, StorageError> {
+        let row = self.conn.query_row(
+            "SELECT id, resume_at FROM tracking_pauses WHERE end_time IS NULL ORDER BY id DESC LIMIT 1",
+            [],
+            |row| Ok((row.get::<_, i64>(0)?, row.get::<_, Option<String>>(1)?)),
+        ).optional()?;
+        Ok(row.map(|(id, resume_at)| (id, resume_at.as_deref().and_then(parse_timestamp))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::types::Granularity;
+
+    fn utc(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(value).unwrap().with_timezone(&Utc)
+    }
+
+    fn all_pauses(storage: &Storage) -> Vec<(DateTime<Utc>, Option<DateTime<Utc>>)> {
+        let query = UsageQuery {
+            from: utc("2026-01-01T00:00:00Z"),
+            to: utc("2027-01-01T00:00:00Z"),
+            granularity: Granularity::Day,
+            apps: Vec::new(),
+            limit: None,
+            time_zone: None,
+        };
+        storage.list_tracking_pauses(&query).unwrap()
+            .into_iter()
+            .map(|pause| (pause.start_time, pause.end_time))
+            .collect()
+    }
+
+    // 1. 暂停和恢复记录为一段暂停，重复暂停不会开始新的一段
+    #[test]
+    fn test_pause_and_resume() {
+        let storage = Storage::open_in_memory().unwrap();
+        assert_eq!(storage.get_tracking_state(utc("2026-05-01T09:00:00Z")).unwrap(), TrackingState::Running);
+
+        storage.set_tracking_state(TrackingState::Paused, utc("2026-05-01T09:00:00Z")).unwrap();
+        storage.set_tracking_state(TrackingState::Paused, utc("2026-05-01T09:10:00Z")).unwrap();
+        assert_eq!(storage.get_tracking_state(utc("2026-05-01T09:20:00Z")).unwrap(), TrackingState::Paused);
+        assert_eq!(all_pauses(&storage), vec![(utc("2026-05-01T09:00:00Z"), None)]);
+
+        storage.set_tracking_state(TrackingState::Running, utc("2026-05-01T09:30:00Z")).unwrap();
+        // 运行中再次恢复没有影响
+        storage.set_tracking_state(TrackingState::Running, utc("2026-05-01T09:40:00Z")).unwrap();
+        assert_eq!(all_pauses(&storage), vec![(utc("2026-05-01T09:00:00Z"), Some(utc("2026-05-01T09:30:00Z")))]);
+    }
+
+    // 2. 定时暂停到期后在计划的时间点恢复
+    #[test]
+    fn test_paused_until_expires() {
+        let storage = Storage::open_in_memory().unwrap();
+        let until = utc("2026-05-01T10:00:00Z");
+        storage.set_tracking_state(TrackingState::PausedUntil(until), utc("2026-05-01T09:30:00Z")).unwrap();
+        assert_eq!(storage.get_tracking_state(utc("2026-05-01T09:59:59Z")).unwrap(), TrackingState::PausedUntil(until));
+
+        assert_eq!(storage.get_tracking_state(utc("2026-05-01T12:00:00Z")).unwrap(), TrackingState::Running);
+        assert_eq!(all_pauses(&storage), vec![(utc("2026-05-01T09:30:00Z"), Some(until))]);
+
+        // 已经过去的恢复时间被拒绝
+        assert!(matches!(
+            storage.set_tracking_state(TrackingState::PausedUntil(until), utc("2026-05-01T12:00:00Z")),
+            Err(StorageError::InvalidQuery(_))
+        ));
+    }
+
+    // 3. 暂停中改为定时暂停只修改恢复时间
+    #[test]
+    fn test_extend_pause() {
+        let storage = Storage::open_in_memory().unwrap();
+        storage.set_tracking_state(TrackingState::Paused, utc("2026-05-01T09:00:00Z")).unwrap();
+        let until = utc("2026-05-01T11:00:00Z");
+        storage.set_tracking_state(TrackingState::PausedUntil(until), utc("2026-05-01T10:00:00Z")).unwrap();
+        assert_eq!(storage.get_tracking_state(utc("2026-05-01T10:30:00Z")).unwrap(), TrackingState::PausedUntil(until));
+        assert_eq!(storage.get_tracking_state(utc("2026-05-01T11:30:00Z")).unwrap(), TrackingState::Running);
+        assert_eq!(all_pauses(&storage), vec![(utc("2026-05-01T09:00:00Z"), Some(until))]);
+    }
+}
@@ -1,11 +1,69 @@
 use serde::{Serialize, Deserialize};
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use chrono_tz::Tz;
+use super::timezone::local_midnight;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum UsageState {
+    #[default]
+    Active,
+    Idle,
+}
+
+impl UsageState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UsageState::Active => "active",
+            UsageState::Idle => "idle",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "active" => Some(UsageState::Active),
+            "idle" => Some(UsageState::Idle),
+            _ => None,
+        }
+    }
+}
+
+// 本次运行期间各应用的实时累计，last_active 为 Unix 秒
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct AppUsage {
+    pub name: String,
+    pub total_time: u64,
+    pub last_active: u64,
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct AppUsageRecord {
     pub timestamp: DateTime<Utc>,
     pub app_name: String,
     pub duration: u64,
+    #[serde(default)]
+    pub state: UsageState,
+    pub window_title: Option<String>,
+    pub window_class: Option<String>,
+    pub exe_path: Option<String>,
+    pub pid: Option<u32>,
+    // 记录时本地时间相对 UTC 的偏移（秒）
+    pub utc_offset: Option<i32>,
+}
+
+// 数据库中的一条会话，导出原始数据时使用
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SessionRecord {
+    pub app_name: String,
+    pub state: UsageState,
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+    pub duration: u64,
+    pub window_title: Option<String>,
+    pub window_class: Option<String>,
+    pub exe_path: Option<String>,
+    pub pid: Option<u32>,
+    pub utc_offset: Option<i32>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -13,10 +71,403 @@ pub struct AppUsageStats {
     pub name: String,
     pub total_time: u64,
     pub daily_usage: Vec<DailyUsage>,
+    pub titles: Vec<TitleUsage>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TitleUsage {
+    pub title: String,
+    pub window_class: Option<String>,
+    pub duration: u64,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct DailyUsage {
-    pub date: DateTime<Utc>,
+    // 本地日历日期；按周/月统计时为该时间段的第一天
+    pub date: NaiveDate,
     pub duration: u64,
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Granularity {
+    #[default]
+    Day,
+    Week,
+    Month,
+}
+
+// 查询 [from, to) 区间内的使用情况，按 granularity 分桶
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UsageQuery {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    #[serde(default)]
+    pub granularity: Granularity,
+    // 为空时不过滤应用
+    #[serde(default)]
+    pub apps: Vec<String>,
+    pub limit: Option<usize>,
+    // IANA 时区名或 "recorded"，为空时使用设置中的时区
+    pub time_zone: Option<String>,
+}
+
+impl UsageQuery {
+    // 兼容前端的 daily/3days/weekly/monthly 时间范围
+    pub fn from_range(range: &str, now: DateTime<Utc>, time_zone: &Tz) -> Option<Self> {
+        let from = match range {
+            "daily" => local_midnight(time_zone, now.with_timezone(time_zone).date_naive()),
+            "3days" => now - Duration::days(3),
+            "weekly" => now - Duration::days(7),
+            "monthly" => now - Duration::days(30),
+            _ => return None,
+        };
+
+        Some(UsageQuery {
+            from,
+            to: now,
+            granularity: Granularity::Day,
+            apps: Vec::new(),
+            limit: None,
+            time_zone: None,
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Productivity {
+    Productive,
+    #[default]
+    Neutral,
+    Distracting,
+}
+
+impl Productivity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Productivity::Productive => "productive",
+            Productivity::Neutral => "neutral",
+            Productivity::Distracting => "distracting",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "productive" => Some(Productivity::Productive),
+            "neutral" => Some(Productivity::Neutral),
+            "distracting" => Some(Productivity::Distracting),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Category {
+    pub id: i64,
+    pub name: String,
+    pub productivity: Productivity,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchField {
+    ProcessName,
+    WindowClass,
+    WindowTitle,
+}
+
+impl MatchField {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MatchField::ProcessName => "process_name",
+            MatchField::WindowClass => "window_class",
+            MatchField::WindowTitle => "window_title",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "process_name" => Some(MatchField::ProcessName),
+            "window_class" => Some(MatchField::WindowClass),
+            "window_title" => Some(MatchField::WindowTitle),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchKind {
+    // * 匹配任意字符，? 匹配单个字符，忽略大小写
+    Glob,
+    Regex,
+}
+
+impl MatchKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MatchKind::Glob => "glob",
+            MatchKind::Regex => "regex",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "glob" => Some(MatchKind::Glob),
+            "regex" => Some(MatchKind::Regex),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct CategoryRule {
+    pub id: i64,
+    pub category_id: i64,
+    pub field: MatchField,
+    pub kind: MatchKind,
+    pub pattern: String,
+    // 数值大的规则先匹配，相同时先添加的优先
+    #[serde(default)]
+    pub priority: i32,
+}
+
+// 按分类汇总的使用时长，category_id 为空表示未分类
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CategoryStats {
+    pub category_id: Option<i64>,
+    pub name: String,
+    pub productivity: Productivity,
+    pub total_time: u64,
+    pub daily_usage: Vec<DailyUsage>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ProductivityScore {
+    pub date: NaiveDate,
+    pub productive: u64,
+    pub neutral: u64,
+    pub distracting: u64,
+    // 0-100，高效时间计满分、中性时间计一半
+    pub score: u8,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum LimitTarget {
+    App(String),
+    Category(i64),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct UsageLimit {
+    pub id: i64,
+    pub target: LimitTarget,
+    pub daily_limit: u64,
+}
+
+// 某条限额今天的使用情况
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct LimitStatus {
+    pub limit: UsageLimit,
+    // 应用名或分类名
+    pub name: String,
+    pub used: u64,
+    pub remaining: u64,
+    pub percent: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct LimitAlert {
+    pub limit_id: i64,
+    pub name: String,
+    pub threshold: u32,
+    pub used: u64,
+    pub daily_limit: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FocusStatus {
+    Active,
+    Completed,
+    Cancelled,
+}
+
+impl FocusStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FocusStatus::Active => "active",
+            FocusStatus::Completed => "completed",
+            FocusStatus::Cancelled => "cancelled",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "active" => Some(FocusStatus::Active),
+            "completed" => Some(FocusStatus::Completed),
+            "cancelled" => Some(FocusStatus::Cancelled),
+            _ => None,
+        }
+    }
+}
+
+// 专注期间切到允许列表以外的应用
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct FocusInterruption {
+    pub app_name: String,
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+    pub duration: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct FocusSession {
+    pub id: i64,
+    pub start_time: DateTime<Utc>,
+    // 进行中的会话为空
+    pub end_time: Option<DateTime<Utc>>,
+    pub target_duration: u64,
+    // 为空时不限制应用
+    pub allowed_apps: Vec<String>,
+    pub status: FocusStatus,
+    pub focused_time: u64,
+    pub interrupted_time: u64,
+    pub interruptions: Vec<FocusInterruption>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct DistractingApp {
+    pub name: String,
+    pub total_time: u64,
+    pub interruptions: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct FocusHistory {
+    pub sessions: Vec<FocusSession>,
+    pub completed: u32,
+    pub cancelled: u32,
+    pub focused_time: u64,
+    pub interrupted_time: u64,
+    pub top_distractions: Vec<DistractingApp>,
+}
+
+// 数据保留策略：原始会话保留 raw_days 天，之后按小时汇总，
+// 小时汇总保留 hourly_days 天后再按天汇总，delete_after_months 为空时永不删除
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    pub raw_days: u32,
+    pub hourly_days: u32,
+    pub delete_after_months: Option<u32>,
+}
+
+// 一次维护中处理的行数
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MaintenanceReport {
+    pub sessions_rolled_up: usize,
+    pub hourly_rolled_up: usize,
+    pub deleted: usize,
+}
+
+// 记录状态：运行中、暂停，或暂停到某个时间点后自动恢复
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TrackingState {
+    Running,
+    Paused,
+    PausedUntil(DateTime<Utc>),
+}
+
+impl TrackingState {
+    pub fn is_paused(&self) -> bool {
+        !matches!(self, TrackingState::Running)
+    }
+}
+
+// 暂停记录的时间段，进行中的暂停 end_time 为空
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct TrackingPause {
+    pub start_time: DateTime<Utc>,
+    pub end_time: Option<DateTime<Utc>>,
+    // 定时暂停计划恢复的时间
+    pub resume_at: Option<DateTime<Utc>>,
+}
+
+// 隐私规则匹配时的处理方式，同时匹配多条规则时取最严格的（Drop > Private > StripTitle）
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum PrivacyAction {
+    // 只记录应用，不记录窗口标题
+    StripTitle,
+    // 记录为 "Private"，不保留应用名和窗口信息
+    Private,
+    // 完全不记录
+    Drop,
+}
+
+impl PrivacyAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PrivacyAction::StripTitle => "strip_title",
+            PrivacyAction::Private => "private",
+            PrivacyAction::Drop => "drop",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "strip_title" => Some(PrivacyAction::StripTitle),
+            "private" => Some(PrivacyAction::Private),
+            "drop" => Some(PrivacyAction::Drop),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PrivacyRule {
+    pub id: i64,
+    pub field: MatchField,
+    pub kind: MatchKind,
+    pub pattern: String,
+    pub action: PrivacyAction,
+}
+
+// 按当前规则清理历史记录时处理的行数
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PurgeReport {
+    pub deleted: usize,
+    pub anonymized: usize,
+    pub stripped: usize,
+}
+
+// 加密数据库的密钥来源
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum KeySource {
+    // 随机生成并保存在系统密钥链中
+    Keyring,
+    // 由用户的口令派生，不保存
+    Passphrase,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct EncryptionStatus {
+    // 构建时是否启用了 encryption 功能
+    pub supported: bool,
+    pub encrypted: bool,
+    pub key_source: Option<KeySource>,
+    // 加密的数据库还没有可用的密钥，需要输入口令解锁
+    pub locked: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct BackupInfo {
+    // 备份目录中的文件名，恢复时用它指定备份
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    pub size: u64,
+}
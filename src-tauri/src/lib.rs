@@ -1,2 +1,4 @@
 pub mod platform;
-pub mod db;
\ No newline at end of file
+pub mod db;
+pub mod api;
+pub mod monitor;
@@ -1,38 +1,142 @@
 mod platform;
 mod db;
+mod api;
+mod monitor;
+mod tray;
 
 use db::storage::StorageError;
+#[cfg(target_os = "windows")]
 use platform::windows::Windows;
-use platform::AutoStart;
-use serde::{Deserialize, Serialize};
+#[cfg(target_os = "macos")]
+use platform::macos::MacOS;
+#[cfg(target_os = "linux")]
+use platform::linux::Linux;
+use platform::{AutoStart, MonitorDiagnostics};
 use std::collections::HashMap;
-use std::sync::Mutex;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 use std::time::{SystemTime, UNIX_EPOCH};
 use tauri::{AppHandle, Emitter, Manager};
-use crate::db::{storage::Storage, types::{AppUsageRecord, AppUsageStats}};
+use crate::db::export::{self, ExportFormat, ExportKind};
+use crate::db::import::{self, ImportSource, ImportSummary};
+use crate::db::{app_storage, storage::Storage, types::{AppUsage, AppUsageRecord, AppUsageStats, UsageQuery, UsageState}};
+use crate::db::types::{Category, CategoryRule, CategoryStats, MatchField, MatchKind, Productivity, ProductivityScore};
+use crate::db::types::{LimitAlert, LimitStatus, LimitTarget, UsageLimit};
+use crate::db::types::{FocusHistory, FocusSession};
+use crate::db::types::{MaintenanceReport, RetentionPolicy};
+use crate::db::types::{TrackingPause, TrackingState};
+use crate::db::types::{PrivacyAction, PrivacyRule, PurgeReport};
+use crate::db::privacy::PrivacyFilter;
+use crate::db::types::EncryptionStatus;
+use crate::db::encryption::{self, DatabaseKey};
+use crate::db::backup;
+use crate::db::types::BackupInfo;
+use crate::api::{ApiServer, ApiSettings, LiveUsage};
+use crate::monitor::{source::FocusSource, writer::BatchedWriter};
+use tauri_plugin_notification::NotificationExt;
+use tokio::sync::{oneshot, watch};
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct AppUsage {
-    pub name: String,
-    pub total_time: u64,
-    pub last_active: u64,
-}
+const IDLE_THRESHOLD_KEY: &str = "idle_threshold_secs";
+const DEFAULT_IDLE_THRESHOLD_SECS: u64 = 300;
+const MONITOR_SHUTDOWN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+const MAINTENANCE_DELAY: std::time::Duration = std::time::Duration::from_secs(60);
+const MAINTENANCE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(6 * 60 * 60);
+const BACKUP_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+const BACKUP_INTERVAL: chrono::Duration = chrono::Duration::hours(24);
 
 pub struct AppState {
+    // 今天各应用的活跃时长，跨过本地零点时清空
     usage_data: Mutex<HashMap<String, AppUsage>>,
-    storage: Mutex<Storage>,
+    storage: Arc<Mutex<Storage>>,
+    // 超过该秒数没有输入即视为空闲，0 表示关闭空闲检测
+    idle_threshold: Mutex<u64>,
+    // 推送给本地 HTTP API 的实时数据
+    live_usage: watch::Sender<LiveUsage>,
+    api_server: Mutex<Option<ApiServer>>,
+    // 监控任务启动后记录选中的窗口后端
+    monitor_diagnostics: Mutex<Option<MonitorDiagnostics>>,
+    // 退出时通知监控任务停止，并等待它写完缓存的记录
+    monitor_task: Mutex<Option<(oneshot::Sender<()>, tauri::async_runtime::JoinHandle<()>)>>,
+    // 记录状态，tracking_paused 是给监控任务每次检查时读取的副本
+    tracking_state: Mutex<TrackingState>,
+    tracking_paused: Arc<AtomicBool>,
+    // 按隐私规则编译的过滤器，规则修改后重新生成
+    privacy_filter: Arc<RwLock<PrivacyFilter>>,
+    // 加密的数据库还没有解锁，storage 是临时的内存数据库，监控任务没有启动
+    database_locked: AtomicBool,
+    // 创建备份和替换数据库文件互斥，避免备份读到替换了一半的文件或使用已经换掉的密钥
+    backup_lock: Mutex<()>,
 }
 
 impl AppState {
     fn new(app_handle: &AppHandle) -> Result<Self, StorageError> {
-        let storage = Storage::new(app_handle)?;
+        let (storage, locked) = match open_database(app_handle) {
+            Ok(storage) => (storage, false),
+            // 没有可用的密钥时先用内存数据库启动，等用户通过 unlock_database 输入口令
+            Err(StorageError::Encryption(e)) => {
+                tracing::warn!("Database is locked: {}", e);
+                (Storage::open_in_memory()?, true)
+            }
+            Err(e) => return Err(e),
+        };
+        let idle_threshold = load_idle_threshold(&storage)?;
+        let usage_data = load_today_usage(&storage);
+        let tracking_state = storage.get_tracking_state(chrono::Utc::now())?;
+        let privacy_filter = storage.privacy_filter()?;
         Ok(Self {
-            usage_data: Mutex::new(HashMap::new()),
-            storage: Mutex::new(storage),
+            usage_data: Mutex::new(usage_data),
+            storage: Arc::new(Mutex::new(storage)),
+            idle_threshold: Mutex::new(idle_threshold),
+            live_usage: watch::Sender::new(LiveUsage::new()),
+            api_server: Mutex::new(None),
+            monitor_diagnostics: Mutex::new(None),
+            monitor_task: Mutex::new(None),
+            tracking_state: Mutex::new(tracking_state),
+            tracking_paused: Arc::new(AtomicBool::new(tracking_state.is_paused())),
+            privacy_filter: Arc::new(RwLock::new(privacy_filter)),
+            database_locked: AtomicBool::new(locked),
+            backup_lock: Mutex::new(()),
         })
     }
 }
 
+// 加密的数据库解锁前 storage 只是内存中的占位，写入的内容会在解锁后丢失
+fn ensure_unlocked(state: &AppState) -> Result<(), String> {
+    if state.database_locked.load(Ordering::SeqCst) {
+        return Err("The database is locked".to_string());
+    }
+    Ok(())
+}
+
+// 数据库损坏时先从最近一个完好的备份恢复，再打开
+fn open_database(app_handle: &AppHandle) -> Result<Storage, StorageError> {
+    let db_path = app_storage::database_path(app_handle)?;
+    if let Err(e) = backup::restore_if_corrupted(&db_path) {
+        tracing::error!("Failed to check database integrity: {}", e);
+    }
+    app_storage::open_storage(app_handle)
+}
+
+fn load_idle_threshold(storage: &Storage) -> Result<u64, StorageError> {
+    Ok(storage.get_setting(IDLE_THRESHOLD_KEY)?
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_IDLE_THRESHOLD_SECS))
+}
+
+// 用数据库中今天已有的记录初始化，重启后托盘显示的仍是全天的总时长
+fn load_today_usage(storage: &Storage) -> HashMap<String, AppUsage> {
+    match storage.get_usage_stats("daily") {
+        Ok(stats) => stats.into_iter()
+            .map(|stat| (stat.name.clone(), AppUsage { name: stat.name, total_time: stat.total_time, last_active: 0 }))
+            .collect(),
+        Err(e) => {
+            tracing::error!("Failed to load today's usage: {}", e);
+            HashMap::new()
+        }
+    }
+}
+
 #[tauri::command]
 async fn get_app_usage(state: tauri::State<'_, AppState>) -> Result<HashMap<String, AppUsage>, String> {
     state.usage_data
@@ -66,70 +170,838 @@ async fn get_auto_start_status() -> Result<bool, String> {
 }
 
 #[tauri::command]
-async fn get_app_usage_stats(app_handle: tauri::AppHandle, range: String) -> Result<Vec<AppUsageStats>, String> {
-    let storage = Storage::new(&app_handle).map_err(|e| e.to_string())?;
-    storage.get_usage_stats(&range)
+async fn get_app_usage_stats(state: tauri::State<'_, AppState>, range: String) -> Result<Vec<AppUsageStats>, String> {
+    state.storage
+        .lock()
+        .map_err(|e| e.to_string())?
+        .get_usage_stats(&range)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_usage_stats_range(state: tauri::State<'_, AppState>, query: UsageQuery) -> Result<Vec<AppUsageStats>, String> {
+    state.storage
+        .lock()
+        .map_err(|e| e.to_string())?
+        .get_usage_stats_range(&query)
+        .map_err(|e| e.to_string())
+}
+
+// 把查询区间内的原始会话或统计写到用户选择的文件
+#[tauri::command]
+async fn export_usage(
+    state: tauri::State<'_, AppState>,
+    query: UsageQuery,
+    kind: ExportKind,
+    format: ExportFormat,
+    path: String,
+) -> Result<usize, String> {
+    let storage = state.storage.lock().map_err(|e| e.to_string())?;
+    let count = export::export_to_path(&storage, &query, kind, format, Path::new(&path))
+        .map_err(|e| e.to_string())?;
+    tracing::info!("Exported {} rows to {}", count, path);
+    Ok(count)
+}
+
+// 导入 ActivityWatch 或 CSV 格式的历史记录
+#[tauri::command]
+async fn import_usage(state: tauri::State<'_, AppState>, source: ImportSource, path: String) -> Result<ImportSummary, String> {
+    ensure_unlocked(&state)?;
+    let storage = state.storage.lock().map_err(|e| e.to_string())?;
+    import::import_file(&storage, source, Path::new(&path))
         .map_err(|e| e.to_string())
 }
 
 #[tauri::command]
 async fn record_app_usage(app_handle: tauri::AppHandle, record: AppUsageRecord) -> Result<(), String> {
-    let storage = Storage::new(&app_handle).map_err(|e| e.to_string())?;
-    storage.record_usage(record)
+    let state = app_handle.state::<AppState>();
+    ensure_unlocked(&state)?;
+    // 暂停期间不记录前端上报的使用时间
+    if state.tracking_paused.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+    let filtered = state.privacy_filter
+        .read()
+        .map_err(|e| e.to_string())?
+        .apply_record(record);
+    let Some(record) = filtered else {
+        return Ok(());
+    };
+    state.storage
+        .lock()
+        .map_err(|e| e.to_string())?
+        .record_usage(record)
         .map_err(|e| e.to_string())
 }
 
-async fn monitor_active_window(handle: tauri::AppHandle) {
-    tracing::info!("Starting window monitor...");
-    let window_monitor = platform::create_window_monitor();
-    
+#[tauri::command]
+async fn get_idle_threshold(state: tauri::State<'_, AppState>) -> Result<u64, String> {
+    state.idle_threshold
+        .lock()
+        .map(|threshold| *threshold)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_idle_threshold(state: tauri::State<'_, AppState>, seconds: u64) -> Result<(), String> {
+    ensure_unlocked(&state)?;
+    state.storage
+        .lock()
+        .map_err(|e| e.to_string())?
+        .set_setting(IDLE_THRESHOLD_KEY, &seconds.to_string())
+        .map_err(|e| e.to_string())?;
+    *state.idle_threshold.lock().map_err(|e| e.to_string())? = seconds;
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_tracking_state(state: tauri::State<'_, AppState>) -> Result<TrackingState, String> {
+    state.tracking_state
+        .lock()
+        .map(|tracking_state| *tracking_state)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_tracking_state(app_handle: tauri::AppHandle, state: TrackingState) -> Result<TrackingState, String> {
+    apply_tracking_state(&app_handle, state)
+}
+
+// 暂停 minutes 分钟后自动恢复
+#[tauri::command]
+async fn snooze_tracking(app_handle: tauri::AppHandle, minutes: u64) -> Result<TrackingState, String> {
+    if minutes == 0 {
+        return Err("Snooze duration must be positive".to_string());
+    }
+    let until = chrono::Utc::now() + chrono::Duration::minutes(minutes as i64);
+    apply_tracking_state(&app_handle, TrackingState::PausedUntil(until))
+}
+
+#[tauri::command]
+async fn get_tracking_pauses(state: tauri::State<'_, AppState>, query: UsageQuery) -> Result<Vec<TrackingPause>, String> {
+    state.storage
+        .lock()
+        .map_err(|e| e.to_string())?
+        .list_tracking_pauses(&query)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn list_privacy_rules(state: tauri::State<'_, AppState>) -> Result<Vec<PrivacyRule>, String> {
+    state.storage
+        .lock()
+        .map_err(|e| e.to_string())?
+        .list_privacy_rules()
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn add_privacy_rule(
+    state: tauri::State<'_, AppState>,
+    field: MatchField,
+    kind: MatchKind,
+    pattern: String,
+    action: PrivacyAction,
+) -> Result<PrivacyRule, String> {
+    ensure_unlocked(&state)?;
+    let storage = state.storage.lock().map_err(|e| e.to_string())?;
+    let rule = storage.add_privacy_rule(field, kind, &pattern, action).map_err(|e| e.to_string())?;
+    reload_privacy_filter(&state, &storage)?;
+    Ok(rule)
+}
+
+#[tauri::command]
+async fn delete_privacy_rule(state: tauri::State<'_, AppState>, id: i64) -> Result<(), String> {
+    ensure_unlocked(&state)?;
+    let storage = state.storage.lock().map_err(|e| e.to_string())?;
+    storage.delete_privacy_rule(id).map_err(|e| e.to_string())?;
+    reload_privacy_filter(&state, &storage)
+}
+
+// 按当前规则删除或匿名化已有的记录
+#[tauri::command]
+async fn purge_private_history(state: tauri::State<'_, AppState>) -> Result<PurgeReport, String> {
+    ensure_unlocked(&state)?;
+    state.storage
+        .lock()
+        .map_err(|e| e.to_string())?
+        .purge_private_history()
+        .map_err(|e| e.to_string())
+}
+
+fn reload_privacy_filter(state: &AppState, storage: &Storage) -> Result<(), String> {
+    let filter = storage.privacy_filter().map_err(|e| e.to_string())?;
+    *state.privacy_filter.write().map_err(|e| e.to_string())? = filter;
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_encryption_status(app_handle: tauri::AppHandle) -> Result<EncryptionStatus, String> {
+    let path = app_storage::database_path(&app_handle).map_err(|e| e.to_string())?;
+    encryption::encryption_status(&path).map_err(|e| e.to_string())
+}
+
+// 加密未加密的数据库或更换密钥。没有口令时生成随机密钥保存在系统密钥链中
+#[tauri::command]
+async fn set_database_key(app_handle: tauri::AppHandle, passphrase: Option<String>) -> Result<EncryptionStatus, String> {
+    let key = match passphrase {
+        Some(passphrase) => DatabaseKey::passphrase(&passphrase),
+        None => DatabaseKey::generate(),
+    }.map_err(|e| e.to_string())?;
+    change_database_key(&app_handle, Some(key)).await
+}
+
+#[tauri::command]
+async fn disable_database_encryption(app_handle: tauri::AppHandle) -> Result<EncryptionStatus, String> {
+    change_database_key(&app_handle, None).await
+}
+
+// 输入口令解锁启动时无法打开的加密数据库，打开数据库文件后开始记录
+#[tauri::command]
+async fn unlock_database(app_handle: tauri::AppHandle, passphrase: String) -> Result<EncryptionStatus, String> {
+    let state = app_handle.state::<AppState>();
+    if !state.database_locked.load(Ordering::SeqCst) {
+        return Err("The database is not locked".to_string());
+    }
+    let path = app_storage::database_path(&app_handle).map_err(|e| e.to_string())?;
+    encryption::unlock_database(&path, &passphrase).map_err(|e| e.to_string())?;
+    let storage = open_database(&app_handle).map_err(|e| e.to_string())?;
+    switch_storage(&app_handle, storage);
+    if state.database_locked.swap(false, Ordering::SeqCst) {
+        start_monitor(&app_handle);
+        start_saved_api(&app_handle);
+    }
+    encryption::encryption_status(&path).map_err(|e| e.to_string())
+}
+
+async fn change_database_key(app_handle: &AppHandle, key: Option<DatabaseKey>) -> Result<EncryptionStatus, String> {
+    let path = app_storage::database_path(app_handle).map_err(|e| e.to_string())?;
+    replace_database_file(app_handle, |path| encryption::change_database_key(path, key)).await?;
+    encryption::encryption_status(&path).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn list_backups(app_handle: tauri::AppHandle) -> Result<Vec<BackupInfo>, String> {
+    let path = app_storage::database_path(&app_handle).map_err(|e| e.to_string())?;
+    backup::list_backups(&path).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn create_backup(app_handle: tauri::AppHandle) -> Result<BackupInfo, String> {
+    let state = app_handle.state::<AppState>();
+    ensure_unlocked(&state)?;
+    let path = app_storage::database_path(&app_handle).map_err(|e| e.to_string())?;
+    let _backup_guard = state.backup_lock.lock().unwrap();
+    backup::create_backup(&path, chrono::Utc::now()).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn restore_backup(app_handle: tauri::AppHandle, name: String) -> Result<(), String> {
+    replace_database_file(&app_handle, |path| backup::restore_backup(path, &name)).await
+}
+
+// 替换数据库文件前停止监控并关闭界面使用的连接，完成后重新打开并重新读取依赖数据库的状态。
+// 替换失败时换回原来的文件，只有重新打开了数据库文件才恢复监控
+async fn replace_database_file(
+    app_handle: &AppHandle,
+    replace: impl FnOnce(&Path) -> Result<(), StorageError> + Send,
+) -> Result<(), String> {
+    ensure_unlocked(&app_handle.state::<AppState>())?;
+    let path = app_storage::database_path(app_handle).map_err(|e| e.to_string())?;
+    shutdown_monitor(app_handle).await;
+    let result = {
+        let state = app_handle.state::<AppState>();
+        let _backup_guard = state.backup_lock.lock().unwrap();
+        let mut storage = state.storage.lock().unwrap();
+        Storage::open_in_memory().and_then(|placeholder| {
+            *storage = placeholder;
+            backup::replace_with_rollback(&path, replace)
+        })
+    };
+    match app_storage::open_storage(app_handle) {
+        Ok(reopened) => switch_storage(app_handle, reopened),
+        Err(e) => {
+            tracing::error!("Failed to reopen the database: {}", e);
+            return Err(format!("Failed to reopen the database: {}", e));
+        }
+    }
+    start_monitor(app_handle);
+    result.map_err(|e| e.to_string())
+}
+
+// 界面和 HTTP API 改用 storage，并重新读取依赖数据库的状态
+fn switch_storage(app_handle: &AppHandle, storage: Storage) {
+    let state = app_handle.state::<AppState>();
+    *state.usage_data.lock().unwrap() = load_today_usage(&storage);
+    if let Err(e) = reload_privacy_filter(&state, &storage) {
+        tracing::error!("Failed to reload privacy rules: {}", e);
+    }
+    match load_idle_threshold(&storage) {
+        Ok(threshold) => *state.idle_threshold.lock().unwrap() = threshold,
+        Err(e) => tracing::error!("Failed to load the idle threshold: {}", e),
+    }
+    let tracking_state = storage.get_tracking_state(chrono::Utc::now());
+    *state.storage.lock().unwrap() = storage;
+    match tracking_state {
+        Ok(tracking_state) => sync_tracking_state(app_handle, tracking_state),
+        Err(e) => tracing::error!("Failed to load the tracking state: {}", e),
+    }
+}
+
+// 保存新的记录状态，再通知监控任务、托盘和前端
+fn apply_tracking_state(handle: &AppHandle, state: TrackingState) -> Result<TrackingState, String> {
+    ensure_unlocked(&handle.state::<AppState>())?;
+    let state = handle.state::<AppState>().storage
+        .lock()
+        .map_err(|e| e.to_string())?
+        .set_tracking_state(state, chrono::Utc::now())
+        .map_err(|e| e.to_string())?;
+    sync_tracking_state(handle, state);
+    Ok(state)
+}
+
+fn sync_tracking_state(handle: &AppHandle, state: TrackingState) {
+    let app_state = handle.state::<AppState>();
+    *app_state.tracking_state.lock().unwrap() = state;
+    app_state.tracking_paused.store(state.is_paused(), Ordering::Relaxed);
+    tracing::info!("Tracking state changed to {:?}", state);
+    let _ = handle.emit("tracking_state_changed", state);
+    tray::update_tray(handle);
+    if let TrackingState::PausedUntil(until) = state {
+        tauri::async_runtime::spawn(resume_when_due(handle.clone(), until));
+    }
+}
+
+// 定时暂停到期后恢复记录，期间状态又被修改过时什么也不做
+async fn resume_when_due(handle: AppHandle, until: chrono::DateTime<chrono::Utc>) {
+    let wait = (until - chrono::Utc::now()).to_std().unwrap_or_default();
+    tokio::time::sleep(wait).await;
+
+    let app_state = handle.state::<AppState>();
+    if *app_state.tracking_state.lock().unwrap() != TrackingState::PausedUntil(until) {
+        return;
+    }
+    let state = app_state.storage.lock().unwrap().get_tracking_state(chrono::Utc::now());
+    match state {
+        Ok(state) => sync_tracking_state(&handle, state),
+        Err(e) => tracing::error!("Failed to resume tracking: {}", e),
+    }
+}
+
+#[tauri::command]
+async fn get_time_zone(state: tauri::State<'_, AppState>) -> Result<String, String> {
+    state.storage
+        .lock()
+        .map_err(|e| e.to_string())?
+        .get_time_zone()
+        .map_err(|e| e.to_string())
+}
+
+// 可以是 IANA 时区名、"recorded"，或空字符串表示跟随系统
+#[tauri::command]
+async fn set_time_zone(state: tauri::State<'_, AppState>, time_zone: String) -> Result<(), String> {
+    ensure_unlocked(&state)?;
+    state.storage
+        .lock()
+        .map_err(|e| e.to_string())?
+        .set_time_zone(&time_zone)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn list_categories(state: tauri::State<'_, AppState>) -> Result<Vec<Category>, String> {
+    state.storage
+        .lock()
+        .map_err(|e| e.to_string())?
+        .list_categories()
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn create_category(state: tauri::State<'_, AppState>, name: String, productivity: Productivity) -> Result<Category, String> {
+    ensure_unlocked(&state)?;
+    state.storage
+        .lock()
+        .map_err(|e| e.to_string())?
+        .create_category(&name, productivity)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn update_category(state: tauri::State<'_, AppState>, category: Category) -> Result<(), String> {
+    ensure_unlocked(&state)?;
+    state.storage
+        .lock()
+        .map_err(|e| e.to_string())?
+        .update_category(&category)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn delete_category(state: tauri::State<'_, AppState>, id: i64) -> Result<(), String> {
+    ensure_unlocked(&state)?;
+    state.storage
+        .lock()
+        .map_err(|e| e.to_string())?
+        .delete_category(id)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn list_category_rules(state: tauri::State<'_, AppState>) -> Result<Vec<CategoryRule>, String> {
+    state.storage
+        .lock()
+        .map_err(|e| e.to_string())?
+        .list_category_rules()
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn add_category_rule(
+    state: tauri::State<'_, AppState>,
+    category_id: i64,
+    field: MatchField,
+    kind: MatchKind,
+    pattern: String,
+    priority: Option<i32>,
+) -> Result<CategoryRule, String> {
+    ensure_unlocked(&state)?;
+    state.storage
+        .lock()
+        .map_err(|e| e.to_string())?
+        .add_category_rule(category_id, field, kind, &pattern, priority.unwrap_or(0))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn delete_category_rule(state: tauri::State<'_, AppState>, id: i64) -> Result<(), String> {
+    ensure_unlocked(&state)?;
+    state.storage
+        .lock()
+        .map_err(|e| e.to_string())?
+        .delete_category_rule(id)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_category_stats(state: tauri::State<'_, AppState>, query: UsageQuery) -> Result<Vec<CategoryStats>, String> {
+    state.storage
+        .lock()
+        .map_err(|e| e.to_string())?
+        .get_category_stats(&query)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_productivity_scores(state: tauri::State<'_, AppState>, query: UsageQuery) -> Result<Vec<ProductivityScore>, String> {
+    state.storage
+        .lock()
+        .map_err(|e| e.to_string())?
+        .get_productivity_scores(&query)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn list_limits(state: tauri::State<'_, AppState>) -> Result<Vec<UsageLimit>, String> {
+    state.storage
+        .lock()
+        .map_err(|e| e.to_string())?
+        .list_limits()
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_limit(state: tauri::State<'_, AppState>, target: LimitTarget, daily_limit: u64) -> Result<UsageLimit, String> {
+    ensure_unlocked(&state)?;
+    state.storage
+        .lock()
+        .map_err(|e| e.to_string())?
+        .set_limit(&target, daily_limit)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn delete_limit(state: tauri::State<'_, AppState>, id: i64) -> Result<(), String> {
+    ensure_unlocked(&state)?;
+    state.storage
+        .lock()
+        .map_err(|e| e.to_string())?
+        .delete_limit(id)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_limit_status(state: tauri::State<'_, AppState>) -> Result<Vec<LimitStatus>, String> {
+    state.storage
+        .lock()
+        .map_err(|e| e.to_string())?
+        .get_limit_status(chrono::Utc::now())
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_limit_thresholds(state: tauri::State<'_, AppState>) -> Result<Vec<u32>, String> {
+    state.storage
+        .lock()
+        .map_err(|e| e.to_string())?
+        .get_limit_thresholds()
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_limit_thresholds(state: tauri::State<'_, AppState>, thresholds: Vec<u32>) -> Result<(), String> {
+    ensure_unlocked(&state)?;
+    state.storage
+        .lock()
+        .map_err(|e| e.to_string())?
+        .set_limit_thresholds(&thresholds)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn start_focus_session(
+    state: tauri::State<'_, AppState>,
+    target_duration: u64,
+    allowed_apps: Vec<String>,
+) -> Result<FocusSession, String> {
+    ensure_unlocked(&state)?;
+    state.storage
+        .lock()
+        .map_err(|e| e.to_string())?
+        .start_focus_session(target_duration, &allowed_apps, chrono::Utc::now())
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn stop_focus_session(state: tauri::State<'_, AppState>) -> Result<FocusSession, String> {
+    ensure_unlocked(&state)?;
+    state.storage
+        .lock()
+        .map_err(|e| e.to_string())?
+        .stop_focus_session(chrono::Utc::now())
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_active_focus_session(state: tauri::State<'_, AppState>) -> Result<Option<FocusSession>, String> {
+    state.storage
+        .lock()
+        .map_err(|e| e.to_string())?
+        .get_active_focus_session(chrono::Utc::now())
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_focus_history(state: tauri::State<'_, AppState>, query: UsageQuery) -> Result<FocusHistory, String> {
+    state.storage
+        .lock()
+        .map_err(|e| e.to_string())?
+        .get_focus_history(&query)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_retention_policy(state: tauri::State<'_, AppState>) -> Result<RetentionPolicy, String> {
+    state.storage
+        .lock()
+        .map_err(|e| e.to_string())?
+        .get_retention_policy()
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_retention_policy(state: tauri::State<'_, AppState>, policy: RetentionPolicy) -> Result<(), String> {
+    ensure_unlocked(&state)?;
+    state.storage
+        .lock()
+        .map_err(|e| e.to_string())?
+        .set_retention_policy(&policy)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn run_maintenance(state: tauri::State<'_, AppState>) -> Result<MaintenanceReport, String> {
+    ensure_unlocked(&state)?;
+    state.storage
+        .lock()
+        .map_err(|e| e.to_string())?
+        .run_maintenance(chrono::Utc::now())
+        .map_err(|e| e.to_string())
+}
+
+// 启动后稍等片刻执行第一次维护，之后每隔 MAINTENANCE_INTERVAL 执行一次
+async fn schedule_maintenance(handle: tauri::AppHandle) {
+    tokio::time::sleep(MAINTENANCE_DELAY).await;
+    let mut interval = tokio::time::interval(MAINTENANCE_INTERVAL);
     loop {
-        if let Some(process_name) = window_monitor.get_active_window() {
-            tracing::debug!("Detected active window: {}", process_name);
-            
-            let current_time = SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_secs();
+        interval.tick().await;
+        // 解锁前的数据库只是内存中的占位
+        if handle.state::<AppState>().database_locked.load(Ordering::SeqCst) {
+            continue;
+        }
+        let result = handle.state::<AppState>().storage
+            .lock()
+            .map_err(|e| e.to_string())
+            .and_then(|storage| storage.run_maintenance(chrono::Utc::now()).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            tracing::error!("Scheduled maintenance failed: {}", e);
+        }
+    }
+}
 
-            let state = handle.state::<AppState>();
-            {
-                let mut data = state.usage_data.lock().unwrap();
-                let app_usage = data.entry(process_name.clone())
-                    .or_insert(AppUsage {
-                        name: process_name.clone(),
-                        total_time: 0,
-                        last_active: current_time,
-                    });
-
-                if current_time - app_usage.last_active <= 2 {
-                    app_usage.total_time += 1;
-                    tracing::debug!("Updating usage for {}: {} seconds", process_name, app_usage.total_time);
-                    
-                    let mut storage = state.storage.lock().unwrap();
-                    let record = AppUsageRecord {
-                        timestamp: chrono::Utc::now(),
-                        app_name: process_name.clone(),
-                        duration: 1,
-                    };
-                    
-                    if let Err(e) = storage.record_usage(record) {
-                        tracing::error!("Failed to record usage: {}", e);
-                    } else {
-                        tracing::info!("Successfully recorded usage for: {}", process_name);
-                    }
+// 每小时检查一次，距离最近的备份超过 BACKUP_INTERVAL 时创建新备份
+async fn schedule_backups(handle: tauri::AppHandle) {
+    let mut interval = tokio::time::interval(BACKUP_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        // 解锁前没有可用的密钥，无法读取数据库文件
+        let state = handle.state::<AppState>();
+        if state.database_locked.load(Ordering::SeqCst) {
+            continue;
+        }
+        let _backup_guard = state.backup_lock.lock().unwrap();
+        let result = app_storage::database_path(&handle).and_then(|path| {
+            let now = chrono::Utc::now();
+            let latest = backup::list_backups(&path)?.into_iter().next();
+            match latest {
+                Some(latest) if now - latest.created_at < BACKUP_INTERVAL => Ok(()),
+                _ => backup::create_backup(&path, now).map(|_| ()),
+            }
+        });
+        if let Err(e) = result {
+            tracing::error!("Scheduled backup failed: {}", e);
+        }
+    }
+}
+
+// 按设置停止旧的服务并在需要时重新启动
+async fn apply_api_settings(app_handle: &AppHandle, settings: &ApiSettings) -> Result<(), String> {
+    let state = app_handle.state::<AppState>();
+    state.api_server.lock().map_err(|e| e.to_string())?.take();
+    if !settings.enabled {
+        return Ok(());
+    }
+    let server = ApiServer::start(settings.port, state.storage.clone(), &settings.token, state.live_usage.subscribe())
+        .await
+        .map_err(|e| format!("Failed to start HTTP API on port {}: {}", settings.port, e))?;
+    *state.api_server.lock().map_err(|e| e.to_string())? = Some(server);
+    Ok(())
+}
+
+// 启用了本地 HTTP API 时随应用启动
+fn start_saved_api(handle: &AppHandle) {
+    let api_settings = {
+        let state = handle.state::<AppState>();
+        let storage = state.storage.lock().unwrap();
+        ApiSettings::load(&storage)
+    };
+    match api_settings {
+        Ok(settings) if settings.enabled => {
+            let handle = handle.clone();
+            tauri::async_runtime::spawn(async move {
+                if let Err(e) = apply_api_settings(&handle, &settings).await {
+                    tracing::error!("{}", e);
                 }
-                app_usage.last_active = current_time;
+            });
+        }
+        Ok(_) => {}
+        Err(e) => tracing::error!("Failed to load HTTP API settings: {}", e),
+    }
+}
 
-                let data_clone = data.clone();
-                drop(data);
-                let _ = handle.emit("usage_updated", data_clone);
-            }
+#[tauri::command]
+async fn get_api_settings(state: tauri::State<'_, AppState>) -> Result<ApiSettings, String> {
+    let storage = state.storage.lock().map_err(|e| e.to_string())?;
+    ApiSettings::load(&storage).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_api_settings(app_handle: tauri::AppHandle, enabled: bool, port: u16) -> Result<ApiSettings, String> {
+    let settings = {
+        let state = app_handle.state::<AppState>();
+        ensure_unlocked(&state)?;
+        let storage = state.storage.lock().map_err(|e| e.to_string())?;
+        let settings = ApiSettings {
+            enabled,
+            port,
+            ..ApiSettings::load(&storage).map_err(|e| e.to_string())?
+        };
+        settings.save(&storage).map_err(|e| e.to_string())?;
+        settings
+    };
+    apply_api_settings(&app_handle, &settings).await?;
+    Ok(settings)
+}
+
+// 生成新令牌后重启服务，旧令牌立即失效
+#[tauri::command]
+async fn regenerate_api_token(app_handle: tauri::AppHandle) -> Result<ApiSettings, String> {
+    let settings = {
+        let state = app_handle.state::<AppState>();
+        ensure_unlocked(&state)?;
+        let storage = state.storage.lock().map_err(|e| e.to_string())?;
+        let settings = ApiSettings {
+            token: api::generate_token().map_err(|e| e.to_string())?,
+            ..ApiSettings::load(&storage).map_err(|e| e.to_string())?
+        };
+        settings.save(&storage).map_err(|e| e.to_string())?;
+        settings
+    };
+    apply_api_settings(&app_handle, &settings).await?;
+    Ok(settings)
+}
+
+#[tauri::command]
+async fn get_monitor_diagnostics(state: tauri::State<'_, AppState>) -> Result<MonitorDiagnostics, String> {
+    state.monitor_diagnostics
+        .lock()
+        .map_err(|e| e.to_string())?
+        .clone()
+        .ok_or_else(|| "Window monitor has not started yet".to_string())
+}
+
+fn notify_focus_completed(handle: &AppHandle, session: &FocusSession) {
+    let body = format!(
+        "Focus session finished: {} minutes focused, {} interruptions",
+        session.focused_time / 60,
+        session.interruptions.len()
+    );
+    if let Err(e) = handle.notification().builder().title("Time Whisper").body(body).show() {
+        tracing::error!("Failed to show focus notification: {}", e);
+    }
+    let _ = handle.emit("focus_completed", session);
+}
+
+fn notify_limit_alert(handle: &AppHandle, alert: &LimitAlert) {
+    let body = if alert.threshold >= 100 {
+        format!("You have reached today's limit of {} minutes for {}", alert.daily_limit / 60, alert.name)
+    } else {
+        format!("{} has used {}% of today's {} minute limit", alert.name, alert.threshold, alert.daily_limit / 60)
+    };
+    if let Err(e) = handle.notification().builder().title("Time Whisper").body(body).show() {
+        tracing::error!("Failed to show limit notification: {}", e);
+    }
+    let _ = handle.emit("limit_alert", alert);
+}
+
+// 会话构建输出一段记录时更新实时统计
+fn update_live_usage(handle: &AppHandle, record: &AppUsageRecord) {
+    let state = handle.state::<AppState>();
+    let current_time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+
+    let mut data = state.usage_data.lock().unwrap();
+    // 上一次更新在昨天时从零开始统计
+    let today = chrono::Local::now().date_naive();
+    let last_update = data.values().map(|usage| usage.last_active).max().unwrap_or(0);
+    let last_date = chrono::DateTime::from_timestamp(last_update as i64, 0)
+        .map(|time| time.with_timezone(&chrono::Local).date_naive());
+    if last_update > 0 && last_date != Some(today) {
+        data.clear();
+    }
+    let app_usage = data.entry(record.app_name.clone())
+        .or_insert(AppUsage {
+            name: record.app_name.clone(),
+            total_time: 0,
+            last_active: current_time,
+        });
+
+    // 空闲时间单独记录，不计入前台应用的使用时长
+    if record.state == UsageState::Active {
+        app_usage.total_time += record.duration;
+        tracing::debug!("Updating usage for {}: {} seconds", record.app_name, app_usage.total_time);
+    } else {
+        tracing::debug!("User idle, recording idle time while {} is in foreground", record.app_name);
+    }
+    app_usage.last_active = current_time;
+
+    let data_clone = data.clone();
+    drop(data);
+    state.live_usage.send_replace(data_clone.clone());
+    let _ = handle.emit("usage_updated", data_clone);
+    tray::update_tray(handle);
+}
+
+// 一批记录写入数据库后检查限额和专注会话
+fn after_flush(handle: &AppHandle, storage: &Storage, records: &[AppUsageRecord]) {
+    // 只有活跃时间会让限额的用量增加，也只有活跃时间计入专注
+    let active: Vec<_> = records.iter().filter(|r| r.state == UsageState::Active).collect();
+    if active.is_empty() {
+        return;
+    }
+    match storage.check_limits(chrono::Utc::now()) {
+        Ok(alerts) => alerts.iter().for_each(|alert| notify_limit_alert(handle, alert)),
+        Err(e) => tracing::error!("Failed to check usage limits: {}", e),
+    }
+    for record in active {
+        match storage.record_focus_tick(&record.app_name, record.timestamp, record.duration) {
+            Ok(Some(session)) => notify_focus_completed(handle, &session),
+            Ok(None) => {}
+            Err(e) => tracing::error!("Failed to record focus time: {}", e),
         }
-        tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
     }
 }
 
+async fn monitor_active_window(handle: tauri::AppHandle, shutdown: oneshot::Receiver<()>) {
+    tracing::info!("Starting window monitor...");
+    let (window_monitor, diagnostics) = platform::create_window_monitor();
+    *handle.state::<AppState>().monitor_diagnostics.lock().unwrap() = Some(diagnostics);
+    let idle_detector = platform::create_idle_detector();
+
+    // 写入使用单独的连接，不阻塞界面查询
+    let storage = match app_storage::open_storage(&handle) {
+        Ok(storage) => storage,
+        Err(e) => {
+            tracing::error!("Failed to open storage for the usage writer: {}", e);
+            return;
+        }
+    };
+
+    let idle_handle = handle.clone();
+    let source = FocusSource::new(window_monitor, idle_detector, move || {
+        *idle_handle.state::<AppState>().idle_threshold.lock().unwrap()
+    })
+    .with_pause_flag(handle.state::<AppState>().tracking_paused.clone())
+    .with_privacy_filter(handle.state::<AppState>().privacy_filter.clone());
+    let span_handle = handle.clone();
+    let flush_handle = handle.clone();
+    monitor::run_pipeline(
+        source,
+        BatchedWriter::new(storage),
+        shutdown,
+        move |record| update_live_usage(&span_handle, record),
+        move |storage, records| after_flush(&flush_handle, storage, records),
+    ).await;
+    tracing::info!("Window monitor stopped");
+}
+
+fn start_monitor(handle: &AppHandle) {
+    let handle_clone = handle.clone();
+    let (shutdown_tx, shutdown_rx) = oneshot::channel();
+    let monitor_task = tauri::async_runtime::spawn(async move {
+        monitor_active_window(handle_clone, shutdown_rx).await;
+    });
+    *handle.state::<AppState>().monitor_task.lock().unwrap() = Some((shutdown_tx, monitor_task));
+}
+
+// 等待监控任务写完缓存的记录，最多等待 MONITOR_SHUTDOWN_TIMEOUT
+async fn shutdown_monitor(handle: &AppHandle) {
+    let Some((shutdown, task)) = handle.state::<AppState>().monitor_task.lock().unwrap().take() else {
+        return;
+    };
+    let _ = shutdown.send(());
+    match tokio::time::timeout(MONITOR_SHUTDOWN_TIMEOUT, task).await {
+        Ok(_) => tracing::info!("Usage records flushed"),
+        Err(_) => tracing::warn!("Timed out waiting for the window monitor to flush"),
+    }
+}
+
+fn stop_monitor(handle: &AppHandle) {
+    tauri::async_runtime::block_on(shutdown_monitor(handle));
+}
+
 fn main() {
     tracing_subscriber::fmt()
         .with_max_level(tracing::Level::DEBUG)
@@ -151,21 +1023,38 @@ fn main() {
     };
 
     let result = tauri::Builder::default()
+        .plugin(tauri_plugin_notification::init())
         .setup(|app| {
             let handle = app.handle();
             
             // 在setup中初始化AppState
-            let app_state = AppState::new(&handle)
-                .expect("Failed to initialize app state");
+            let app_state = AppState::new(&handle).map_err(|e| e.to_string())?;
+            let locked = app_state.database_locked.load(Ordering::SeqCst);
             app.manage(app_state);
             
             tracing::info!("App state initialized successfully");
             
-            // 启动监控任务
-            let handle_clone = handle.clone();
-            tauri::async_runtime::spawn(async move {
-                monitor_active_window(handle_clone).await;
-            });
+            // 启动监控任务，加密的数据库解锁后才启动
+            if !locked {
+                start_monitor(handle);
+            }
+
+            // 定期汇总和清理旧数据，并备份数据库
+            tauri::async_runtime::spawn(schedule_maintenance(handle.clone()));
+            tauri::async_runtime::spawn(schedule_backups(handle.clone()));
+
+            // 关闭主窗口后通过托盘继续记录
+            tray::create_tray(handle)?;
+
+            // 上次退出前设置的定时暂停
+            let tracking_state = *handle.state::<AppState>().tracking_state.lock().unwrap();
+            if let TrackingState::PausedUntil(until) = tracking_state {
+                tauri::async_runtime::spawn(resume_when_due(handle.clone(), until));
+            }
+
+            if !locked {
+                start_saved_api(handle);
+            }
             
             tracing::info!("Tauri setup started");
             #[cfg(debug_assertions)]
@@ -176,17 +1065,81 @@ fn main() {
             tracing::info!("Tauri setup completed");
             Ok(())
         })
+        .on_window_event(|window, event| {
+            // 关闭窗口只是隐藏，监控任务继续运行，从托盘菜单退出
+            if let tauri::WindowEvent::CloseRequested { api, .. } = event {
+                if window.label() == "main" {
+                    let _ = window.hide();
+                    api.prevent_close();
+                }
+            }
+        })
         .invoke_handler(tauri::generate_handler![
             get_app_usage,
             toggle_auto_start,
             get_auto_start_status,
             get_app_usage_stats,
-            record_app_usage
+            get_usage_stats_range,
+            export_usage,
+            import_usage,
+            record_app_usage,
+            get_idle_threshold,
+            set_idle_threshold,
+            get_time_zone,
+            set_time_zone,
+            list_categories,
+            create_category,
+            update_category,
+            delete_category,
+            list_category_rules,
+            add_category_rule,
+            delete_category_rule,
+            get_category_stats,
+            get_productivity_scores,
+            list_limits,
+            set_limit,
+            delete_limit,
+            get_limit_status,
+            get_limit_thresholds,
+            set_limit_thresholds,
+            start_focus_session,
+            stop_focus_session,
+            get_active_focus_session,
+            get_focus_history,
+            get_api_settings,
+            set_api_settings,
+            regenerate_api_token,
+            get_monitor_diagnostics,
+            get_retention_policy,
+            set_retention_policy,
+            run_maintenance,
+            get_tracking_state,
+            set_tracking_state,
+            snooze_tracking,
+            get_tracking_pauses,
+            list_privacy_rules,
+            add_privacy_rule,
+            delete_privacy_rule,
+            purge_private_history,
+            get_encryption_status,
+            set_database_key,
+            disable_database_encryption,
+            unlock_database,
+            list_backups,
+            create_backup,
+            restore_backup
         ])
-        .run(tauri::generate_context!());
+        .build(tauri::generate_context!());
 
     match result {
-        Ok(_) => tracing::info!("Application exited normally"),
+        Ok(app) => {
+            app.run(|handle, event| {
+                if let tauri::RunEvent::Exit = event {
+                    stop_monitor(handle);
+                }
+            });
+            tracing::info!("Application exited normally");
+        }
         Err(e) => {
             tracing::error!("Application error: {}", e);
             std::process::exit(1);
@@ -0,0 +1,122 @@
+pub mod source;
+pub mod session;
+pub mod writer;
+
+use chrono::{DateTime, Utc};
+use tokio::sync::{mpsc, oneshot};
+use crate::db::storage::Storage;
+use crate::db::types::{AppUsageRecord, UsageState};
+use crate::platform::ActiveWindow;
+use self::session::SessionBuilder;
+use self::source::FocusSource;
+use self::writer::BatchedWriter;
+
+// 某一时刻的前台窗口和空闲状态，window 为空表示没有可统计的窗口
+#[derive(Debug, Clone, PartialEq)]
+pub struct FocusEvent {
+    pub timestamp: DateTime<Utc>,
+    pub window: Option<ActiveWindow>,
+    pub state: UsageState,
+}
+
+const CHANNEL_CAPACITY: usize = 256;
+
+// 事件源 -> 会话构建 -> 批量写入，三个环节通过通道连接
+// on_span 在会话构建输出一段记录时调用（用于实时统计），on_flush 在每次写入数据库后调用。
+// 收到 shutdown（或发送端被丢弃）后结束当前时间段，写入所有缓存的记录再返回
+pub async fn run_pipeline<I, S, F>(
+    source: FocusSource<I>,
+    writer: BatchedWriter,
+    mut shutdown: oneshot::Receiver<()>,
+    mut on_span: S,
+    on_flush: F,
+)
+where
+    I: Fn() -> u64 + Send + 'static,
+    S: FnMut(&AppUsageRecord) + Send + 'static,
+    F: FnMut(&Storage, &[AppUsageRecord]) + Send + 'static,
+{
+    let (event_tx, mut event_rx) = mpsc::channel::<FocusEvent>(CHANNEL_CAPACITY);
+    let (record_tx, record_rx) = mpsc::channel::<AppUsageRecord>(CHANNEL_CAPACITY);
+
+    let source_task = tokio::spawn(source.run(event_tx));
+    let writer_task = tokio::spawn(writer.run(record_rx, on_flush));
+
+    let mut builder = SessionBuilder::new();
+    let mut stopping = false;
+    loop {
+        let event = tokio::select! {
+            event = event_rx.recv() => match event {
+                Some(event) => event,
+                None => break,
+            },
+            _ = &mut shutdown => {
+                tracing::info!("Stopping window monitor");
+                stopping = true;
+                FocusEvent { timestamp: Utc::now(), window: None, state: UsageState::Active }
+            }
+        };
+        if let Some(record) = builder.handle(event) {
+            on_span(&record);
+            if record_tx.send(record).await.is_err() {
+                break;
+            }
+        }
+        if stopping {
+            break;
+        }
+    }
+
+    drop(record_tx);
+    source_task.abort();
+    let _ = writer_task.await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use crate::platform::{NoIdleDetector, WindowInfo};
+
+    struct FixedMonitor;
+
+    impl WindowInfo for FixedMonitor {
+        fn get_active_window(&self) -> Option<ActiveWindow> {
+            Some(ActiveWindow { process_name: "code".to_string(), ..Default::default() })
+        }
+
+        fn backend_name(&self) -> &'static str {
+            "fixed"
+        }
+    }
+
+    // 1. 停止时写入还没到写入间隔的记录
+    #[tokio::test]
+    async fn test_shutdown_flushes_pending_records() {
+        let source = FocusSource::new(Box::new(FixedMonitor), Box::new(NoIdleDetector), || 0);
+        let writer = BatchedWriter::new(Storage::open_in_memory().unwrap())
+            .with_flush_interval(Duration::from_secs(3600));
+        let (shutdown_tx, shutdown_rx) = oneshot::channel();
+        let spans = Arc::new(Mutex::new(0));
+        let flushed = Arc::new(Mutex::new(0));
+
+        let (spans_clone, flushed_clone) = (spans.clone(), flushed.clone());
+        let pipeline = tokio::spawn(run_pipeline(
+            source,
+            writer,
+            shutdown_rx,
+            move |record| *spans_clone.lock().unwrap() += record.duration,
+            move |_, records| *flushed_clone.lock().unwrap() += records.iter().map(|r| r.duration).sum::<u64>(),
+        ));
+
+        tokio::time::sleep(Duration::from_millis(2_100)).await;
+        assert_eq!(*flushed.lock().unwrap(), 0);
+        shutdown_tx.send(()).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), pipeline).await.unwrap().unwrap();
+
+        let flushed = *flushed.lock().unwrap();
+        assert!((2..=3).contains(&flushed), "flushed {} seconds", flushed);
+        assert_eq!(*spans.lock().unwrap(), flushed);
+    }
+}
@@ -0,0 +1,157 @@
+use chrono::{DateTime, Local, TimeZone, Utc};
+use crate::db::types::{AppUsageRecord, UsageState};
+use crate::platform::ActiveWindow;
+use super::FocusEvent;
+
+struct OpenSpan {
+    window: ActiveWindow,
+    state: UsageState,
+    start: DateTime<Utc>,
+}
+
+// 把焦点变化事件转换成带起止时间的使用记录
+pub struct SessionBuilder {
+    current: Option<OpenSpan>,
+}
+
+// 按整秒边界计算时长，首尾相接的记录加起来等于真实经过的时间，不会累积误差
+fn span_record(span: &OpenSpan, end: DateTime<Utc>) -> Option<AppUsageRecord> {
+    let start_secs = span.start.timestamp();
+    let duration = end.timestamp() - start_secs;
+    if duration <= 0 {
+        return None;
+    }
+    let timestamp = Utc.timestamp_opt(start_secs, 0).single()?;
+    Some(AppUsageRecord {
+        timestamp,
+        app_name: span.window.process_name.clone(),
+        duration: duration as u64,
+        state: span.state,
+        window_title: span.window.window_title.clone(),
+        window_class: span.window.window_class.clone(),
+        exe_path: span.window.exe_path.clone(),
+        pid: span.window.pid,
+        utc_offset: Some(Local.offset_from_utc_datetime(&timestamp.naive_utc()).local_minus_utc()),
+    })
+}
+
+impl SessionBuilder {
+    pub fn new() -> Self {
+        SessionBuilder { current: None }
+    }
+
+    // 窗口和状态不变时输出到事件时刻为止的整秒部分，否则结束当前时间段并开始新的
+    pub fn handle(&mut self, event: FocusEvent) -> Option<AppUsageRecord> {
+        if let Some(span) = &self.current {
+            if event.window.as_ref() == Some(&span.window) && event.state == span.state {
+                return self.checkpoint(event.timestamp);
+            }
+            // 乱序的事件按当前时间段的开始处理
+            if event.timestamp < span.start {
+                tracing::warn!("Focus event at {} is earlier than the open span", event.timestamp);
+            }
+        }
+        let closed = self.current.take()
+            .and_then(|span| span_record(&span, event.timestamp.max(span.start)));
+        self.current = event.window.map(|window| OpenSpan {
+            window,
+            state: event.state,
+            start: event.timestamp,
+        });
+        closed
+    }
+
+    // 输出当前时间段到 now 为止的整秒部分，剩余部分留在时间段中
+    pub fn checkpoint(&mut self, now: DateTime<Utc>) -> Option<AppUsageRecord> {
+        let span = self.current.as_mut()?;
+        let record = span_record(span, now)?;
+        span.start = record.timestamp + chrono::Duration::seconds(record.duration as i64);
+        Some(record)
+    }
+}
+
+impl Default for SessionBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn at(millis: i64) -> DateTime<Utc> {
+        Utc.timestamp_millis_opt(1_772_000_000_000 + millis).unwrap()
+    }
+
+    fn event(millis: i64, app_name: Option<&str>, state: UsageState) -> FocusEvent {
+        FocusEvent {
+            timestamp: at(millis),
+            window: app_name.map(|name| ActiveWindow {
+                process_name: name.to_string(),
+                ..Default::default()
+            }),
+            state,
+        }
+    }
+
+    fn total(records: &[AppUsageRecord], app_name: &str) -> u64 {
+        records.iter().filter(|r| r.app_name == app_name).map(|r| r.duration).sum()
+    }
+
+    // 1. 时长来自事件的时间戳，而不是检查的次数
+    #[test]
+    fn test_spans_from_timestamps() {
+        let mut builder = SessionBuilder::new();
+        assert!(builder.handle(event(0, Some("code"), UsageState::Active)).is_none());
+        // 相同的窗口和状态只输出已经过的整秒
+        let record = builder.handle(event(4_500, Some("code"), UsageState::Active)).unwrap();
+        assert_eq!((record.timestamp, record.duration), (at(0), 4));
+
+        let record = builder.handle(event(12_300, Some("firefox"), UsageState::Active)).unwrap();
+        assert_eq!((record.app_name.as_str(), record.timestamp, record.duration), ("code", at(4_000), 8));
+
+        let record = builder.handle(event(15_000, Some("firefox"), UsageState::Idle)).unwrap();
+        assert_eq!((record.app_name.as_str(), record.timestamp, record.duration), ("firefox", at(12_000), 3));
+
+        // 没有窗口时结束当前时间段，之后不再产生记录
+        let record = builder.handle(event(20_000, None, UsageState::Active)).unwrap();
+        assert_eq!((record.state, record.duration), (UsageState::Idle, 5));
+        assert!(builder.checkpoint(at(60_000)).is_none());
+    }
+
+    // 2. 定期切分不会丢失或重复计算不足一秒的部分
+    #[test]
+    fn test_checkpoints_add_up() {
+        let mut builder = SessionBuilder::new();
+        let mut records = Vec::new();
+        builder.handle(event(500, Some("code"), UsageState::Active));
+        for millis in [900, 1_700, 2_600, 2_650, 4_100, 9_999] {
+            records.extend(builder.checkpoint(at(millis)));
+        }
+        records.extend(builder.handle(event(10_400, Some("firefox"), UsageState::Active)));
+        records.extend(builder.checkpoint(at(11_200)));
+
+        assert_eq!(total(&records, "code"), 10);
+        assert_eq!(total(&records, "firefox"), 1);
+        // 记录首尾相接
+        for pair in records.windows(2) {
+            assert_eq!(pair[0].timestamp + Duration::seconds(pair[0].duration as i64), pair[1].timestamp);
+        }
+    }
+
+    // 3. 窗口标题变化也会切分，便于按标题统计
+    #[test]
+    fn test_title_change_splits_span() {
+        let mut builder = SessionBuilder::new();
+        let mut first = event(0, Some("code"), UsageState::Active);
+        first.window.as_mut().unwrap().window_title = Some("main.rs".to_string());
+        let mut second = event(3_000, Some("code"), UsageState::Active);
+        second.window.as_mut().unwrap().window_title = Some("lib.rs".to_string());
+
+        builder.handle(first);
+        let record = builder.handle(second).unwrap();
+        assert_eq!((record.window_title.as_deref(), record.duration), (Some("main.rs"), 3));
+    }
+}
@@ -0,0 +1,250 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use chrono::Utc;
+use tokio::sync::mpsc;
+use crate::db::privacy::PrivacyFilter;
+use crate::db::types::UsageState;
+use crate::platform::{ActiveWindow, IdleDetector, WindowInfo};
+use super::FocusEvent;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+// 支持事件推送的后端只需要定期检查空闲状态
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+// 两次检查的间隔比预期的等待时间多出这个值说明系统休眠过或循环被阻塞，这段时间不计入
+const MAX_POLL_DELAY: Duration = Duration::from_secs(2);
+
+// 每次检查前台窗口和空闲状态时发出带时间戳的事件，焦点切换时立即检查
+pub struct FocusSource<I> {
+    window_monitor: Box<dyn WindowInfo>,
+    idle_detector: Box<dyn IdleDetector>,
+    idle_threshold: I,
+    // 暂停期间不读取前台窗口，当作没有窗口
+    paused: Option<Arc<AtomicBool>>,
+    // 读取到的窗口先按隐私规则处理，规则修改后立即生效
+    privacy_filter: Option<Arc<RwLock<PrivacyFilter>>>,
+}
+
+impl<I: Fn() -> u64 + Send + 'static> FocusSource<I> {
+    // idle_threshold 每次检查时读取，设置修改后立即生效
+    pub fn new(window_monitor: Box<dyn WindowInfo>, idle_detector: Box<dyn IdleDetector>, idle_threshold: I) -> Self {
+        FocusSource { window_monitor, idle_detector, idle_threshold, paused: None, privacy_filter: None }
+    }
+
+    pub fn with_pause_flag(mut self, paused: Arc<AtomicBool>) -> Self {
+        self.paused = Some(paused);
+        self
+    }
+
+    pub fn with_privacy_filter(mut self, filter: Arc<RwLock<PrivacyFilter>>) -> Self {
+        self.privacy_filter = Some(filter);
+        self
+    }
+
+    fn active_window(&self) -> Option<ActiveWindow> {
+        let window = self.window_monitor.get_active_window()?;
+        match &self.privacy_filter {
+            Some(filter) => filter.read().unwrap().apply_window(window),
+            None => Some(window),
+        }
+    }
+
+    fn is_paused(&self) -> bool {
+        self.paused.as_ref().is_some_and(|paused| paused.load(Ordering::Relaxed))
+    }
+
+    fn current_state(&self) -> UsageState {
+        let threshold = (self.idle_threshold)();
+        match self.idle_detector.get_idle_seconds() {
+            Some(idle) if threshold > 0 && idle >= threshold => UsageState::Idle,
+            _ => UsageState::Active,
+        }
+    }
+
+    pub async fn run(self, events: mpsc::Sender<FocusEvent>) {
+        let mut focus_events = self.window_monitor.focus_events();
+        let mut last: Option<(Option<ActiveWindow>, UsageState)> = None;
+        let mut last_poll_time = Utc::now();
+        let mut wait = POLL_INTERVAL;
+
+        loop {
+            // 按墙上时间判断，系统休眠期间单调时钟不会前进。休眠前的时间段在上一次检查时结束
+            let gap = Utc::now() - last_poll_time;
+            if gap.to_std().is_ok_and(|gap| gap > wait + MAX_POLL_DELAY) && matches!(last, Some((Some(_), _))) {
+                tracing::info!("Focus polling resumed after {}s, closing the open span", gap.num_seconds());
+                let event = FocusEvent { timestamp: last_poll_time, window: None, state: UsageState::Active };
+                if events.send(event).await.is_err() {
+                    return;
+                }
+                last = Some((None, UsageState::Active));
+            }
+
+            let (window, state) = if self.is_paused() {
+                (None, UsageState::Active)
+            } else {
+                (self.active_window(), self.current_state())
+            };
+            last_poll_time = Utc::now();
+
+            if last.as_ref() != Some(&(window.clone(), state)) {
+                if let Some(window) = &window {
+                    tracing::debug!("Focus changed to {} ({:?}), {}", window.process_name, window.window_title, state.as_str());
+                }
+            }
+            let event = FocusEvent { timestamp: last_poll_time, window: window.clone(), state };
+            if events.send(event).await.is_err() {
+                return;
+            }
+            last = Some((window, state));
+
+            // 支持事件推送的后端等待焦点切换，空闲状态按较慢的间隔检查
+            let mut events_closed = false;
+            match focus_events.as_mut() {
+                Some(changes) => {
+                    wait = IDLE_CHECK_INTERVAL;
+                    tokio::select! {
+                        _ = tokio::time::sleep(wait) => {}
+                        changed = changes.changed() => events_closed = changed.is_err(),
+                    }
+                }
+                None => {
+                    wait = POLL_INTERVAL;
+                    tokio::time::sleep(wait).await;
+                }
+            }
+            if events_closed {
+                tracing::warn!("Focus events stopped, falling back to polling");
+                focus_events = None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    struct FakeMonitor {
+        window: Arc<Mutex<Option<ActiveWindow>>>,
+        changes: tokio::sync::watch::Receiver<Option<ActiveWindow>>,
+    }
+
+    impl WindowInfo for FakeMonitor {
+        fn get_active_window(&self) -> Option<ActiveWindow> {
+            self.window.lock().unwrap().clone()
+        }
+
+        fn backend_name(&self) -> &'static str {
+            "fake"
+        }
+
+        fn focus_events(&self) -> Option<tokio::sync::watch::Receiver<Option<ActiveWindow>>> {
+            Some(self.changes.clone())
+        }
+    }
+
+    struct FakeIdle(Arc<Mutex<u64>>);
+
+    impl IdleDetector for FakeIdle {
+        fn get_idle_seconds(&self) -> Option<u64> {
+            Some(*self.0.lock().unwrap())
+        }
+    }
+
+    // 跳过没有变化的检查结果
+    async fn next_change(rx: &mut mpsc::Receiver<FocusEvent>, previous: &FocusEvent) -> FocusEvent {
+        loop {
+            let event = rx.recv().await.unwrap();
+            if event.window != previous.window || event.state != previous.state {
+                return event;
+            }
+        }
+    }
+
+    fn window(name: &str) -> ActiveWindow {
+        ActiveWindow { process_name: name.to_string(), ..Default::default() }
+    }
+
+    // 1. 焦点变化通过推送立即检查，空闲状态变化在下一次空闲检查时发出
+    #[tokio::test]
+    async fn test_emits_on_change() {
+        let current = Arc::new(Mutex::new(Some(window("code"))));
+        let idle = Arc::new(Mutex::new(0));
+        let (changes_tx, changes) = tokio::sync::watch::channel(None);
+        let source = FocusSource::new(
+            Box::new(FakeMonitor { window: current.clone(), changes }),
+            Box::new(FakeIdle(idle.clone())),
+            || 60,
+        );
+        let (tx, mut rx) = mpsc::channel(8);
+        let task = tokio::spawn(source.run(tx));
+
+        let first = rx.recv().await.unwrap();
+        assert_eq!((first.window.clone(), first.state), (Some(window("code")), UsageState::Active));
+
+        *current.lock().unwrap() = Some(window("firefox"));
+        changes_tx.send_replace(Some(window("firefox")));
+        let second = tokio::time::timeout(Duration::from_millis(500), next_change(&mut rx, &first)).await.unwrap();
+        assert_eq!(second.window.clone(), Some(window("firefox")));
+        assert!(second.timestamp >= first.timestamp);
+
+        *idle.lock().unwrap() = 120;
+        let third = next_change(&mut rx, &second).await;
+        assert_eq!((third.window, third.state), (Some(window("firefox")), UsageState::Idle));
+        task.abort();
+    }
+
+    // 2. 暂停期间发出没有窗口的事件，恢复后重新读取前台窗口
+    #[tokio::test]
+    async fn test_pause_flag() {
+        let (_changes_tx, changes) = tokio::sync::watch::channel(None);
+        let paused = Arc::new(AtomicBool::new(true));
+        let source = FocusSource::new(
+            Box::new(FakeMonitor { window: Arc::new(Mutex::new(Some(window("code")))), changes }),
+            Box::new(FakeIdle(Arc::new(Mutex::new(0)))),
+            || 60,
+        ).with_pause_flag(paused.clone());
+        let (tx, mut rx) = mpsc::channel(8);
+        let task = tokio::spawn(source.run(tx));
+
+        let first = rx.recv().await.unwrap();
+        assert_eq!(first.window, None);
+        paused.store(false, Ordering::Relaxed);
+        let second = next_change(&mut rx, &first).await;
+        assert_eq!(second.window, Some(window("code")));
+        task.abort();
+    }
+
+    // 3. 隐私规则在窗口离开数据源之前生效
+    #[tokio::test]
+    async fn test_privacy_filter() {
+        use crate::db::types::{MatchField, MatchKind, PrivacyAction, PrivacyRule};
+
+        let rule = |id, pattern: &str, action| PrivacyRule {
+            id,
+            field: MatchField::ProcessName,
+            kind: MatchKind::Glob,
+            pattern: pattern.to_string(),
+            action,
+        };
+        let filter = Arc::new(RwLock::new(PrivacyFilter::new(&[rule(1, "keepass*", PrivacyAction::Drop)]).unwrap()));
+        let current = Arc::new(Mutex::new(Some(window("keepassxc"))));
+        let (_changes_tx, changes) = tokio::sync::watch::channel(None);
+        let source = FocusSource::new(
+            Box::new(FakeMonitor { window: current.clone(), changes }),
+            Box::new(FakeIdle(Arc::new(Mutex::new(0)))),
+            || 60,
+        ).with_privacy_filter(filter.clone());
+        let (tx, mut rx) = mpsc::channel(8);
+        let task = tokio::spawn(source.run(tx));
+
+        let first = rx.recv().await.unwrap();
+        assert_eq!(first.window, None);
+
+        *filter.write().unwrap() = PrivacyFilter::new(&[rule(2, "keepass*", PrivacyAction::Private)]).unwrap();
+        let second = next_change(&mut rx, &first).await;
+        assert_eq!(second.window, Some(window(crate::db::privacy::PRIVATE_APP_NAME)));
+        task.abort();
+    }
+}
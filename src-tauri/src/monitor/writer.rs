@@ -0,0 +1,168 @@
+use std::time::Duration;
+use chrono::Duration as ChronoDuration;
+use tokio::sync::mpsc;
+use crate::db::storage::Storage;
+use crate::db::types::AppUsageRecord;
+
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+const MAX_BATCH_SIZE: usize = 64;
+
+// 从通道接收记录，攒够一批或到达写入间隔时一次性写入数据库
+pub struct BatchedWriter {
+    storage: Storage,
+    flush_interval: Duration,
+    max_batch_size: usize,
+    pending: Vec<AppUsageRecord>,
+}
+
+// 首尾相接且窗口、状态相同的记录合并为一条
+fn coalesce(pending: &mut Vec<AppUsageRecord>, record: AppUsageRecord) {
+    if let Some(last) = pending.last_mut() {
+        if last.app_name == record.app_name
+            && last.state == record.state
+            && last.window_title == record.window_title
+            && last.window_class == record.window_class
+            && last.exe_path == record.exe_path
+            && last.pid == record.pid
+            && last.utc_offset == record.utc_offset
+            && last.timestamp + ChronoDuration::seconds(last.duration as i64) == record.timestamp
+        {
+            last.duration += record.duration;
+            return;
+        }
+    }
+    pending.push(record);
+}
+
+impl BatchedWriter {
+    pub fn new(storage: Storage) -> Self {
+        BatchedWriter {
+            storage,
+            flush_interval: FLUSH_INTERVAL,
+            max_batch_size: MAX_BATCH_SIZE,
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn with_flush_interval(mut self, flush_interval: Duration) -> Self {
+        self.flush_interval = flush_interval;
+        self
+    }
+
+    pub fn push(&mut self, record: AppUsageRecord) {
+        coalesce(&mut self.pending, record);
+    }
+
+    pub fn pending(&self) -> &[AppUsageRecord] {
+        &self.pending
+    }
+
+    // 写入失败时保留未写入的记录，下次再试
+    pub fn flush<F: FnMut(&Storage, &[AppUsageRecord])>(&mut self, on_flush: &mut F) {
+        if self.pending.is_empty() {
+            return;
+        }
+        match self.storage.record_batch(&self.pending) {
+            Ok(()) => {
+                tracing::debug!("Flushed {} usage records", self.pending.len());
+                let batch = std::mem::take(&mut self.pending);
+                on_flush(&self.storage, &batch);
+            }
+            Err(e) => tracing::error!("Failed to flush {} usage records: {}", self.pending.len(), e),
+        }
+    }
+
+    // 通道关闭时写入剩余的记录后返回
+    pub async fn run<F: FnMut(&Storage, &[AppUsageRecord])>(mut self, mut records: mpsc::Receiver<AppUsageRecord>, mut on_flush: F) {
+        let mut ticker = tokio::time::interval(self.flush_interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                record = records.recv() => match record {
+                    Some(record) => {
+                        self.push(record);
+                        if self.pending.len() >= self.max_batch_size {
+                            self.flush(&mut on_flush);
+                        }
+                    }
+                    None => break,
+                },
+                _ = ticker.tick() => self.flush(&mut on_flush),
+            }
+        }
+        self.flush(&mut on_flush);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, TimeZone, Utc};
+    use crate::db::types::{UsageQuery, UsageState};
+
+    fn at(secs: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(1_772_000_000 + secs, 0).unwrap()
+    }
+
+    fn record(secs: i64, app_name: &str, duration: u64) -> AppUsageRecord {
+        AppUsageRecord {
+            timestamp: at(secs),
+            app_name: app_name.to_string(),
+            duration,
+            state: UsageState::Active,
+            window_title: None,
+            window_class: None,
+            exe_path: None,
+            pid: None,
+            utc_offset: Some(0),
+        }
+    }
+
+    // 1. 首尾相接的同一窗口合并，中间有间隔或切换窗口时分开
+    #[test]
+    fn test_coalesce_contiguous_records() {
+        let mut writer = BatchedWriter::new(Storage::open_in_memory().unwrap());
+        writer.push(record(0, "code", 1));
+        writer.push(record(1, "code", 2));
+        writer.push(record(3, "firefox", 1));
+        writer.push(record(4, "firefox", 1));
+        writer.push(record(10, "firefox", 1));
+
+        let summary: Vec<_> = writer.pending().iter().map(|r| (r.app_name.as_str(), r.timestamp, r.duration)).collect();
+        assert_eq!(summary, vec![("code", at(0), 3), ("firefox", at(3), 2), ("firefox", at(10), 1)]);
+    }
+
+    // 2. 通道关闭时写入剩余记录，写入后调用回调
+    #[tokio::test]
+    async fn test_run_flushes_on_close() {
+        let writer = BatchedWriter::new(Storage::open_in_memory().unwrap())
+            .with_flush_interval(Duration::from_secs(3600));
+        let (tx, rx) = mpsc::channel(8);
+        for secs in 0..5 {
+            tx.send(record(secs, "code", 1)).await.unwrap();
+        }
+        tx.send(record(5, "firefox", 2)).await.unwrap();
+        drop(tx);
+
+        let mut flushed = Vec::new();
+        let mut totals = Vec::new();
+        writer.run(rx, |storage, batch| {
+            flushed.extend(batch.iter().map(|r| (r.app_name.clone(), r.duration)));
+            let query = UsageQuery {
+                from: at(0),
+                to: at(60),
+                granularity: Default::default(),
+                apps: Vec::new(),
+                limit: None,
+                time_zone: Some("UTC".to_string()),
+            };
+            totals = storage.get_usage_stats_range(&query).unwrap().into_iter()
+                .map(|stats| (stats.name, stats.total_time))
+                .collect();
+        }).await;
+
+        assert_eq!(flushed, vec![("code".to_string(), 5), ("firefox".to_string(), 2)]);
+        totals.sort();
+        assert_eq!(totals, vec![("code".to_string(), 5), ("firefox".to_string(), 2)]);
+    }
+}
@@ -1,63 +1,648 @@
-#[cfg(target_os = "linux")]
-mod linux {
-    use super::WindowInfo;
-    use x11rb::connection::Connection;
-    use x11rb::protocol::xproto::*;
-    use std::process::Command;
-    use std::path::PathBuf;
+use super::{ActiveWindow, AutoStart, BackendProbe, IdleDetector, MonitorDiagnostics, WindowInfo};
+use super::wayland::{GnomeShellMonitor, KWinMonitor, WlrToplevelMonitor};
+use x11rb::connection::Connection;
+use x11rb::errors::ConnectionError;
+use x11rb::protocol::screensaver::ConnectionExt as _;
+use x11rb::protocol::xproto::*;
+use x11rb::protocol::Event;
+use x11rb::rust_connection::RustConnection;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tokio::sync::watch;
+
+const DESKTOP_ENTRY_NAME: &str = "time-whisper.desktop";
+const SYSTEMD_UNIT_NAME: &str = "time-whisper.service";
+const SYSTEMD_TARGET: &str = "graphical-session.target";
+// 设置为 systemd 时使用 systemd 用户服务代替 XDG autostart
+const AUTOSTART_MODE_ENV: &str = "TIME_WHISPER_AUTOSTART";
+const X11_RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+pub struct Linux;
+// 保持一个 X11 连接，由后台线程监听焦点变化并推送最新的前台窗口
+pub struct LinuxMonitor {
+    updates: Arc<watch::Sender<Option<ActiveWindow>>>,
+    // 释放时通知后台线程退出
+    stop: Arc<AtomicBool>,
+    waker: Arc<Mutex<Option<X11Waker>>>,
+    thread: thread::Thread,
+}
+
+impl LinuxMonitor {
+    // 连不上 X 服务器时在后台重试
+    pub fn new() -> Self {
+        Self::start(X11Tracker::connect().ok())
+    }
 
-    pub struct Linux;
-    pub struct LinuxMonitor;
+    pub fn connect() -> Result<Self, String> {
+        Ok(Self::start(Some(X11Tracker::connect()?)))
+    }
 
-    impl LinuxMonitor {
-        pub fn new() -> Self {
-            LinuxMonitor
+    fn start(tracker: Option<X11Tracker>) -> Self {
+        let (updates, _) = watch::channel(None);
+        let updates = Arc::new(updates);
+        let mut tracker = tracker;
+        if let Some(tracker) = tracker.as_mut() {
+            updates.send_replace(tracker.active_window());
         }
+        let stop = Arc::new(AtomicBool::new(false));
+        let waker = Arc::new(Mutex::new(None));
+        let (thread_updates, thread_stop, thread_waker) = (updates.clone(), stop.clone(), waker.clone());
+        let thread = thread::spawn(move || track_focus(tracker, thread_updates, thread_stop, thread_waker))
+            .thread()
+            .clone();
+        LinuxMonitor { updates, stop, waker, thread }
     }
+}
 
-    impl WindowInfo for LinuxMonitor {
-        fn get_active_window(&self) -> Option<String> {
-            let (conn, screen_num) = x11rb::connect(None).ok()?;
-            let screen = &conn.setup().roots[screen_num];
-            
-            let active_window = conn.get_property(
-                false,
-                screen.root,
-                conn.atom("_NET_ACTIVE_WINDOW")?,
-                conn.atom("WINDOW")?,
-                0,
-                1
-            ).ok()?.reply().ok()?;
-
-            if active_window.value.len() >= 4 {
-                let window_id = u32::from_ne_bytes(active_window.value[0..4].try_into().ok()?);
-                
-                let pid = conn.get_property(
-                    false,
-                    window_id,
-                    conn.atom("_NET_WM_PID")?,
-                    conn.atom("CARDINAL")?,
-                    0,
-                    1
-                ).ok()?.reply().ok()?;
-
-                if pid.value.len() >= 4 {
-                    let pid = u32::from_ne_bytes(pid.value[0..4].try_into().ok()?);
-                    
-                    let output = Command::new("ps")
-                        .arg("-p")
-                        .arg(pid.to_string())
-                        .arg("-o")
-                        .arg("comm=")
-                        .output()
-                        .ok()?;
-                    
-                    return String::from_utf8(output.stdout).ok()
-                        .map(|s| s.trim().to_string());
+impl Drop for LinuxMonitor {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(waker) = self.waker.lock().unwrap().as_ref() {
+            waker.wake();
+        }
+        // 等待重连时直接唤醒
+        self.thread.unpark();
+    }
+}
+
+// 向后台线程自己创建的窗口发送 ClientMessage，让阻塞在 wait_for_event 的线程醒来
+struct X11Waker {
+    conn: Arc<RustConnection>,
+    window: Window,
+}
+
+impl X11Waker {
+    fn wake(&self) {
+        let event = ClientMessageEvent::new(32, self.window, AtomEnum::NONE, [0u32; 5]);
+        // 事件掩码为空时事件发给创建该窗口的客户端
+        let _ = self.conn.send_event(false, self.window, EventMask::NO_EVENT, event);
+        let _ = self.conn.flush();
+    }
+}
+
+struct X11Tracker {
+    conn: Arc<RustConnection>,
+    root: Window,
+    net_active_window: Atom,
+    net_wm_name: Atom,
+    net_wm_pid: Atom,
+    utf8_string: Atom,
+    // 只用于接收唤醒消息的不可见窗口
+    wakeup_window: Window,
+    // 当前前台窗口，监听它的标题变化
+    watched: Window,
+}
+
+impl X11Tracker {
+    fn connect() -> Result<Self, String> {
+        let (conn, screen_num) = x11rb::connect(None).map_err(|e| e.to_string())?;
+        let root = conn.setup().roots[screen_num].root;
+        let atom = |name: &str| intern_atom(&conn, name).ok_or_else(|| format!("Failed to intern {}", name));
+        let (net_active_window, net_wm_name, net_wm_pid, utf8_string) =
+            (atom("_NET_ACTIVE_WINDOW")?, atom("_NET_WM_NAME")?, atom("_NET_WM_PID")?, atom("UTF8_STRING")?);
+
+        // 窗口管理器切换焦点时会更新根窗口的 _NET_ACTIVE_WINDOW
+        conn.change_window_attributes(root, &ChangeWindowAttributesAux::new().event_mask(EventMask::PROPERTY_CHANGE))
+            .map_err(|e| e.to_string())?
+            .check()
+            .map_err(|e| e.to_string())?;
+
+        let wakeup_window = conn.generate_id().map_err(|e| e.to_string())?;
+        conn.create_window(
+            x11rb::COPY_DEPTH_FROM_PARENT,
+            wakeup_window,
+            root,
+            0, 0, 1, 1, 0,
+            WindowClass::INPUT_ONLY,
+            x11rb::COPY_FROM_PARENT,
+            &CreateWindowAux::new(),
+        )
+            .map_err(|e| e.to_string())?
+            .check()
+            .map_err(|e| e.to_string())?;
+
+        Ok(X11Tracker {
+            conn: Arc::new(conn),
+            root,
+            net_active_window,
+            net_wm_name,
+            net_wm_pid,
+            utf8_string,
+            wakeup_window,
+            watched: x11rb::NONE,
+        })
+    }
+
+    fn waker(&self) -> X11Waker {
+        X11Waker { conn: self.conn.clone(), window: self.wakeup_window }
+    }
+
+    fn active_window(&mut self) -> Option<ActiveWindow> {
+        let window_id = self.conn.get_property(false, self.root, self.net_active_window, AtomEnum::WINDOW, 0, 1)
+            .ok()?.reply().ok()?
+            .value32()?.next()
+            .unwrap_or(x11rb::NONE);
+        self.watch(window_id);
+        if window_id == x11rb::NONE {
+            return None;
+        }
+
+        let conn = &*self.conn;
+        let pid = get_cardinal_property(conn, window_id, self.net_wm_pid)?;
+        let window_class = get_text_property(conn, window_id, AtomEnum::WM_CLASS.into(), AtomEnum::STRING.into())
+            .and_then(|value| parse_wm_class(&value));
+        window_for_pid(pid, get_window_title(conn, window_id, self.net_wm_name, self.utf8_string), window_class)
+    }
+
+    fn watch(&mut self, window: Window) {
+        if window == self.watched {
+            return;
+        }
+        // 旧窗口可能已经销毁，出错也没有关系
+        if self.watched != x11rb::NONE {
+            let _ = self.conn.change_window_attributes(self.watched, &ChangeWindowAttributesAux::new().event_mask(EventMask::NO_EVENT));
+        }
+        if window != x11rb::NONE {
+            let _ = self.conn.change_window_attributes(window, &ChangeWindowAttributesAux::new().event_mask(EventMask::PROPERTY_CHANGE));
+        }
+        let _ = self.conn.flush();
+        self.watched = window;
+    }
+
+    fn is_focus_change(&self, event: &Event) -> bool {
+        match event {
+            Event::PropertyNotify(notify) if notify.window == self.root => notify.atom == self.net_active_window,
+            Event::PropertyNotify(notify) if notify.window == self.watched => {
+                notify.atom == self.net_wm_name || notify.atom == u32::from(AtomEnum::WM_NAME)
+            }
+            _ => false,
+        }
+    }
+
+    // 阻塞等待事件，直到连接断开或 stop 被设置
+    fn run(&mut self, updates: &watch::Sender<Option<ActiveWindow>>, stop: &AtomicBool) -> Result<(), ConnectionError> {
+        while !stop.load(Ordering::SeqCst) {
+            let event = self.conn.wait_for_event()?;
+            if self.is_focus_change(&event) {
+                let window = self.active_window();
+                updates.send_if_modified(|current| {
+                    let changed = *current != window;
+                    *current = window;
+                    changed
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+fn track_focus(
+    mut tracker: Option<X11Tracker>,
+    updates: Arc<watch::Sender<Option<ActiveWindow>>>,
+    stop: Arc<AtomicBool>,
+    waker: Arc<Mutex<Option<X11Waker>>>,
+) {
+    loop {
+        if let Some(mut connected) = tracker.take() {
+            // 先登记唤醒方式再检查 stop，LinuxMonitor 释放时一定能唤醒这个连接
+            *waker.lock().unwrap() = Some(connected.waker());
+            if let Err(e) = connected.run(&updates, &stop) {
+                tracing::warn!("X11 connection lost: {}", e);
+                updates.send_replace(None);
+            }
+            *waker.lock().unwrap() = None;
+        }
+        if stop.load(Ordering::SeqCst) {
+            tracing::debug!("X11 focus tracker stopped");
+            return;
+        }
+        thread::park_timeout(X11_RECONNECT_DELAY);
+        if stop.load(Ordering::SeqCst) {
+            return;
+        }
+        tracker = X11Tracker::connect().ok();
+    }
+}
+
+fn intern_atom(conn: &impl Connection, name: &str) -> Option<Atom> {
+    Some(conn.intern_atom(false, name.as_bytes()).ok()?.reply().ok()?.atom)
+}
+
+fn get_cardinal_property(conn: &impl Connection, window: Window, property: Atom) -> Option<u32> {
+    let reply = conn.get_property(false, window, property, AtomEnum::CARDINAL, 0, 1)
+        .ok()?.reply().ok()?;
+    let value = reply.value32()?.next();
+    value
+}
+
+fn get_text_property(conn: &impl Connection, window: Window, property: Atom, type_: Atom) -> Option<Vec<u8>> {
+    let reply = conn.get_property(false, window, property, type_, 0, 1024)
+        .ok()?.reply().ok()?;
+    if reply.value.is_empty() {
+        None
+    } else {
+        Some(reply.value)
+    }
+}
+
+fn get_window_title(conn: &impl Connection, window: Window, net_wm_name: Atom, utf8_string: Atom) -> Option<String> {
+    // 优先使用 EWMH 的 UTF-8 标题，旧程序只设置 WM_NAME
+    let title = match get_text_property(conn, window, net_wm_name, utf8_string) {
+        Some(value) => value,
+        None => get_text_property(conn, window, AtomEnum::WM_NAME.into(), AtomEnum::ANY.into())?,
+    };
+    Some(String::from_utf8_lossy(&title).trim().to_string())
+}
+
+// WM_CLASS 是以 \0 分隔的 "实例名\0类名\0"，取类名
+fn parse_wm_class(value: &[u8]) -> Option<String> {
+    let parts: Vec<&[u8]> = value.split(|b| *b == 0).filter(|part| !part.is_empty()).collect();
+    parts.get(1).or(parts.first())
+        .map(|part| String::from_utf8_lossy(part).to_string())
+}
+
+// 与 ps -o comm= 相同，内核截断为 15 个字符
+fn get_process_name(pid: u32) -> Option<String> {
+    fs::read_to_string(format!("/proc/{}/comm", pid)).ok()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
+impl WindowInfo for LinuxMonitor {
+    fn get_active_window(&self) -> Option<ActiveWindow> {
+        self.updates.borrow().clone()
+    }
+
+    fn backend_name(&self) -> &'static str {
+        "x11"
+    }
+
+    fn focus_events(&self) -> Option<watch::Receiver<Option<ActiveWindow>>> {
+        Some(self.updates.subscribe())
+    }
+}
+
+// 根据窗口所属进程补全进程名和可执行文件路径，X11 与 Wayland 后端共用
+pub(super) fn window_for_pid(pid: u32, window_title: Option<String>, window_class: Option<String>) -> Option<ActiveWindow> {
+    let process_name = get_process_name(pid)?;
+    let exe_path = std::fs::read_link(format!("/proc/{}/exe", pid))
+        .ok()
+        .map(|path| path.to_string_lossy().to_string());
+    Some(ActiveWindow {
+        process_name,
+        pid: Some(pid),
+        exe_path,
+        window_title,
+        window_class,
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinuxBackend {
+    GnomeShell,
+    KWin,
+    WlrToplevel,
+    X11,
+}
+
+impl LinuxBackend {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LinuxBackend::GnomeShell => "gnome-shell-extension",
+            LinuxBackend::KWin => "kwin-script",
+            LinuxBackend::WlrToplevel => "wlr-foreign-toplevel",
+            LinuxBackend::X11 => "x11",
+        }
+    }
+
+    fn connect(&self) -> Result<Box<dyn WindowInfo>, String> {
+        match self {
+            LinuxBackend::GnomeShell => Ok(Box::new(GnomeShellMonitor::connect()?)),
+            LinuxBackend::KWin => Ok(Box::new(KWinMonitor::connect()?)),
+            LinuxBackend::WlrToplevel => Ok(Box::new(WlrToplevelMonitor::connect()?)),
+            // Wayland 下连上的通常是 XWayland
+            LinuxBackend::X11 => Ok(Box::new(LinuxMonitor::connect()?)),
+        }
+    }
+}
+
+// Wayland 下优先使用当前桌面对应的后端，其余后端依次作为后备，最后回退到 XWayland
+pub fn backend_order(session_type: Option<&str>, desktop: Option<&str>) -> Vec<LinuxBackend> {
+    if !session_type.is_some_and(|session| session.eq_ignore_ascii_case("wayland")) {
+        return vec![LinuxBackend::X11];
+    }
+    // XDG_CURRENT_DESKTOP 可能是 "ubuntu:GNOME" 这样以冒号分隔的列表
+    let desktops: Vec<String> = desktop.unwrap_or_default()
+        .split(':')
+        .map(|name| name.trim().to_ascii_lowercase())
+        .collect();
+    let preferred = if desktops.iter().any(|name| name == "gnome") {
+        Some(LinuxBackend::GnomeShell)
+    } else if desktops.iter().any(|name| name == "kde") {
+        Some(LinuxBackend::KWin)
+    } else {
+        None
+    };
+
+    let mut order: Vec<LinuxBackend> = preferred.into_iter().collect();
+    order.extend([LinuxBackend::WlrToplevel, LinuxBackend::X11]);
+    order
+}
+
+pub fn create_monitor() -> (Box<dyn WindowInfo>, MonitorDiagnostics) {
+    let session_type = std::env::var("XDG_SESSION_TYPE").ok();
+    let desktop = std::env::var("XDG_CURRENT_DESKTOP").ok();
+    let mut probes = Vec::new();
+    let mut selected = None;
+
+    for backend in backend_order(session_type.as_deref(), desktop.as_deref()) {
+        match backend.connect() {
+            Ok(monitor) => {
+                probes.push(BackendProbe { backend: backend.as_str().to_string(), error: None });
+                selected = Some(monitor);
+                break;
+            }
+            Err(e) => {
+                tracing::warn!("Window backend {} unavailable: {}", backend.as_str(), e);
+                probes.push(BackendProbe { backend: backend.as_str().to_string(), error: Some(e) });
+            }
+        }
+    }
+
+    // 所有后端都不可用时仍使用 X11，等待之后 X 服务器可用
+    let monitor = selected.unwrap_or_else(|| Box::new(LinuxMonitor::new()));
+    tracing::info!("Using {} window backend", monitor.backend_name());
+    let diagnostics = MonitorDiagnostics {
+        backend: monitor.backend_name().to_string(),
+        session_type,
+        desktop,
+        probes,
+    };
+    (monitor, diagnostics)
+}
+
+// 通过 X11 ScreenSaver 扩展查询用户最后一次输入距今的时间
+// 连接在第一次查询时建立并复用，出错后下次查询重新连接
+pub struct LinuxIdleDetector {
+    conn: Mutex<Option<(RustConnection, Window)>>,
+}
+
+impl LinuxIdleDetector {
+    pub fn new() -> Self {
+        LinuxIdleDetector { conn: Mutex::new(None) }
+    }
+}
+
+impl IdleDetector for LinuxIdleDetector {
+    fn get_idle_seconds(&self) -> Option<u64> {
+        let mut conn = self.conn.lock().unwrap();
+        if conn.is_none() {
+            let (connection, screen_num) = x11rb::connect(None).ok()?;
+            let root = connection.setup().roots[screen_num].root;
+            *conn = Some((connection, root));
+        }
+        let (connection, root) = conn.as_ref()?;
+
+        let info = connection.screensaver_query_info(*root).ok().and_then(|cookie| cookie.reply().ok());
+        if info.is_none() {
+            *conn = None;
+        }
+        Some(info?.ms_since_user_input as u64 / 1000)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinuxAutoStartMode {
+    Xdg,
+    Systemd,
+}
+
+pub struct LinuxAutoStart {
+    mode: LinuxAutoStartMode,
+    config_dir: PathBuf,
+    exec_path: PathBuf,
+    reload_systemd: bool,
+}
+
+// XDG_CONFIG_HOME 未设置或不是绝对路径时按规范回退到 ~/.config
+fn resolve_config_dir(xdg_config_home: Option<String>, home: Option<PathBuf>) -> Option<PathBuf> {
+    match xdg_config_home {
+        Some(dir) if Path::new(&dir).is_absolute() => Some(PathBuf::from(dir)),
+        _ => home.map(|home| home.join(".config")),
+    }
+}
+
+// Desktop Entry 与 systemd 都支持用双引号包裹带空格的路径
+fn quote_exec(path: &Path) -> String {
+    let path = path.to_string_lossy();
+    if !path.contains(|c: char| c.is_whitespace() || "\"'\\$`".contains(c)) {
+        return path.to_string();
+    }
+    let mut quoted = String::from("\"");
+    for c in path.chars() {
+        if "\"\\$`".contains(c) {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+impl LinuxAutoStart {
+    pub fn new(mode: LinuxAutoStartMode, config_dir: PathBuf, exec_path: PathBuf) -> Self {
+        LinuxAutoStart {
+            mode,
+            config_dir,
+            exec_path,
+            reload_systemd: false,
+        }
+    }
+
+    pub fn from_env() -> Result<Self, String> {
+        let config_dir = resolve_config_dir(std::env::var("XDG_CONFIG_HOME").ok(), dirs::home_dir())
+            .ok_or_else(|| "Failed to resolve config directory".to_string())?;
+        let exec_path = std::env::current_exe()
+            .map_err(|e| format!("Failed to get executable path: {}", e))?;
+
+        // 已经安装过 systemd 服务时继续沿用
+        let systemd_installed = config_dir.join("systemd/user").join(SYSTEMD_UNIT_NAME).exists();
+        let mode = match std::env::var(AUTOSTART_MODE_ENV) {
+            Ok(value) if value.eq_ignore_ascii_case("systemd") => LinuxAutoStartMode::Systemd,
+            Ok(value) if value.eq_ignore_ascii_case("xdg") => LinuxAutoStartMode::Xdg,
+            _ if systemd_installed => LinuxAutoStartMode::Systemd,
+            _ => LinuxAutoStartMode::Xdg,
+        };
+
+        Ok(LinuxAutoStart {
+            mode,
+            config_dir,
+            exec_path,
+            reload_systemd: true,
+        })
+    }
+
+    pub fn entry_path(&self) -> PathBuf {
+        match self.mode {
+            LinuxAutoStartMode::Xdg => self.config_dir.join("autostart").join(DESKTOP_ENTRY_NAME),
+            LinuxAutoStartMode::Systemd => self.config_dir.join("systemd/user").join(SYSTEMD_UNIT_NAME),
+        }
+    }
+
+    fn wants_link_path(&self) -> PathBuf {
+        self.config_dir
+            .join("systemd/user")
+            .join(format!("{}.wants", SYSTEMD_TARGET))
+            .join(SYSTEMD_UNIT_NAME)
+    }
+
+    fn entry_content(&self) -> String {
+        let exec = quote_exec(&self.exec_path);
+        match self.mode {
+            LinuxAutoStartMode::Xdg => format!(
+                "[Desktop Entry]\n\
+                 Type=Application\n\
+                 Name=Time Whisper\n\
+                 Comment=Track application usage time\n\
+                 Exec={}\n\
+                 Terminal=false\n\
+                 X-GNOME-Autostart-enabled=true\n",
+                exec
+            ),
+            LinuxAutoStartMode::Systemd => format!(
+                "[Unit]\n\
+                 Description=Time Whisper\n\
+                 PartOf={target}\n\
+                 After={target}\n\
+                 \n\
+                 [Service]\n\
+                 ExecStart={exec}\n\
+                 Restart=on-failure\n\
+                 \n\
+                 [Install]\n\
+                 WantedBy={target}\n",
+                target = SYSTEMD_TARGET,
+                exec = exec
+            ),
+        }
+    }
+
+    // 条目必须指向当前可执行文件，且没有被桌面环境禁用
+    fn is_valid_entry(&self, content: &str) -> bool {
+        let exec = quote_exec(&self.exec_path);
+        let mut has_header = false;
+        let mut exec_matches = false;
+
+        for line in content.lines().map(str::trim) {
+            match self.mode {
+                LinuxAutoStartMode::Xdg => {
+                    if line == "[Desktop Entry]" {
+                        has_header = true;
+                    } else if let Some(value) = line.strip_prefix("Exec=") {
+                        exec_matches = value == exec;
+                    } else if line == "Hidden=true" || line == "X-GNOME-Autostart-enabled=false" {
+                        return false;
+                    }
+                }
+                LinuxAutoStartMode::Systemd => {
+                    if line == "[Service]" {
+                        has_header = true;
+                    } else if let Some(value) = line.strip_prefix("ExecStart=") {
+                        exec_matches = value == exec;
+                    }
                 }
             }
-            None
         }
+        has_header && exec_matches
+    }
+
+    fn systemctl(&self, args: &[&str]) {
+        if !self.reload_systemd {
+            return;
+        }
+        match Command::new("systemctl").arg("--user").args(args).output() {
+            Ok(output) if !output.status.success() => tracing::warn!(
+                "systemctl --user {:?} failed: {}",
+                args,
+                String::from_utf8_lossy(&output.stderr)
+            ),
+            Err(e) => tracing::warn!("Failed to run systemctl: {}", e),
+            _ => {}
+        }
+    }
+}
+
+impl AutoStart for LinuxAutoStart {
+    fn set_auto_start(&self, enable: bool) -> Result<(), String> {
+        let entry_path = self.entry_path();
+
+        if enable {
+            if let Some(parent) = entry_path.parent() {
+                fs::create_dir_all(parent)
+                    .map_err(|e| format!("Failed to create autostart directory: {}", e))?;
+            }
+            fs::write(&entry_path, self.entry_content())
+                .map_err(|e| format!("Failed to write autostart entry: {}", e))?;
+
+            if self.mode == LinuxAutoStartMode::Systemd {
+                // 与 systemctl enable 等价：在 target 的 wants 目录中建立链接
+                let link = self.wants_link_path();
+                if let Some(parent) = link.parent() {
+                    fs::create_dir_all(parent)
+                        .map_err(|e| format!("Failed to create systemd wants directory: {}", e))?;
+                }
+                let _ = fs::remove_file(&link);
+                std::os::unix::fs::symlink(&entry_path, &link)
+                    .map_err(|e| format!("Failed to enable systemd unit: {}", e))?;
+                self.systemctl(&["daemon-reload"]);
+            }
+        } else {
+            if self.mode == LinuxAutoStartMode::Systemd {
+                match fs::remove_file(self.wants_link_path()) {
+                    Ok(_) => {}
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(e) => return Err(format!("Failed to disable systemd unit: {}", e)),
+                }
+            }
+            match fs::remove_file(&entry_path) {
+                Ok(_) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(format!("Failed to remove autostart entry: {}", e)),
+            }
+            if self.mode == LinuxAutoStartMode::Systemd {
+                self.systemctl(&["daemon-reload"]);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn is_auto_start_enabled(&self) -> Result<bool, String> {
+        let content = match fs::read_to_string(self.entry_path()) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(format!("Failed to read autostart entry: {}", e)),
+        };
+
+        if self.mode == LinuxAutoStartMode::Systemd && !self.wants_link_path().exists() {
+            return Ok(false);
+        }
+        Ok(self.is_valid_entry(&content))
+    }
+}
+
+impl AutoStart for Linux {
+    fn set_auto_start(&self, enable: bool) -> Result<(), String> {
+        LinuxAutoStart::from_env()?.set_auto_start(enable)
+    }
+
+    fn is_auto_start_enabled(&self) -> Result<bool, String> {
+        LinuxAutoStart::from_env()?.is_auto_start_enabled()
     }
 }
 
@@ -77,10 +662,11 @@ mod tests {
         let result = monitor.get_active_window();
         
         match result {
-            Some(window_name) => {
+            Some(window) => {
                 // Linux 下进程名通常不包含路径
-                assert!(!window_name.is_empty());
-                assert!(!window_name.contains('/'));
+                assert!(!window.process_name.is_empty());
+                assert!(!window.process_name.contains('/'));
+                assert!(window.pid.is_some());
             }
             None => {} // 可能没有活动窗口，这是合法的
         }
@@ -94,9 +680,9 @@ mod tests {
         for _ in 0..5 {
             let result = monitor.get_active_window();
             match result {
-                Some(name) => {
-                    assert!(!name.is_empty());
-                    assert!(!name.contains('\0')); // 不应包含空字符
+                Some(window) => {
+                    assert!(!window.process_name.is_empty());
+                    assert!(!window.process_name.contains('\0')); // 不应包含空字符
                 }
                 None => continue,
             }
@@ -197,11 +783,30 @@ mod tests {
         let result = monitor.get_active_window();
         // 没有 DISPLAY 环境变量时应该返回 None
         assert!(result.is_none());
+
+        // 4. 没有 DISPLAY 时空闲检测同样返回 None
+        let detector = LinuxIdleDetector::new();
+        assert!(detector.get_idle_seconds().is_none());
+    }
+
+    // 空闲检测测试
+    #[test]
+    fn test_idle_detector() {
+        let detector = LinuxIdleDetector::new();
+
+        // 连续两次查询，第二次的空闲时间不会明显大于第一次
+        if let Some(first) = detector.get_idle_seconds() {
+            assert!(first < 60 * 60 * 24 * 365);
+            if let Some(second) = detector.get_idle_seconds() {
+                assert!(second <= first + 1);
+            }
+        }
     }
 
     // 6. 进程名解析测试扩展
     #[test]
     fn test_process_name_parsing() {
+        let long_name = "a".repeat(1000);
         let test_cases = vec![
             // 基本测试
             ("simple", "simple"),
@@ -219,7 +824,7 @@ mod tests {
             ("process\u{200B}name", "processname"),
             
             // 长度边界测试
-            ("a".repeat(1000).as_str(), "a".repeat(1000).as_str()),
+            (long_name.as_str(), long_name.as_str()),
             ("", ""),
             
             // Unicode 字符测试
@@ -236,6 +841,183 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_process_name_from_proc() {
+        let pid = std::process::id();
+        let name = get_process_name(pid).unwrap();
+        assert!(!name.is_empty() && name.len() <= 15);
+        assert!(get_process_name(999_999_999).is_none());
+
+        let window = window_for_pid(pid, Some("title".to_string()), None).unwrap();
+        assert_eq!(window.process_name, name);
+        assert!(window.exe_path.is_some());
+    }
+
+    // 焦点变化通过订阅推送，没有 X 服务器时保持为空
+    #[test]
+    fn test_focus_events() {
+        let monitor = LinuxMonitor::new();
+        let events = monitor.focus_events().unwrap();
+        assert_eq!(*events.borrow(), monitor.get_active_window());
+        if std::env::var_os("DISPLAY").is_none() {
+            assert!(LinuxMonitor::connect().is_err());
+        }
+    }
+
+    #[test]
+    fn test_backend_order() {
+        use LinuxBackend::*;
+        assert_eq!(backend_order(None, None), vec![X11]);
+        assert_eq!(backend_order(Some("x11"), Some("GNOME")), vec![X11]);
+        assert_eq!(backend_order(Some("wayland"), Some("ubuntu:GNOME")), vec![GnomeShell, WlrToplevel, X11]);
+        assert_eq!(backend_order(Some("wayland"), Some("KDE")), vec![KWin, WlrToplevel, X11]);
+        assert_eq!(backend_order(Some("Wayland"), Some("sway")), vec![WlrToplevel, X11]);
+        assert_eq!(backend_order(Some("wayland"), None), vec![WlrToplevel, X11]);
+    }
+
+    #[test]
+    fn test_wm_class_parsing() {
+        assert_eq!(parse_wm_class(b"navigator\0Firefox\0"), Some("Firefox".to_string()));
+        assert_eq!(parse_wm_class(b"code\0Code"), Some("Code".to_string()));
+        assert_eq!(parse_wm_class(b"xterm\0"), Some("xterm".to_string()));
+        assert_eq!(parse_wm_class(b""), None);
+    }
+
+    // 自启动测试，全部在临时配置目录中进行
+    fn temp_config_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir()
+            .join(format!("time-whisper-autostart-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_resolve_config_dir() {
+        let home = Some(PathBuf::from("/home/user"));
+        assert_eq!(
+            resolve_config_dir(Some("/tmp/xdg".to_string()), home.clone()),
+            Some(PathBuf::from("/tmp/xdg"))
+        );
+        // 相对路径按规范忽略
+        assert_eq!(
+            resolve_config_dir(Some("relative".to_string()), home.clone()),
+            Some(PathBuf::from("/home/user/.config"))
+        );
+        assert_eq!(resolve_config_dir(None, home), Some(PathBuf::from("/home/user/.config")));
+        assert_eq!(resolve_config_dir(None, None), None);
+    }
+
+    #[test]
+    fn test_quote_exec() {
+        assert_eq!(quote_exec(Path::new("/usr/bin/time-whisper")), "/usr/bin/time-whisper");
+        assert_eq!(
+            quote_exec(Path::new("/opt/Time Whisper/time-whisper")),
+            "\"/opt/Time Whisper/time-whisper\""
+        );
+        assert_eq!(quote_exec(Path::new("/opt/$app/bin")), "\"/opt/\\$app/bin\"");
+    }
+
+    #[test]
+    fn test_xdg_auto_start() {
+        let dir = temp_config_dir("xdg");
+        let autostart = LinuxAutoStart::new(
+            LinuxAutoStartMode::Xdg,
+            dir.clone(),
+            PathBuf::from("/opt/Time Whisper/time-whisper"),
+        );
+
+        assert!(!autostart.is_auto_start_enabled().unwrap());
+
+        autostart.set_auto_start(true).unwrap();
+        let entry = dir.join("autostart/time-whisper.desktop");
+        assert_eq!(autostart.entry_path(), entry);
+        let content = fs::read_to_string(&entry).unwrap();
+        assert!(content.starts_with("[Desktop Entry]"));
+        assert!(content.contains("Type=Application"));
+        assert!(content.contains("Exec=\"/opt/Time Whisper/time-whisper\""));
+        assert!(autostart.is_auto_start_enabled().unwrap());
+
+        // 重复启用是幂等的
+        autostart.set_auto_start(true).unwrap();
+        assert!(autostart.is_auto_start_enabled().unwrap());
+
+        autostart.set_auto_start(false).unwrap();
+        assert!(!entry.exists());
+        assert!(!autostart.is_auto_start_enabled().unwrap());
+
+        // 关闭不存在的条目不报错
+        autostart.set_auto_start(false).unwrap();
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_xdg_entry_validation() {
+        let dir = temp_config_dir("validation");
+        let autostart = LinuxAutoStart::new(
+            LinuxAutoStartMode::Xdg,
+            dir.clone(),
+            PathBuf::from("/usr/bin/time-whisper"),
+        );
+        let entry = dir.join("autostart/time-whisper.desktop");
+        fs::create_dir_all(entry.parent().unwrap()).unwrap();
+
+        // 指向旧路径的条目视为未启用
+        fs::write(&entry, "[Desktop Entry]\nType=Application\nExec=/old/path/time-whisper\n").unwrap();
+        assert!(!autostart.is_auto_start_enabled().unwrap());
+
+        // 被桌面环境禁用的条目
+        fs::write(&entry, "[Desktop Entry]\nType=Application\nExec=/usr/bin/time-whisper\nHidden=true\n").unwrap();
+        assert!(!autostart.is_auto_start_enabled().unwrap());
+
+        fs::write(&entry, "[Desktop Entry]\nType=Application\nExec=/usr/bin/time-whisper\nX-GNOME-Autostart-enabled=false\n").unwrap();
+        assert!(!autostart.is_auto_start_enabled().unwrap());
+
+        // 缺少 [Desktop Entry] 头
+        fs::write(&entry, "Exec=/usr/bin/time-whisper\n").unwrap();
+        assert!(!autostart.is_auto_start_enabled().unwrap());
+
+        // 重新启用会修复条目
+        autostart.set_auto_start(true).unwrap();
+        assert!(autostart.is_auto_start_enabled().unwrap());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_systemd_auto_start() {
+        let dir = temp_config_dir("systemd");
+        let autostart = LinuxAutoStart::new(
+            LinuxAutoStartMode::Systemd,
+            dir.clone(),
+            PathBuf::from("/usr/bin/time-whisper"),
+        );
+
+        assert!(!autostart.is_auto_start_enabled().unwrap());
+
+        autostart.set_auto_start(true).unwrap();
+        let unit = dir.join("systemd/user/time-whisper.service");
+        let link = dir.join("systemd/user/graphical-session.target.wants/time-whisper.service");
+        let content = fs::read_to_string(&unit).unwrap();
+        assert!(content.contains("ExecStart=/usr/bin/time-whisper"));
+        assert!(content.contains("WantedBy=graphical-session.target"));
+        assert_eq!(fs::read_link(&link).unwrap(), unit);
+        assert!(autostart.is_auto_start_enabled().unwrap());
+
+        // 只删掉链接相当于 systemctl disable
+        fs::remove_file(&link).unwrap();
+        assert!(!autostart.is_auto_start_enabled().unwrap());
+
+        autostart.set_auto_start(true).unwrap();
+        autostart.set_auto_start(false).unwrap();
+        assert!(!unit.exists());
+        assert!(fs::symlink_metadata(&link).is_err());
+        assert!(!autostart.is_auto_start_enabled().unwrap());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
     // 7. 边界条件测试扩展
     #[test]
     fn test_edge_cases() {
@@ -325,7 +1107,7 @@ mod tests {
         let mut window_stats = std::collections::HashMap::new();
         for _ in 0..10 {
             if let Some(window) = monitor.get_active_window() {
-                *window_stats.entry(window).or_insert(0) += 1;
+                *window_stats.entry(window.process_name).or_insert(0) += 1;
             }
             thread::sleep(Duration::from_millis(100));
         }
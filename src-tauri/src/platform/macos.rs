@@ -1,6 +1,6 @@
 #[cfg(target_os = "macos")]
 mod macos {
-    use super::WindowInfo;
+    use super::{ActiveWindow, WindowInfo};
     use core_foundation::string::CFString;
     use core_foundation::array::CFArray;
     use core_foundation::dictionary::CFDictionary;
@@ -18,18 +18,26 @@ mod macos {
     }
 
     impl WindowInfo for MacOSMonitor {
-        fn get_active_window(&self) -> Option<String> {
+        fn get_active_window(&self) -> Option<ActiveWindow> {
             let options = CGWindowListOption::OPTION_ON_SCREEN | 
                          CGWindowListOption::OPTION_RELATIVE_TO_FRONT;
             let window_list = CGWindow::window_list_info(options, None)?;
             
             if let Some(window_info) = window_list.get(0) {
                 if let Some(app_name) = window_info.get("kCGWindowOwnerName") {
-                    return Some(app_name.to_string());
+                    return Some(ActiveWindow {
+                        process_name: app_name.to_string(),
+                        window_title: window_info.get("kCGWindowName").map(|title| title.to_string()),
+                        ..Default::default()
+                    });
                 }
             }
             None
         }
+
+        fn backend_name(&self) -> &'static str {
+            "core-graphics"
+        }
     }
 }
 
@@ -103,11 +111,11 @@ mod tests {
         let monitor = MacOSMonitor::new();
         let result = monitor.get_active_window();
         match result {
-            Some(window_name) => {
-                assert!(!window_name.is_empty());
-                assert!(!window_name.ends_with(".app"));
-                assert!(!window_name.contains('\0'));
-                assert!(!window_name.contains('/'));
+            Some(window) => {
+                assert!(!window.process_name.is_empty());
+                assert!(!window.process_name.ends_with(".app"));
+                assert!(!window.process_name.contains('\0'));
+                assert!(!window.process_name.contains('/'));
             }
             None => {} // 可能没有活动窗口
         }
@@ -212,8 +220,8 @@ mod tests {
         let mut thread_counts = HashMap::new();
         
         for (thread_id, time, window) in all_results {
-            if let Some(name) = window {
-                *window_counts.entry(name).or_insert(0) += 1;
+            if let Some(window) = window {
+                *window_counts.entry(window.process_name).or_insert(0) += 1;
             }
             *thread_counts.entry(thread_id).or_insert(0) += 1;
         }
@@ -4,27 +4,106 @@ pub mod windows;
 pub mod macos;
 #[cfg(target_os = "linux")]
 pub mod linux;
+#[cfg(target_os = "linux")]
+pub mod wayland;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ActiveWindow {
+    pub process_name: String,
+    pub pid: Option<u32>,
+    pub exe_path: Option<String>,
+    pub window_title: Option<String>,
+    pub window_class: Option<String>,
+}
 
 pub trait WindowInfo : Send + Sync{
-    fn get_active_window(&self) -> Option<String>;
+    fn get_active_window(&self) -> Option<ActiveWindow>;
+    // 诊断信息中显示的后端名称
+    fn backend_name(&self) -> &'static str;
+    // 能主动推送焦点变化的后端返回订阅，监控循环据此提前醒来而不必等到下一次轮询
+    fn focus_events(&self) -> Option<tokio::sync::watch::Receiver<Option<ActiveWindow>>> {
+        None
+    }
+}
+
+// 选择窗口监控后端时依次尝试的结果，error 为空表示可用
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackendProbe {
+    pub backend: String,
+    pub error: Option<String>,
 }
 
-pub fn create_window_monitor() -> Box<dyn WindowInfo> {
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MonitorDiagnostics {
+    pub backend: String,
+    pub session_type: Option<String>,
+    pub desktop: Option<String>,
+    pub probes: Vec<BackendProbe>,
+}
+
+impl MonitorDiagnostics {
+    #[cfg(not(target_os = "linux"))]
+    fn single(monitor: &dyn WindowInfo) -> Self {
+        MonitorDiagnostics {
+            backend: monitor.backend_name().to_string(),
+            session_type: None,
+            desktop: None,
+            probes: vec![BackendProbe { backend: monitor.backend_name().to_string(), error: None }],
+        }
+    }
+}
+
+pub fn create_window_monitor() -> (Box<dyn WindowInfo>, MonitorDiagnostics) {
     #[cfg(target_os = "windows")]
     {
-        Box::new(windows::WindowsMonitor::new())
+        let monitor = windows::WindowsMonitor::new();
+        let diagnostics = MonitorDiagnostics::single(&monitor);
+        (Box::new(monitor), diagnostics)
     }
     #[cfg(target_os = "macos")]
     {
-        Box::new(macos::MacOSMonitor::new())
+        let monitor = macos::MacOSMonitor::new();
+        let diagnostics = MonitorDiagnostics::single(&monitor);
+        (Box::new(monitor), diagnostics)
     }
     #[cfg(target_os = "linux")]
     {
-        Box::new(linux::LinuxMonitor::new())
+        linux::create_monitor()
+    }
+}
+
+pub trait IdleDetector : Send + Sync {
+    // 距离用户最后一次键盘/鼠标输入的秒数，无法获取时返回 None
+    fn get_idle_seconds(&self) -> Option<u64>;
+}
+
+// 不支持空闲检测的平台使用，永远视为非空闲
+pub struct NoIdleDetector;
+
+impl IdleDetector for NoIdleDetector {
+    fn get_idle_seconds(&self) -> Option<u64> {
+        None
+    }
+}
+
+pub fn create_idle_detector() -> Box<dyn IdleDetector> {
+    #[cfg(target_os = "windows")]
+    {
+        Box::new(windows::WindowsIdleDetector::new())
+    }
+    #[cfg(target_os = "linux")]
+    {
+        Box::new(linux::LinuxIdleDetector::new())
+    }
+    #[cfg(not(any(target_os = "windows", target_os = "linux")))]
+    {
+        Box::new(NoIdleDetector)
     }
 }
 
 pub trait AutoStart {
     fn set_auto_start(&self, enable: bool) -> Result<(), String>;
     fn is_auto_start_enabled(&self) -> Result<bool, String>;
-}
\ No newline at end of file
+}
@@ -0,0 +1,335 @@
+use super::linux::window_for_pid;
+use super::{ActiveWindow, WindowInfo};
+use std::collections::HashMap;
+use std::fs;
+use std::sync::{Arc, Mutex};
+use wayland_client::backend::ObjectId;
+use wayland_client::globals::{registry_queue_init, GlobalListContents};
+use wayland_client::protocol::wl_registry;
+use wayland_client::{event_created_child, Connection, Dispatch, Proxy, QueueHandle};
+use wayland_protocols_wlr::foreign_toplevel::v1::client::zwlr_foreign_toplevel_handle_v1::{self, ZwlrForeignToplevelHandleV1};
+use wayland_protocols_wlr::foreign_toplevel::v1::client::zwlr_foreign_toplevel_manager_v1::{self, ZwlrForeignToplevelManagerV1};
+
+// KWin 脚本和 GNOME Shell 扩展通过这个接口把前台窗口交给我们
+const BRIDGE_INTERFACE: &str = "com.timewhisper.ActiveWindow";
+const BRIDGE_PATH: &str = "/com/timewhisper/ActiveWindow";
+const KWIN_BUS_NAME: &str = "com.timewhisper.ActiveWindow";
+const KWIN_SCRIPT_NAME: &str = "time-whisper-active-window";
+const GNOME_EXTENSION_UUID: &str = "time-whisper@time-whisper.dev";
+
+// KWin 6 使用 windowActivated/activeWindow，KWin 5 使用 clientActivated/activeClient
+const KWIN_SCRIPT: &str = r#"
+function report(window) {
+    if (!window) {
+        return;
+    }
+    callDBus("com.timewhisper.ActiveWindow", "/com/timewhisper/ActiveWindow",
+             "com.timewhisper.ActiveWindow", "Update",
+             window.pid, String(window.resourceClass), String(window.caption));
+}
+var activated = workspace.windowActivated || workspace.clientActivated;
+activated.connect(report);
+report(workspace.activeWindow || workspace.activeClient);
+"#;
+
+type SharedWindow = Arc<Mutex<Option<ActiveWindow>>>;
+
+// 只有窗口类没有 pid 时用窗口类代替进程名
+fn bridge_window(pid: u32, window_class: String, title: String) -> Option<ActiveWindow> {
+    let window_class = Some(window_class).filter(|class| !class.is_empty());
+    let window_title = Some(title).filter(|title| !title.is_empty());
+    if pid > 0 {
+        if let Some(window) = window_for_pid(pid, window_title.clone(), window_class.clone()) {
+            return Some(window);
+        }
+    }
+    Some(ActiveWindow {
+        process_name: window_class.clone()?,
+        window_title,
+        window_class,
+        ..Default::default()
+    })
+}
+
+// wlroots 系合成器（sway、Hyprland、river 等）实现的 wlr-foreign-toplevel-management
+pub struct WlrToplevelMonitor {
+    active: SharedWindow,
+}
+
+#[derive(Default)]
+struct Toplevel {
+    title: Option<String>,
+    app_id: Option<String>,
+    activated: bool,
+}
+
+struct ToplevelState {
+    toplevels: HashMap<ObjectId, Toplevel>,
+    active: SharedWindow,
+}
+
+impl ToplevelState {
+    // 协议不提供 pid，用 app_id 作为进程名和窗口类
+    fn publish(&self) {
+        let window = self.toplevels.values()
+            .find(|toplevel| toplevel.activated)
+            .and_then(|toplevel| {
+                Some(ActiveWindow {
+                    process_name: toplevel.app_id.clone().filter(|id| !id.is_empty())?,
+                    window_title: toplevel.title.clone(),
+                    window_class: toplevel.app_id.clone(),
+                    ..Default::default()
+                })
+            });
+        *self.active.lock().unwrap() = window;
+    }
+}
+
+impl Dispatch<wl_registry::WlRegistry, GlobalListContents> for ToplevelState {
+    fn event(
+        _: &mut Self,
+        _: &wl_registry::WlRegistry,
+        _: wl_registry::Event,
+        _: &GlobalListContents,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<ZwlrForeignToplevelManagerV1, ()> for ToplevelState {
+    fn event(
+        state: &mut Self,
+        _: &ZwlrForeignToplevelManagerV1,
+        event: zwlr_foreign_toplevel_manager_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let zwlr_foreign_toplevel_manager_v1::Event::Finished = event {
+            tracing::warn!("Compositor stopped sending toplevel events");
+            state.toplevels.clear();
+            state.publish();
+        }
+    }
+
+    event_created_child!(ToplevelState, ZwlrForeignToplevelManagerV1, [
+        zwlr_foreign_toplevel_manager_v1::EVT_TOPLEVEL_OPCODE => (ZwlrForeignToplevelHandleV1, ()),
+    ]);
+}
+
+impl Dispatch<ZwlrForeignToplevelHandleV1, ()> for ToplevelState {
+    fn event(
+        state: &mut Self,
+        handle: &ZwlrForeignToplevelHandleV1,
+        event: zwlr_foreign_toplevel_handle_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        use zwlr_foreign_toplevel_handle_v1::Event;
+        match event {
+            Event::Title { title } => state.toplevels.entry(handle.id()).or_default().title = Some(title),
+            Event::AppId { app_id } => state.toplevels.entry(handle.id()).or_default().app_id = Some(app_id),
+            Event::State { state: values } => {
+                // 状态是 u32 数组
+                let activated = zwlr_foreign_toplevel_handle_v1::State::Activated as u32;
+                state.toplevels.entry(handle.id()).or_default().activated = values
+                    .chunks_exact(4)
+                    .any(|value| u32::from_ne_bytes([value[0], value[1], value[2], value[3]]) == activated);
+            }
+            // 属性在 done 之后才算一次完整的更新
+            Event::Done => state.publish(),
+            Event::Closed => {
+                state.toplevels.remove(&handle.id());
+                handle.destroy();
+                state.publish();
+            }
+            _ => {}
+        }
+    }
+}
+
+impl WlrToplevelMonitor {
+    pub fn connect() -> Result<Self, String> {
+        let conn = Connection::connect_to_env().map_err(|e| e.to_string())?;
+        let (globals, mut queue) = registry_queue_init::<ToplevelState>(&conn).map_err(|e| e.to_string())?;
+        let qh = queue.handle();
+        let _manager: ZwlrForeignToplevelManagerV1 = globals.bind(&qh, 1..=3, ())
+            .map_err(|_| "compositor does not support wlr-foreign-toplevel-management".to_string())?;
+
+        let active: SharedWindow = Arc::new(Mutex::new(None));
+        let mut state = ToplevelState {
+            toplevels: HashMap::new(),
+            active: active.clone(),
+        };
+        // 先取到已有窗口再返回，避免第一次查询为空
+        queue.roundtrip(&mut state).map_err(|e| e.to_string())?;
+
+        std::thread::spawn(move || loop {
+            if let Err(e) = queue.blocking_dispatch(&mut state) {
+                tracing::error!("Wayland connection lost: {}", e);
+                *state.active.lock().unwrap() = None;
+                break;
+            }
+        });
+        Ok(WlrToplevelMonitor { active })
+    }
+}
+
+impl WindowInfo for WlrToplevelMonitor {
+    fn get_active_window(&self) -> Option<ActiveWindow> {
+        self.active.lock().unwrap().clone()
+    }
+
+    fn backend_name(&self) -> &'static str {
+        "wlr-foreign-toplevel"
+    }
+}
+
+// KDE Plasma：加载一个 KWin 脚本，窗口切换时由脚本通过 D-Bus 调用 Update
+pub struct KWinMonitor {
+    active: SharedWindow,
+    // 保持连接，断开后脚本的调用没有接收方
+    _connection: zbus::blocking::Connection,
+}
+
+struct KWinBridge {
+    active: SharedWindow,
+}
+
+#[zbus::interface(name = "com.timewhisper.ActiveWindow")]
+impl KWinBridge {
+    fn update(&self, pid: i32, window_class: String, title: String) {
+        *self.active.lock().unwrap() = bridge_window(pid.max(0) as u32, window_class, title);
+    }
+}
+
+impl KWinMonitor {
+    pub fn connect() -> Result<Self, String> {
+        let active: SharedWindow = Arc::new(Mutex::new(None));
+        let connection = zbus::blocking::connection::Builder::session()
+            .and_then(|builder| builder.name(KWIN_BUS_NAME))
+            .and_then(|builder| builder.serve_at(BRIDGE_PATH, KWinBridge { active: active.clone() }))
+            .and_then(|builder| builder.build())
+            .map_err(|e| format!("Failed to register D-Bus bridge: {}", e))?;
+
+        let script_dir = dirs::cache_dir()
+            .ok_or("Cannot determine the cache directory")?
+            .join("time-whisper");
+        fs::create_dir_all(&script_dir).map_err(|e| e.to_string())?;
+        let script_path = script_dir.join("kwin-active-window.js");
+        fs::write(&script_path, KWIN_SCRIPT).map_err(|e| e.to_string())?;
+
+        let scripting = zbus::blocking::Proxy::new(&connection, "org.kde.KWin", "/Scripting", "org.kde.kwin.Scripting")
+            .map_err(|e| e.to_string())?;
+        // 上次运行留下的脚本先卸载，否则会加载失败
+        let _: Result<bool, _> = scripting.call("unloadScript", &(KWIN_SCRIPT_NAME,));
+        let id: i32 = scripting.call("loadScript", &(script_path.to_string_lossy().as_ref(), KWIN_SCRIPT_NAME))
+            .map_err(|e| format!("KWin scripting is not available: {}", e))?;
+        if id < 0 {
+            return Err("KWin refused to load the script".to_string());
+        }
+
+        // KWin 6 的脚本对象在 /Scripting/Script<id>，KWin 5 在 /<id>
+        let started = [format!("/Scripting/Script{}", id), format!("/{}", id)].iter().any(|path| {
+            zbus::blocking::Proxy::new(&connection, "org.kde.KWin", path.as_str(), "org.kde.kwin.Script")
+                .and_then(|script| script.call::<_, _, ()>("run", &()))
+                .is_ok()
+        });
+        if !started {
+            return Err("Failed to start the KWin script".to_string());
+        }
+        Ok(KWinMonitor { active, _connection: connection })
+    }
+}
+
+impl WindowInfo for KWinMonitor {
+    fn get_active_window(&self) -> Option<ActiveWindow> {
+        self.active.lock().unwrap().clone()
+    }
+
+    fn backend_name(&self) -> &'static str {
+        "kwin-script"
+    }
+}
+
+// GNOME Shell：查询随应用提供的扩展（extensions/gnome-shell）导出的 D-Bus 接口
+pub struct GnomeShellMonitor {
+    proxy: zbus::blocking::Proxy<'static>,
+}
+
+impl GnomeShellMonitor {
+    pub fn connect() -> Result<Self, String> {
+        let connection = zbus::blocking::Connection::session().map_err(|e| e.to_string())?;
+        let proxy = zbus::blocking::Proxy::new_owned(connection, "org.gnome.Shell", BRIDGE_PATH, BRIDGE_INTERFACE)
+            .map_err(|e| e.to_string())?;
+        let monitor = GnomeShellMonitor { proxy };
+        monitor.query()
+            .map_err(|e| format!("GNOME Shell extension {} is not enabled: {}", GNOME_EXTENSION_UUID, e))?;
+        Ok(monitor)
+    }
+
+    fn query(&self) -> zbus::Result<(u32, String, String)> {
+        self.proxy.call("Get", &())
+    }
+}
+
+impl WindowInfo for GnomeShellMonitor {
+    fn get_active_window(&self) -> Option<ActiveWindow> {
+        let (pid, window_class, title) = self.query().ok()?;
+        bridge_window(pid, window_class, title)
+    }
+
+    fn backend_name(&self) -> &'static str {
+        "gnome-shell-extension"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 1. 有 pid 时使用进程信息，否则用窗口类代替进程名
+    #[test]
+    fn test_bridge_window() {
+        let window = bridge_window(std::process::id(), "org.kde.konsole".to_string(), "~ : bash".to_string()).unwrap();
+        assert_eq!(window.pid, Some(std::process::id()));
+        assert_eq!(window.window_class.as_deref(), Some("org.kde.konsole"));
+        assert!(!window.process_name.is_empty());
+
+        let window = bridge_window(0, "firefox".to_string(), String::new()).unwrap();
+        assert_eq!(window.process_name, "firefox");
+        assert_eq!(window.window_title, None);
+        assert_eq!(window.pid, None);
+
+        assert!(bridge_window(0, String::new(), "title".to_string()).is_none());
+    }
+
+    // 2. 只有激活的窗口会被发布
+    #[test]
+    fn test_toplevel_publish() {
+        let active: SharedWindow = Arc::new(Mutex::new(None));
+        let mut state = ToplevelState { toplevels: HashMap::new(), active: active.clone() };
+        state.toplevels.insert(ObjectId::null(), Toplevel {
+            title: Some("README.md - Code".to_string()),
+            app_id: Some("code".to_string()),
+            activated: true,
+        });
+        state.publish();
+        let window = active.lock().unwrap().clone().unwrap();
+        assert_eq!((window.process_name.as_str(), window.window_title.as_deref()), ("code", Some("README.md - Code")));
+
+        state.toplevels.values_mut().for_each(|toplevel| toplevel.activated = false);
+        state.publish();
+        assert!(active.lock().unwrap().is_none());
+    }
+
+    // 3. 不在 Wayland 会话中时连接失败而不是阻塞
+    #[test]
+    fn test_connect_without_session() {
+        if std::env::var_os("WAYLAND_DISPLAY").is_none() {
+            assert!(WlrToplevelMonitor::connect().is_err());
+        }
+    }
+}
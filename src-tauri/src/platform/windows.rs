@@ -1,8 +1,12 @@
-use super::{AutoStart, WindowInfo};
-use windows::Win32::UI::WindowsAndMessaging::{GetForegroundWindow, GetWindowThreadProcessId};
-use windows::Win32::System::Threading::{OpenProcess, PROCESS_QUERY_INFORMATION, PROCESS_VM_READ};
-use windows::Win32::System::ProcessStatus::GetProcessImageFileNameA;
-use windows::Win32::Foundation::BOOL;
+use super::{ActiveWindow, AutoStart, IdleDetector, WindowInfo};
+use windows::Win32::UI::WindowsAndMessaging::{GetClassNameW, GetForegroundWindow, GetWindowTextW, GetWindowThreadProcessId};
+use windows::Win32::UI::Input::KeyboardAndMouse::{GetLastInputInfo, LASTINPUTINFO};
+use windows::Win32::System::SystemInformation::GetTickCount;
+use windows::Win32::System::Threading::{
+    OpenProcess, QueryFullProcessImageNameW, PROCESS_NAME_WIN32, PROCESS_QUERY_LIMITED_INFORMATION,
+};
+use windows::Win32::Foundation::{CloseHandle, BOOL, HANDLE};
+use windows::core::PWSTR;
 use std::path::PathBuf;
 use winreg::enums::*;
 use winreg::RegKey;
@@ -16,30 +20,78 @@ impl WindowsMonitor {
     }
 }
 
+fn utf16_to_string(buffer: &[u16], len: i32) -> Option<String> {
+    if len <= 0 {
+        return None;
+    }
+    Some(String::from_utf16_lossy(&buffer[..len as usize]))
+}
+
+// C:\... 形式的完整路径，GetProcessImageFileName 返回的是 \Device\HarddiskVolumeN\... 形式
+unsafe fn process_image_path(process: HANDLE) -> Option<String> {
+    let mut buffer = [0u16; 1024];
+    let mut size = buffer.len() as u32;
+    if !QueryFullProcessImageNameW(process, PROCESS_NAME_WIN32, PWSTR(buffer.as_mut_ptr()), &mut size).as_bool() {
+        return None;
+    }
+    utf16_to_string(&buffer, size as i32)
+}
+
 impl WindowInfo for WindowsMonitor {
-    fn get_active_window(&self) -> Option<String> {
+    fn get_active_window(&self) -> Option<ActiveWindow> {
         unsafe {
             let hwnd = GetForegroundWindow();
             let mut process_id: u32 = 0;
             GetWindowThreadProcessId(hwnd, Some(&mut process_id));
             
-            let process_handle = OpenProcess(
-                PROCESS_QUERY_INFORMATION | PROCESS_VM_READ,
-                BOOL(0),
-                process_id
-            ).ok()?;
-
-            if !process_handle.is_invalid() {
-                let mut buffer = [0u8; 260];
-                let result = GetProcessImageFileNameA(process_handle, &mut buffer);
-                if result != 0 {
-                    let path = String::from_utf8_lossy(&buffer)
-                        .trim_matches(char::from(0))
-                        .to_string();
-                    return path.split('\\').last().map(String::from);
-                }
+            // 只需要查询进程路径，受限权限也能打开以管理员身份运行的进程
+            let process_handle = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, BOOL(0), process_id).ok()?;
+            let path = process_image_path(process_handle);
+            let _ = CloseHandle(process_handle);
+            let path = path?;
+            let process_name = path.split('\\').last().map(String::from)?;
+
+            let mut title = [0u16; 512];
+            let title_len = GetWindowTextW(hwnd, &mut title);
+            let mut class = [0u16; 256];
+            let class_len = GetClassNameW(hwnd, &mut class);
+
+            Some(ActiveWindow {
+                process_name,
+                pid: Some(process_id),
+                exe_path: Some(path),
+                window_title: utf16_to_string(&title, title_len),
+                window_class: utf16_to_string(&class, class_len),
+            })
+        }
+    }
+
+    fn backend_name(&self) -> &'static str {
+        "win32"
+    }
+}
+
+pub struct WindowsIdleDetector;
+
+impl WindowsIdleDetector {
+    pub fn new() -> Self {
+        WindowsIdleDetector
+    }
+}
+
+impl IdleDetector for WindowsIdleDetector {
+    fn get_idle_seconds(&self) -> Option<u64> {
+        unsafe {
+            let mut info = LASTINPUTINFO {
+                cbSize: std::mem::size_of::<LASTINPUTINFO>() as u32,
+                dwTime: 0,
+            };
+            if !GetLastInputInfo(&mut info).as_bool() {
+                return None;
             }
-            None
+            // GetTickCount 约 49 天回绕一次，用 wrapping_sub 处理
+            let idle_ms = GetTickCount().wrapping_sub(info.dwTime);
+            Some(idle_ms as u64 / 1000)
         }
     }
 }
@@ -101,9 +153,10 @@ mod tests {
         // 这里我们只能测试返回的类型是否正确，因为实际窗口依赖于运行环境
         let result = monitor.get_active_window();
         match result {
-            Some(window_name) => {
-                assert!(!window_name.is_empty());
-                assert!(window_name.ends_with(".exe"));
+            Some(window) => {
+                assert!(!window.process_name.is_empty());
+                assert!(window.process_name.ends_with(".exe"));
+                assert!(window.pid.is_some());
             }
             None => {} // 也可能没有活动窗口，这是合法的
         }
@@ -118,6 +171,13 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_idle_detector() {
+        let detector = WindowsIdleDetector::new();
+        let idle = detector.get_idle_seconds();
+        assert!(idle.is_some());
+    }
+
     // 2. 测试路径处理
     #[test]
     fn test_path_processing() {
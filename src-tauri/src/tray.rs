@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use chrono::{Duration, Local, Utc};
+use tauri::menu::{Menu, MenuEvent, MenuItem, PredefinedMenuItem};
+use tauri::tray::{MouseButton, MouseButtonState, TrayIcon, TrayIconBuilder, TrayIconEvent};
+use tauri::{AppHandle, Manager, Wry};
+use crate::db::types::{AppUsage, TrackingState};
+use crate::AppState;
+
+const MENU_PAUSE: &str = "pause";
+const MENU_SNOOZE_30: &str = "snooze_30";
+const MENU_SNOOZE_60: &str = "snooze_60";
+const MENU_OPEN: &str = "open";
+const MENU_QUIT: &str = "quit";
+
+// 托盘图标和需要随统计更新的菜单项
+pub struct Tray {
+    icon: TrayIcon<Wry>,
+    current: MenuItem<Wry>,
+    today: MenuItem<Wry>,
+    pause: MenuItem<Wry>,
+    // 上一次显示的提示文字，没有变化时不再更新
+    tooltip: Mutex<String>,
+}
+
+// 托盘中显示的当前应用和今天的活跃总时长
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraySummary {
+    pub current: Option<String>,
+    pub today_total: u64,
+}
+
+impl TraySummary {
+    pub fn from_usage(usage: &HashMap<String, AppUsage>) -> Self {
+        TraySummary {
+            current: usage.values()
+                .filter(|usage| usage.last_active > 0)
+                .max_by_key(|usage| usage.last_active)
+                .map(|usage| usage.name.clone()),
+            today_total: usage.values().map(|usage| usage.total_time).sum(),
+        }
+    }
+}
+
+// 1h 05m / 12m
+pub fn format_duration(secs: u64) -> String {
+    let (hours, minutes) = (secs / 3600, secs % 3600 / 60);
+    if hours > 0 {
+        format!("{}h {:02}m", hours, minutes)
+    } else {
+        format!("{}m", minutes)
+    }
+}
+
+pub fn create_tray(app: &AppHandle) -> tauri::Result<()> {
+    let current = MenuItem::with_id(app, "current", "No active app", false, None::<&str>)?;
+    let today = MenuItem::with_id(app, "today", "Today: 0m", false, None::<&str>)?;
+    let pause = MenuItem::with_id(app, MENU_PAUSE, "Pause tracking", true, None::<&str>)?;
+    let snooze_30 = MenuItem::with_id(app, MENU_SNOOZE_30, "Pause for 30 minutes", true, None::<&str>)?;
+    let snooze_60 = MenuItem::with_id(app, MENU_SNOOZE_60, "Pause for 1 hour", true, None::<&str>)?;
+    let open = MenuItem::with_id(app, MENU_OPEN, "Open dashboard", true, None::<&str>)?;
+    let quit = MenuItem::with_id(app, MENU_QUIT, "Quit", true, None::<&str>)?;
+    let menu = Menu::with_items(app, &[
+        &current,
+        &today,
+        &PredefinedMenuItem::separator(app)?,
+        &pause,
+        &snooze_30,
+        &snooze_60,
+        &open,
+        &PredefinedMenuItem::separator(app)?,
+        &quit,
+    ])?;
+
+    let mut builder = TrayIconBuilder::with_id("main")
+        .tooltip("Time Whisper")
+        .menu(&menu)
+        .menu_on_left_click(false)
+        .on_menu_event(handle_menu_event)
+        .on_tray_icon_event(|tray, event| {
+            // 左键单击打开主窗口，右键显示菜单
+            if let TrayIconEvent::Click { button: MouseButton::Left, button_state: MouseButtonState::Up, .. } = event {
+                show_dashboard(tray.app_handle());
+            }
+        });
+    if let Some(icon) = app.default_window_icon() {
+        builder = builder.icon(icon.clone());
+    }
+    let icon = builder.build(app)?;
+
+    app.manage(Tray { icon, current, today, pause, tooltip: Mutex::new(String::new()) });
+    update_tray(app);
+    Ok(())
+}
+
+fn handle_menu_event(app: &AppHandle, event: MenuEvent) {
+    let snooze = |minutes| TrackingState::PausedUntil(Utc::now() + Duration::minutes(minutes));
+    let state = match event.id.as_ref() {
+        MENU_PAUSE => {
+            let paused = app.state::<AppState>().tracking_state.lock().unwrap().is_paused();
+            Some(if paused { TrackingState::Running } else { TrackingState::Paused })
+        }
+        MENU_SNOOZE_30 => Some(snooze(30)),
+        MENU_SNOOZE_60 => Some(snooze(60)),
+        _ => None,
+    };
+    if let Some(state) = state {
+        if let Err(e) = crate::apply_tracking_state(app, state) {
+            tracing::error!("Failed to change tracking state: {}", e);
+        }
+        return;
+    }
+
+    match event.id.as_ref() {
+        MENU_OPEN => show_dashboard(app),
+        // 退出时由 RunEvent::Exit 写完缓存的记录
+        MENU_QUIT => app.exit(0),
+        _ => {}
+    }
+}
+
+pub fn show_dashboard(app: &AppHandle) {
+    match app.get_webview_window("main") {
+        Some(window) => {
+            let _ = window.show();
+            let _ = window.unminimize();
+            let _ = window.set_focus();
+        }
+        None => tracing::warn!("Main window not found"),
+    }
+}
+
+// 根据实时统计刷新托盘提示和菜单
+pub fn update_tray(app: &AppHandle) {
+    let Some(tray) = app.try_state::<Tray>() else {
+        return;
+    };
+    let state = app.state::<AppState>();
+    let tracking_state = *state.tracking_state.lock().unwrap();
+    let summary = TraySummary::from_usage(&state.usage_data.lock().unwrap());
+
+    let current = match (&summary.current, tracking_state) {
+        (_, TrackingState::Paused) => "Tracking paused".to_string(),
+        (_, TrackingState::PausedUntil(until)) => {
+            format!("Paused until {}", until.with_timezone(&Local).format("%H:%M"))
+        }
+        (Some(name), TrackingState::Running) => format!("Current: {}", name),
+        (None, TrackingState::Running) => "No active app".to_string(),
+    };
+    let today = format!("Today: {}", format_duration(summary.today_total));
+    let tooltip = format!("Time Whisper\n{}\n{}", current, today);
+
+    let mut last = tray.tooltip.lock().unwrap();
+    if *last == tooltip {
+        return;
+    }
+    *last = tooltip.clone();
+    let _ = tray.icon.set_tooltip(Some(&tooltip));
+    let _ = tray.current.set_text(&current);
+    let _ = tray.today.set_text(&today);
+    let _ = tray.pause.set_text(if tracking_state.is_paused() { "Resume tracking" } else { "Pause tracking" });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usage(name: &str, total_time: u64, last_active: u64) -> (String, AppUsage) {
+        (name.to_string(), AppUsage { name: name.to_string(), total_time, last_active })
+    }
+
+    // 1. 当前应用是最近活跃的应用，今天的总时长是所有应用之和
+    #[test]
+    fn test_tray_summary() {
+        assert_eq!(TraySummary::from_usage(&HashMap::new()), TraySummary { current: None, today_total: 0 });
+
+        let data = HashMap::from([usage("code", 3000, 200), usage("firefox", 900, 260), usage("slack", 60, 0)]);
+        assert_eq!(
+            TraySummary::from_usage(&data),
+            TraySummary { current: Some("firefox".to_string()), today_total: 3960 },
+        );
+    }
+
+    // 2. 时长格式
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(59), "0m");
+        assert_eq!(format_duration(750), "12m");
+        assert_eq!(format_duration(3900), "1h 05m");
+    }
+}